@@ -0,0 +1,280 @@
+//! Macros for declaring grammars inline, in the same EBNF as grammar files.
+//!
+//! ```ignore
+//! let grammar = grammar_macro::grammar! {
+//!     start = circuit;
+//!     %token version newline id;
+//!     circuit -> version, newline, { "module", id, newline };
+//! };
+//! ```
+//!
+//! The input is written as a grammar file would be, except that comments are Rust comments,
+//! and `->` may be used in place of `=`.
+//! An optional leading `start = name;` names the start symbol, which otherwise is the first rule's.
+//! Imports are read relative to the crate's manifest directory.
+//!
+//! Mistakes in the grammar, including names which are used but never defined or declared, are compile errors.
+//! The expanded code refers to `::parsing`, so the calling crate must depend on it.
+
+use std::path::Path;
+
+use parsing::lr0::{Action, ParseTable};
+use proc_macro2::{Delimiter, Spacing, Span, TokenStream, TokenTree};
+use quote::{quote, quote_spanned};
+
+#[cfg(test)]
+mod test;
+
+/// Build a `parsing::Grammar` from EBNF, desugared as `metagrammar::Grammar::split` does.
+#[proc_macro]
+pub fn grammar(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    expand(input.into(), Output::Grammar).into()
+}
+
+/// Build the `parsing::lr0::ParseTable` for a grammar at compile time.
+/// The input is the same as for `grammar!`.
+#[proc_macro]
+pub fn parse_table(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    expand(input.into(), Output::Table).into()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Grammar,
+    Table,
+}
+
+fn expand(input: TokenStream, output: Output) -> TokenStream {
+    match build(input, output) {
+        Ok(tokens) => tokens,
+        Err(errors) => quote!({ #errors }),
+    }
+}
+
+fn build(input: TokenStream, output: Output) -> Result<TokenStream, TokenStream> {
+    let source = Source::new(input)?;
+
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let root = Path::new(&manifest_dir).join("grammar!");
+    let mut ebnf = metagrammar::load(&root, |path| {
+        if path == root {
+            Ok(source.text.clone())
+        } else {
+            std::fs::read_to_string(path)
+        }
+    })
+    .map_err(|e| source.error(&root, e))?;
+
+    let start = match &source.start {
+        Some((start, _span)) => start.clone(),
+        None => match ebnf.rules().first() {
+            Some(rule) => rule.lhs.clone(),
+            None => return Err(error(Span::call_site(), "the grammar has no rules")),
+        },
+    };
+    ebnf.split();
+    let grammar = ebnf.to_parsing_grammar(&start).map_err(|e| source.error(&root, e))?;
+
+    Ok(match output {
+        Output::Grammar => grammar_tokens(&grammar),
+        Output::Table => table_tokens(&ParseTable::new(grammar)),
+    })
+}
+
+fn error(span: Span, message: &str) -> TokenStream {
+    quote_spanned!(span=> ::core::compile_error!(#message);)
+}
+
+/// The macro's input, written back out as the text of a grammar file.
+struct Source {
+    /// The start symbol, if it was given, and where.
+    start: Option<(String, Span)>,
+    text: String,
+    /// Where each token starts in `text`, and its span in the macro's input.
+    spans: Vec<(usize, Span)>,
+    /// Whether the next token should follow the last without a space, as in `%token` or `ns.name`.
+    glue: bool,
+}
+
+impl Source {
+    fn new(input: TokenStream) -> Result<Source, TokenStream> {
+        let mut tokens: Vec<TokenTree> = input.into_iter().collect();
+        let mut start = None;
+        if let [TokenTree::Ident(keyword), TokenTree::Punct(eq), TokenTree::Ident(name), TokenTree::Punct(semi), ..] =
+            tokens.as_slice()
+            && keyword == "start"
+            && eq.as_char() == '='
+            && semi.as_char() == ';'
+        {
+            start = Some((name.to_string(), name.span()));
+            tokens.drain(..4);
+        }
+
+        let mut source = Source {
+            start,
+            text: String::new(),
+            spans: vec![],
+            glue: false,
+        };
+        source.push_all(tokens)?;
+        Ok(source)
+    }
+
+    fn push_all(&mut self, tokens: impl IntoIterator<Item = TokenTree>) -> Result<(), TokenStream> {
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            match token {
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::None => ("", ""),
+                    };
+                    self.push(open, group.span_open());
+                    self.push_all(group.stream())?;
+                    self.push(close, group.span_close());
+                }
+                TokenTree::Ident(ident) => self.push(&ident.to_string(), ident.span()),
+                TokenTree::Literal(literal) => {
+                    let text = literal.to_string();
+                    if !text.starts_with('"') || text.contains('\\') {
+                        return Err(error(literal.span(), "terminals must be plain string literals, eg \"module\""));
+                    }
+                    self.push(&text, literal.span());
+                }
+                TokenTree::Punct(punct) => {
+                    let arrow = punct.as_char() == '-'
+                        && punct.spacing() == Spacing::Joint
+                        && matches!(tokens.peek(), Some(TokenTree::Punct(next)) if next.as_char() == '>');
+                    match punct.as_char() {
+                        '-' if arrow => {
+                            tokens.next();
+                            self.push("=", punct.span());
+                        }
+                        '.' => {
+                            self.glue = true;
+                            self.push(".", punct.span());
+                            self.glue = true;
+                        }
+                        c => {
+                            self.push(&c.to_string(), punct.span());
+                            self.glue = matches!(c, '%' | '@') || punct.spacing() == Spacing::Joint;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, text: &str, span: Span) {
+        if text.is_empty() {
+            return;
+        }
+        if !self.glue && !self.text.is_empty() {
+            self.text.push(' ');
+        }
+        self.spans.push((self.text.len(), span));
+        self.text.push_str(text);
+        self.glue = false;
+    }
+
+    /// The span of the token at `offset` in `text`.
+    fn span_at(&self, offset: usize) -> Span {
+        match self.spans.iter().rev().find(|(start, _span)| *start <= offset) {
+            Some((_start, span)) => *span,
+            None => Span::call_site(),
+        }
+    }
+
+    /// The span of the first use of `name`.
+    fn span_of(&self, name: &str) -> Span {
+        let position = self.spans.iter().position(|(start, _span)| {
+            let rest = &self.text[*start..];
+            rest.starts_with(name) && !rest[name.len()..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+        });
+        position.map_or(Span::call_site(), |i| self.spans[i].1)
+    }
+
+    /// Turn an error from loading the grammar into compile errors, pointing at the input where possible.
+    fn error(&self, root: &Path, e: metagrammar::Error) -> TokenStream {
+        match e {
+            metagrammar::Error::Syntax(e) if Path::new(&e.file) == root => {
+                let mut message = e.message.clone();
+                if !e.expected.is_empty() {
+                    message.push_str(&format!(", expected one of: {}", e.expected.join(", ")));
+                }
+                if let Some(hint) = &e.hint {
+                    message.push_str(&format!("\nhint: {hint}"));
+                }
+                error(self.span_at(e.span.start), &message)
+            }
+            metagrammar::Error::Undefined(names) => names
+                .iter()
+                .map(|name| error(self.span_of(name), &format!("`{name}` is neither defined by a rule nor declared with %token")))
+                .collect(),
+            metagrammar::Error::Redefined(names) => names
+                .iter()
+                .map(|name| error(self.span_of(name), &format!("`{name}` is declared with %token but also defined by a rule")))
+                .collect(),
+            metagrammar::Error::UnknownStart(name) => {
+                let span = self.start.as_ref().map_or(Span::call_site(), |(_name, span)| *span);
+                error(span, &format!("the start symbol `{name}` is not defined by a rule"))
+            }
+            e => error(Span::call_site(), &e.to_string()),
+        }
+    }
+}
+
+/// Code which builds `grammar` with a `GrammarBuilder`.
+fn grammar_tokens(grammar: &parsing::Grammar) -> TokenStream {
+    let symbols: Vec<String> = grammar.symbols().iter().map(|symbol| symbol.as_str().to_string()).collect();
+    let rules = grammar.rules().into_iter().map(|rule| {
+        let lhs = rule.lhs().as_str().to_string();
+        let rhs: Vec<String> = rule.rhs().iter().map(|symbol| symbol.as_str().to_string()).collect();
+        quote!((#lhs, &[#(#rhs),*]))
+    });
+    quote! {{
+        let mut builder = ::parsing::Grammar::new();
+        for symbol in [#(#symbols),*] {
+            builder = builder.symbol(symbol);
+        }
+        let rules: &[(&str, &[&str])] = &[#(#rules),*];
+        for (lhs, rhs) in rules {
+            builder = builder.rule(lhs, rhs);
+        }
+        builder.build()
+    }}
+}
+
+/// Code which assembles `table` from its states and actions, without building it again.
+fn table_tokens(table: &ParseTable) -> TokenStream {
+    let grammar = grammar_tokens(table.grammar());
+    let states = table.states.iter().map(|items| {
+        let items = items.iter().map(|item| {
+            let (rule, pos) = (item.rule, item.pos);
+            quote!(::parsing::ItemData { rule: #rule, pos: #pos })
+        });
+        quote!(vec![#(#items),*])
+    });
+
+    let mut entries: Vec<_> = table.actions.iter().filter(|(_key, actions)| !actions.is_empty()).collect();
+    entries.sort_by_key(|(key, _actions)| **key);
+    let entries = entries.into_iter().map(|((state, symbol), actions)| {
+        let symbol = match symbol {
+            Some(symbol) => quote!(Some(#symbol)),
+            None => quote!(None),
+        };
+        let actions = actions.iter().map(|action| match action {
+            Action::Shift(state) => quote!(::parsing::lr0::Action::Shift(#state)),
+            Action::Reduce(rule) => quote!(::parsing::lr0::Action::Reduce(#rule)),
+            Action::Halt => quote!(::parsing::lr0::Action::Halt),
+        });
+        quote!(((#state, #symbol), vec![#(#actions),*]))
+    });
+
+    quote! {
+        ::parsing::lr0::ParseTable::from_parts(#grammar, vec![#(#states),*], [#(#entries),*])
+    }
+}
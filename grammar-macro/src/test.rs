@@ -0,0 +1,48 @@
+use super::*;
+
+/// The compile errors `input` expands to, or `None` if it expands without any.
+fn errors(input: TokenStream) -> Option<String> {
+    build(input, Output::Grammar).err().map(|errors| errors.to_string())
+}
+
+#[test]
+fn test_source_text() {
+    let source = Source::new(quote! {
+        start = b;
+        %token id;
+        @inline a -> ns.b, "x"?, sep(id, ",");
+        b |= @skip id;
+    })
+    .ok()
+    .unwrap();
+    assert_eq!(source.start.unwrap().0, "b");
+    assert_eq!(source.text, r#"%token id ; @inline a = ns.b , "x" ? , sep ( id , "," ) ; b |= @skip id ;"#);
+}
+
+#[test]
+fn test_expands() {
+    let tokens = build(quote! { %token id; list = "[", { id }, "]"; }, Output::Grammar).unwrap().to_string();
+    assert!(tokens.contains("builder . symbol (symbol)"));
+    assert!(tokens.contains(r#"("list__star1" , & ["list__star1" , "id"])"#));
+
+    let tokens = build(quote! { %token id; list = "[", { id }, "]"; }, Output::Table).unwrap().to_string();
+    assert!(tokens.contains("ParseTable :: from_parts"));
+    assert!(tokens.contains("Action :: Halt"));
+}
+
+#[test]
+fn test_errors() {
+    let message = errors(quote! { %token id; a = id, b; }).unwrap();
+    assert!(message.contains("`b` is neither defined by a rule nor declared with %token"), "{message}");
+
+    let message = errors(quote! { a = "x" b = "y"; }).unwrap();
+    assert!(message.contains("unexpected `b`"), "{message}");
+
+    let message = errors(quote! { start = c; a = "x"; }).unwrap();
+    assert!(message.contains("the start symbol `c` is not defined by a rule"), "{message}");
+
+    let message = errors(quote! { a = 'x'; }).unwrap();
+    assert!(message.contains("terminals must be plain string literals"), "{message}");
+
+    assert_eq!(errors(quote! { a = "x"; }), None);
+}
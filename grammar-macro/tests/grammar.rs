@@ -0,0 +1,49 @@
+use grammar_macro::{grammar, parse_table};
+use parsing::Token;
+use parsing::lr0::{Machine, ParseTable};
+
+/// Feed words to a machine for `table`, where each word is a quoted terminal, or else `id`.
+fn parse(table: &ParseTable, words: &str) -> String {
+    let grammar = table.grammar();
+    let mut machine = Machine::new(table);
+    for word in words.split_whitespace() {
+        let terminal = grammar.symbol(&format!("\"{word}\"")).or(grammar.symbol("id")).unwrap();
+        machine.feed(Token::new(terminal, word, 0..0)).unwrap();
+    }
+    machine.finish().unwrap();
+    format!("{:?}", machine.tree().unwrap())
+}
+
+#[test]
+fn test_grammar() {
+    let grammar = grammar! {
+        start = call;
+        %token id;
+        // Rust comments are allowed.
+        call -> id, "(", [ sep(call, ",") ], ")";
+    };
+    let mut expected = metagrammar::parse(r#"%token id ; call = id , "(" , [ sep(call, ",") ] , ")" ;"#).unwrap();
+    expected.split();
+    assert_eq!(format!("{grammar:?}"), format!("{:?}", expected.to_parsing_grammar("call").unwrap()));
+
+    let table = ParseTable::new(grammar);
+    assert_eq!(parse(&table, "f ( )"), "(START (call id \"(\" (call__opt1) \")\"))");
+}
+
+/// A table built at compile time is the same as one built at run time.
+#[test]
+fn test_parse_table() {
+    let table = parse_table! {
+        %token id;
+        call -> id, "(", [ sep(call, ",") ], ")";
+    };
+    let expected = ParseTable::new(grammar! {
+        %token id;
+        call -> id, "(", [ sep(call, ",") ], ")";
+    });
+    assert_eq!(table.grammar().fingerprint(), expected.grammar().fingerprint());
+    assert_eq!(table.states, expected.states);
+    assert_eq!(table.actions, expected.actions);
+
+    assert_eq!(parse(&table, "f ( g ( ) , h ( ) )"), parse(&expected, "f ( g ( ) , h ( ) )"));
+}
@@ -0,0 +1,38 @@
+//! Parse every statement of tests/statements.txt with the LR(0) machine, already lexed,
+//! so that only the machine is measured.
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use parsing::lr0::{Machine, ParseTable};
+
+fn parse_corpus(c: &mut Criterion) {
+    let root = concat!(env!("CARGO_MANIFEST_DIR"), "/..");
+    let mut grammar = metagrammar::parse_file(format!("{root}/GRAMMAR")).unwrap();
+    grammar.split();
+    let table = ParseTable::new(grammar.to_parsing_grammar(metagrammar::corpus::START).unwrap());
+
+    let corpus = std::fs::read_to_string(format!("{root}/tests/statements.txt")).unwrap();
+    let sources: Vec<String> = corpus.lines().filter(|line| !line.trim().is_empty()).map(|line| format!("{line}\n")).collect();
+    let statements: Vec<_> = sources
+        .iter()
+        .filter_map(|source| metagrammar::firrtl::tokenize(table.grammar(), source).ok())
+        .collect();
+
+    let mut group = c.benchmark_group("corpus");
+    group.throughput(Throughput::Elements(statements.len() as u64));
+    group.bench_function("lr0", |b| {
+        b.iter(|| {
+            let mut parsed = 0;
+            for tokens in &statements {
+                let mut machine = Machine::new(&table);
+                if machine.run(&mut tokens.iter().cloned()).is_ok() {
+                    parsed += 1;
+                }
+            }
+            parsed
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse_corpus);
+criterion_main!(benches);
@@ -1,3 +1,217 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Write;
+use std::path::Path;
+
+// The grammar-file parser, which needs nothing else from the crate, so `check` reads grammars as the crate does.
+#[allow(dead_code)]
+#[path = "src/diagnostic.rs"]
+mod diagnostic;
+#[allow(dead_code)]
+#[path = "src/syntax/parser.rs"]
+mod parser;
+
+const TOKENIZER: &str = "../tokenizer/src/lib.rs";
+const GRAMMAR: &str = "../GRAMMAR";
+
+/// Tokens which `FirrtlLexer` synthesizes from indentation rather than lexing.
+const LAYOUT_TERMINALS: &[&str] = &["newline", "indent", "dedent"];
+
 fn main() {
-    lalrpop::process_src().unwrap();
+    println!("cargo:rerun-if-changed={TOKENIZER}");
+    println!("cargo:rerun-if-changed=src/syntax/parser.rs");
+
+    #[cfg(feature = "lalrpop-oracle")]
+    {
+        println!("cargo:rerun-if-changed=src/metagrammar.lalrpop");
+        lalrpop::process_src().unwrap();
+    }
+
+    let source = std::fs::read_to_string(TOKENIZER).unwrap();
+    let variants = lex_variants(&source);
+
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    std::fs::write(Path::new(&out_dir).join("terminals.rs"), generate(&variants)).unwrap();
+
+    if Path::new(GRAMMAR).exists() {
+        check(&variants);
+    }
+}
+
+/// A variant of `tokenizer::LexToken`.
+struct Variant {
+    name: String,
+    has_payload: bool,
+    /// The grammar terminal it lexes as, or `None` if logos skips it.
+    terminal: Option<String>,
+}
+
+/// Find every `LexToken` variant and the terminal it corresponds to.
+///
+/// A `#[token("lit")]` variant is the quoted terminal `"lit"`.
+/// A `#[regex(..)]` variant is the named terminal spelled as the variant in snake case (eg, `Id` is `id`).
+/// Anything else on a variant is a panic, since it would otherwise be left out of the table.
+fn lex_variants(source: &str) -> Vec<Variant> {
+    let file = syn::parse_file(source).unwrap_or_else(|e| panic!("Could not parse {TOKENIZER}: {e}"));
+    let lex_token = file
+        .items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Enum(item) if item.ident == "LexToken" => Some(item),
+            _ => None,
+        })
+        .expect("Could not find LexToken in the tokenizer");
+
+    let mut variants = vec![];
+    for variant in &lex_token.variants {
+        let name = variant.ident.to_string();
+        let has_payload = match &variant.fields {
+            syn::Fields::Unit => false,
+            syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => true,
+            _ => panic!("LexToken::{name} must have no fields or one unnamed field"),
+        };
+
+        let mut patterns = vec![];
+        for attr in &variant.attrs {
+            let path = attr.path();
+            if path.is_ident("doc") {
+                continue;
+            }
+            if !path.is_ident("token") && !path.is_ident("regex") {
+                panic!("LexToken::{name} has an unrecognised attribute {}", quote_path(path));
+            }
+            let args = attr
+                .parse_args_with(syn::punctuated::Punctuated::<syn::Expr, syn::Token![,]>::parse_terminated)
+                .unwrap_or_else(|e| panic!("Could not parse an attribute of LexToken::{name}: {e}"));
+            let literal = match args.first() {
+                Some(syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(literal), .. })) => literal.value(),
+                _ => panic!("An attribute of LexToken::{name} does not start with a string literal"),
+            };
+            let skipped = args.iter().skip(1).any(|arg| matches!(arg, syn::Expr::Path(arg) if quote_path(&arg.path) == "logos::skip"));
+            patterns.push((path.is_ident("token").then_some(literal), skipped));
+        }
+
+        let terminal = match patterns.as_slice() {
+            [(_, true)] => None,
+            [(Some(literal), false)] => Some(format!("\"{literal}\"")),
+            [(None, false)] => Some(snake_case(&name)),
+            [] => panic!("LexToken::{name} has no #[token] or #[regex] attribute"),
+            _ => panic!("LexToken::{name} has several #[token] or #[regex] attributes, so its terminal is ambiguous"),
+        };
+        variants.push(Variant { name, has_payload, terminal });
+    }
+    variants
+}
+
+/// A path as written, eg `logos::skip`.
+fn quote_path(path: &syn::Path) -> String {
+    let segments: Vec<String> = path.segments.iter().map(|segment| segment.ident.to_string()).collect();
+    segments.join("::")
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+fn generate(variants: &[Variant]) -> String {
+    let mut out = String::new();
+    writeln!(out, "// Generated by build.rs from the attributes on `tokenizer::LexToken`.").unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "/// Each `LexToken` variant which can reach the parser and the terminal it lexes as.").unwrap();
+    writeln!(out, "pub const LEX_TERMINALS: &[(&str, &str)] = &[").unwrap();
+    for variant in variants {
+        if let Some(terminal) = &variant.terminal {
+            writeln!(out, "    ({:?}, {:?}),", variant.name, terminal).unwrap();
+        }
+    }
+    writeln!(out, "];").unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "/// The terminals `FirrtlLexer` synthesizes from newlines and indentation.").unwrap();
+    writeln!(out, "pub const LAYOUT_TERMINALS: &[&str] = &{LAYOUT_TERMINALS:?};").unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "fn lex_terminal_name(token: tokenizer::LexToken) -> &'static str {{").unwrap();
+    writeln!(out, "    match token {{").unwrap();
+    for variant in variants {
+        let pattern = if variant.has_payload {
+            format!("tokenizer::LexToken::{}(_)", variant.name)
+        } else {
+            format!("tokenizer::LexToken::{}", variant.name)
+        };
+        match &variant.terminal {
+            Some(terminal) => writeln!(out, "        {pattern} => {terminal:?},").unwrap(),
+            None => writeln!(out, "        {pattern} => unreachable!(),").unwrap(),
+        }
+    }
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// Warn about terminals in `GRAMMAR` and the files it imports which the tokenizer never produces, and vice versa.
+fn check(variants: &[Variant]) {
+    let terminals = match grammar_terminals(Path::new(GRAMMAR)) {
+        Ok(terminals) => terminals,
+        Err(message) => {
+            println!("cargo:warning={message}");
+            return;
+        }
+    };
+
+    let mut tokens: BTreeSet<String> = variants.iter().filter_map(|variant| variant.terminal.clone()).collect();
+    tokens.extend(LAYOUT_TERMINALS.iter().map(|terminal| terminal.to_string()));
+
+    for terminal in terminals.difference(&tokens) {
+        println!("cargo:warning=GRAMMAR: terminal {terminal} has no token in the tokenizer");
+    }
+    for token in tokens.difference(&terminals) {
+        println!("cargo:warning=GRAMMAR: token {token} has no terminal in the grammar");
+    }
+}
+
+/// The quoted terminals and `%token` names of the grammar file at `path`, and of every file it imports.
+fn grammar_terminals(path: &Path) -> Result<BTreeSet<String>, String> {
+    let mut terminals = BTreeSet::new();
+    let mut files = vec![path.to_path_buf()];
+    let mut seen = HashSet::new();
+    while let Some(path) = files.pop() {
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+        println!("cargo:rerun-if-changed={}", path.display());
+        let file = path.display().to_string();
+        let source = std::fs::read_to_string(&path).map_err(|e| format!("Could not read {file}: {e}"))?;
+        let (items, _comments) = parser::parse_tree(&file, &source)
+            .map_err(|e| format!("{}:{}:{}: {}", e.file, e.line, e.column, e.message))?;
+
+        // Each node, and whether it is in a `%token` declaration.
+        let mut nodes = vec![(&items, false)];
+        while let Some((node, declared)) = nodes.pop() {
+            let text = || node.token.as_ref().unwrap().text;
+            match node.symbol.as_str() {
+                "term" => {
+                    terminals.insert(text().to_string());
+                }
+                "name" if declared => {
+                    terminals.insert(text().to_string());
+                }
+                "Item" if node.children[0].symbol.as_str() == "import" => {
+                    let import = node.children[1].token.as_ref().unwrap().text;
+                    let dir = path.parent().unwrap_or(Path::new(""));
+                    files.push(dir.join(&import[1..import.len() - 1]));
+                }
+                "Item" if node.children[0].symbol.as_str() == "%token" => nodes.push((&node.children[1], true)),
+                _ => nodes.extend(node.children.iter().map(|child| (child, declared))),
+            }
+        }
+    }
+    Ok(terminals)
 }
@@ -0,0 +1,57 @@
+//! Step through the parse of a FIRRTL file with the LR machine. See `metagrammar::debugger`.
+
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use clap::Parser;
+use metagrammar::debugger::Debugger;
+use parsing::lr0;
+
+#[derive(Parser)]
+#[command(about = "Step through the parse of a FIRRTL file with the LR machine")]
+struct Cli {
+    /// The grammar file to load.
+    #[arg(short, long, default_value = "GRAMMAR")]
+    grammar: PathBuf,
+
+    /// The nonterminal to start parsing from.
+    #[arg(short, long, default_value = "circuit")]
+    start: String,
+
+    file: PathBuf,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(&cli) {
+        eprintln!("ERROR: {e}");
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let mut grammar = metagrammar::parse_file(&cli.grammar)?;
+    grammar.split();
+    let table = lr0::ParseTable::new(grammar.to_parsing_grammar(&cli.start)?);
+
+    let source = std::fs::read_to_string(&cli.file)?;
+    let tokens = metagrammar::firrtl::tokenize(table.grammar(), &source)?;
+    println!("{} tokens, {} states; type `help` for the commands", tokens.len(), table.states.len());
+    let mut debugger = Debugger::new(&table, tokens);
+
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        print!("(lrdb) ");
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next().transpose()? else { break };
+        match line.trim() {
+            "" => continue,
+            "q" | "quit" => break,
+            command => match debugger.execute(command) {
+                Ok(output) => println!("{output}"),
+                Err(message) => println!("ERROR: {message}"),
+            },
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+//! Conformance of the grammar against a corpus of FIRRTL statements, one per line, like `tests/statements.txt`.
+//!
+//! Each line is parsed on its own as a `statement`, and the results are tallied by the statement's keyword.
+//! The report is kept as a snapshot, so that a change to the grammar which breaks statements it used to parse is noticed.
+
+use std::collections::HashMap;
+
+use parsing::lr0::{Machine, ParseTable};
+
+use crate::firrtl;
+
+/// The nonterminal each line of the corpus is parsed as.
+pub const START: &str = "statement";
+
+/// How many statements of one kind passed, and the first which failed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Tally {
+    pub passed: usize,
+    pub failed: usize,
+    pub first_failure: Option<Failure>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    /// The line of the corpus, counting from 1.
+    pub line: usize,
+    pub statement: String,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// By keyword, most common first.
+    pub kinds: Vec<(String, Tally)>,
+}
+
+impl Report {
+    pub fn passed(&self) -> usize {
+        self.kinds.iter().map(|(_kind, tally)| tally.passed).sum()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.kinds.iter().map(|(_kind, tally)| tally.failed).sum()
+    }
+}
+
+/// Parse every non-blank line of `corpus` with `table`, which must start from `START`.
+pub fn run(table: &ParseTable, corpus: &str) -> Report {
+    let mut tallies: HashMap<String, Tally> = HashMap::new();
+    for (index, line) in corpus.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (kind, result) = parse_statement(table, line);
+        let tally = tallies.entry(kind).or_default();
+        match result {
+            Ok(()) => tally.passed += 1,
+            Err(message) => {
+                tally.failed += 1;
+                tally.first_failure.get_or_insert(Failure {
+                    line: index + 1,
+                    statement: line.to_string(),
+                    message,
+                });
+            }
+        }
+    }
+
+    let mut kinds: Vec<(String, Tally)> = tallies.into_iter().collect();
+    kinds.sort_by(|(a, a_tally), (b, b_tally)| {
+        let total = |tally: &Tally| tally.passed + tally.failed;
+        total(b_tally).cmp(&total(a_tally)).then(a.cmp(b))
+    });
+    Report { kinds }
+}
+
+/// Parse one statement, returning its kind and why it failed, if it did.
+/// The kind is the first token's terminal, so `node x = y` is a `node`, and `x <= y` is an `id`.
+fn parse_statement(table: &ParseTable, line: &str) -> (String, Result<(), String>) {
+    let source = format!("{line}\n");
+    let first_word = line.split_whitespace().next().unwrap_or_default().to_string();
+    let tokens = match firrtl::tokenize(table.grammar(), &source) {
+        Ok(tokens) => tokens,
+        Err(e) => return (first_word, Err(format!("column {}: {}", e.span.start + 1, e.message))),
+    };
+    let kind = match tokens.first() {
+        Some(token) => token.terminal.as_str().trim_matches('"').to_string(),
+        None => first_word,
+    };
+
+    let mut machine = Machine::new(table);
+    for token in &tokens {
+        let (column, text) = (token.span.start + 1, token.text);
+        if machine.feed(token.clone()).is_err() {
+            return (kind, Err(format!("column {column}: unexpected {} `{}`", token.terminal, text.escape_debug())));
+        }
+    }
+    match machine.finish() {
+        Ok(()) => (kind, Ok(())),
+        Err(_) => (kind, Err("unexpected end of statement".to_string())),
+    }
+}
+
+/// The report, as kept in the snapshot: a table of counts, then the first failure of each kind.
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} statements: {} passed, {} failed", self.passed() + self.failed(), self.passed(), self.failed())?;
+        writeln!(f)?;
+        writeln!(f, "{:<16} {:>8} {:>8} {:>8}", "kind", "total", "passed", "failed")?;
+        for (kind, tally) in &self.kinds {
+            writeln!(f, "{kind:<16} {:>8} {:>8} {:>8}", tally.passed + tally.failed, tally.passed, tally.failed)?;
+        }
+
+        for (kind, tally) in &self.kinds {
+            if let Some(failure) = &tally.first_failure {
+                writeln!(f)?;
+                writeln!(f, "first {kind} failure, line {}: {}", failure.line, failure.message)?;
+                writeln!(f, "    {}", failure.statement)?;
+            }
+        }
+        Ok(())
+    }
+}
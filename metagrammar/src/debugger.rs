@@ -0,0 +1,225 @@
+//! An interactive debugger for `lr0::Machine`, which steps through the parse of a list of tokens.
+//! Its commands are listed in `HELP`.
+
+use parsing::Token;
+use parsing::lr0::{Action, Machine, ParseError, ParseTable, StateIndex};
+
+pub const HELP: &str = "\
+step [N]             take one step, or N
+continue [N]         run until a breakpoint, the end of the parse, or until token N is next
+break state N        stop after entering state N
+break rule N         stop after reducing by rule N
+break                list the breakpoints
+delete               remove every breakpoint
+stack                show the stack, with the items of each state
+input [N]            show the lookahead and the next N tokens (default 10)
+rules                list the rules, by number
+quit                 leave the debugger";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    State(StateIndex),
+    Reduce(parsing::RuleIndex),
+}
+
+pub struct Debugger<'t, 's> {
+    table: &'t ParseTable,
+    machine: Machine<'t, 's>,
+    tokens: Vec<Token<'t, 's>>,
+    /// The index of the next token to feed to the machine.
+    next: usize,
+    breakpoints: Vec<Breakpoint>,
+    /// Why the parse stopped, once the machine can take no more steps.
+    error: Option<ParseError<'t>>,
+}
+
+impl<'t, 's> Debugger<'t, 's> {
+    pub fn new(table: &'t ParseTable, tokens: Vec<Token<'t, 's>>) -> Debugger<'t, 's> {
+        Debugger {
+            table,
+            machine: Machine::new(table),
+            tokens,
+            next: 0,
+            breakpoints: vec![],
+            error: None,
+        }
+    }
+
+    pub fn machine(&self) -> &Machine<'t, 's> {
+        &self.machine
+    }
+
+    /// Run one command, returning what to print, or an error for a command which isn't understood.
+    pub fn execute(&mut self, command: &str) -> Result<String, String> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let number = |word: Option<&&str>| match word {
+            None => Ok(None),
+            Some(word) => word.parse::<usize>().map(Some).map_err(|_| format!("expected a number, found `{word}`")),
+        };
+
+        match words.as_slice() {
+            ["s" | "step", rest @ ..] if rest.len() <= 1 => {
+                let mut lines = vec![];
+                for _ in 0..number(rest.first())?.unwrap_or(1) {
+                    match self.step() {
+                        Ok((_action, line)) => lines.push(line),
+                        Err(line) => {
+                            lines.push(line);
+                            break;
+                        }
+                    }
+                }
+                Ok(lines.join("\n"))
+            }
+            ["c" | "continue", rest @ ..] if rest.len() <= 1 => Ok(self.resume(number(rest.first())?)),
+            ["b" | "break"] => {
+                let lines: Vec<String> = self.breakpoints.iter().map(|breakpoint| self.describe(*breakpoint)).collect();
+                Ok(if lines.is_empty() { "no breakpoints".to_string() } else { lines.join("\n") })
+            }
+            ["b" | "break", kind, index] => {
+                let index = number(Some(index))?.unwrap();
+                let breakpoint = match *kind {
+                    "state" if index < self.table.states.len() => Breakpoint::State(index),
+                    "rule" if index < self.table.grammar().rules().len() => Breakpoint::Reduce(index),
+                    "state" | "rule" => return Err(format!("there is no {kind} {index}")),
+                    _ => return Err(format!("expected `state` or `rule`, found `{kind}`")),
+                };
+                self.breakpoints.push(breakpoint);
+                Ok(format!("breakpoint {}: {}", self.breakpoints.len(), self.describe(breakpoint)))
+            }
+            ["d" | "delete"] => {
+                self.breakpoints.clear();
+                Ok("deleted every breakpoint".to_string())
+            }
+            ["stack"] => Ok(self.show_stack()),
+            ["i" | "input", rest @ ..] if rest.len() <= 1 => Ok(self.show_input(number(rest.first())?.unwrap_or(10))),
+            ["rules"] => {
+                let rules = self.table.grammar().rules();
+                Ok(rules.iter().enumerate().map(|(index, rule)| format!("{index}: {rule:?}")).collect::<Vec<_>>().join("\n"))
+            }
+            ["h" | "help"] => Ok(HELP.to_string()),
+            _ => Err(format!("unknown command `{}`; try `help`", command.trim())),
+        }
+    }
+
+    /// Take one step, returning its action and a description of it.
+    /// `Err` describes why no further step can be taken.
+    fn step(&mut self) -> Result<(Action, String), String> {
+        if let Some(error) = &self.error {
+            return Err(format!("the parse failed: {error}"));
+        }
+        if self.machine.is_halted() {
+            return Err("the parse has halted".to_string());
+        }
+
+        let (step, state) = (self.machine.steps(), self.machine.state());
+        let symbol = match self.machine.lookahead() {
+            Some(token) => token.terminal.to_string(),
+            None => self.tokens.get(self.next).map_or("$".to_string(), |token| token.terminal.to_string()),
+        };
+        let mut input = self.tokens[self.next..].iter().cloned();
+        let result = self.machine.step_once(&mut input);
+        self.next = self.tokens.len() - input.len();
+
+        match result {
+            Ok(action) => {
+                let mut line = format!("step {step}: state {state}, on {symbol} => {}", self.format_action(action));
+                if self.machine.is_halted() && self.next < self.tokens.len() {
+                    let error = ParseError::TrailingInput(self.tokens[self.next].terminal);
+                    line.push_str(&format!("\nthe parse failed: {error}"));
+                    self.error = Some(error);
+                } else if self.machine.is_halted() {
+                    line.push_str("\nthe parse halted");
+                }
+                Ok((action, line))
+            }
+            Err(error) => {
+                let expected: Vec<String> = self.machine.expected_terminals().iter().map(|symbol| symbol.to_string()).collect();
+                let line = format!("step {step}: the parse failed: {error}\nexpected one of: {}", expected.join(" "));
+                self.error = Some(error);
+                Err(line)
+            }
+        }
+    }
+
+    /// Step until a breakpoint, until the parse ends, or until token `until` is the next to be taken.
+    fn resume(&mut self, until: Option<usize>) -> String {
+        let mut last = None;
+        loop {
+            if until.is_some_and(|until| self.next >= until && self.machine.lookahead().is_none()) {
+                let line = format!("stopped before token {}", self.next);
+                return match last {
+                    Some(last) => format!("{last}\n{line}"),
+                    None => line,
+                };
+            }
+            let (action, line) = match self.step() {
+                Ok(step) => step,
+                Err(line) => return line,
+            };
+            if self.machine.is_halted() || self.error.is_some() {
+                return line;
+            }
+
+            let hit = self.breakpoints.iter().position(|breakpoint| match breakpoint {
+                Breakpoint::State(state) => self.machine.state() == *state,
+                Breakpoint::Reduce(rule) => action == Action::Reduce(*rule),
+            });
+            if let Some(index) = hit {
+                return format!("{line}\nbreakpoint {}: {}", index + 1, self.describe(self.breakpoints[index]));
+            }
+            last = Some(line);
+        }
+    }
+
+    fn show_stack(&self) -> String {
+        let mut lines = vec![];
+        let show_state = |lines: &mut Vec<String>, state: StateIndex| {
+            for item in format!("{:?}", self.table.state(state)).lines() {
+                lines.push(format!("    {item}"));
+            }
+        };
+        lines.push("state 0".to_string());
+        show_state(&mut lines, 0);
+        for (state, node) in self.machine.stack() {
+            match &node.token {
+                Some(token) => lines.push(format!("{} {:?} => state {state}", node.symbol, token.text)),
+                None => lines.push(format!("{} => state {state}", node.symbol)),
+            }
+            show_state(&mut lines, *state);
+        }
+        lines.join("\n")
+    }
+
+    fn show_input(&self, count: usize) -> String {
+        let mut lines = vec![];
+        if let Some(token) = self.machine.lookahead() {
+            lines.push(format!("lookahead: {}", token.terminal));
+        }
+        for (index, token) in self.tokens.iter().enumerate().skip(self.next).take(count) {
+            lines.push(format!("{index}: {} {:?} at byte {}", token.terminal, token.text, token.span.start));
+        }
+        let rest = self.tokens.len().saturating_sub(self.next + count);
+        if rest > 0 {
+            lines.push(format!("... and {rest} more"));
+        }
+        if lines.is_empty() {
+            lines.push("the input is empty".to_string());
+        }
+        lines.join("\n")
+    }
+
+    fn describe(&self, breakpoint: Breakpoint) -> String {
+        match breakpoint {
+            Breakpoint::State(state) => format!("state {state}"),
+            Breakpoint::Reduce(rule) => format!("rule {rule}: {:?}", self.table.grammar().rule_at(rule)),
+        }
+    }
+
+    fn format_action(&self, action: Action) -> String {
+        match action {
+            Action::Reduce(rule) => format!("Reduce({:?})", self.table.grammar().rule_at(rule)),
+            action => format!("{action:?}"),
+        }
+    }
+}
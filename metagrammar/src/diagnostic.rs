@@ -0,0 +1,138 @@
+use std::ops::Range;
+
+#[cfg(all(test, feature = "lalrpop-oracle"))]
+use lalrpop_util::ParseError;
+#[cfg(all(test, feature = "lalrpop-oracle"))]
+use lalrpop_util::lexer::Token;
+
+/// A syntax error in a grammar file, with enough context to point at the problem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub file: String,
+    /// 1-based.
+    pub line: usize,
+    /// 1-based, counted in characters.
+    pub column: usize,
+    /// The byte range of the offending text.
+    pub span: Range<usize>,
+    /// The whole line the error starts on.
+    pub source_line: String,
+    pub message: String,
+    /// The tokens which would have been accepted instead.
+    pub expected: Vec<String>,
+    pub hint: Option<String>,
+}
+
+/// An error raised by a semantic action: the span it applies to and a message.
+pub type UserError = (usize, usize, &'static str);
+
+impl SyntaxError {
+    /// `expected` should already be described, eg "`;`" or "a name".
+    pub(crate) fn new(file: &str, source: &str, span: Range<usize>, message: String, expected: Vec<String>) -> SyntaxError {
+        let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[span.start..].find('\n').map_or(source.len(), |i| span.start + i);
+
+        SyntaxError {
+            file: file.to_string(),
+            line: source[..span.start].matches('\n').count() + 1,
+            column: source[line_start..span.start].chars().count() + 1,
+            hint: hint(source, &span, &expected),
+            source_line: source[line_start..line_end].to_string(),
+            span,
+            message,
+            expected,
+        }
+    }
+
+    #[cfg(all(test, feature = "lalrpop-oracle"))]
+    pub(crate) fn from_lalrpop(file: &str, source: &str, error: ParseError<usize, Token<'_>, UserError>) -> SyntaxError {
+        let (span, message, expected) = match error {
+            ParseError::InvalidToken { location } => {
+                let len = source[location..].chars().next().map_or(0, char::len_utf8);
+                (location..location + len, "invalid token".to_string(), vec![])
+            }
+            ParseError::UnrecognizedEof { location, expected } => {
+                (location..location, "unexpected end of file".to_string(), expected)
+            }
+            ParseError::UnrecognizedToken { token: (start, token, end), expected } => {
+                (start..end, format!("unexpected `{}`", token.1), expected)
+            }
+            ParseError::ExtraToken { token: (start, token, end) } => {
+                (start..end, format!("extra token `{}`", token.1), vec![])
+            }
+            ParseError::User { error: (start, end, message) } => (start..end, message.to_string(), vec![]),
+        };
+        let expected: Vec<String> = expected.iter().map(|token| describe_token(token)).collect();
+        SyntaxError::new(file, source, span, message, expected)
+    }
+}
+
+/// Turn a token as lalrpop names it into something readable.
+#[cfg(all(test, feature = "lalrpop-oracle"))]
+fn describe_token(token: &str) -> String {
+    if token.starts_with("r#") {
+        if token.contains("a-zA-Z") {
+            "a name".to_string()
+        } else {
+            "a quoted terminal".to_string()
+        }
+    } else {
+        format!("`{}`", token.trim_matches('"'))
+    }
+}
+
+fn hint(source: &str, span: &Range<usize>, expected: &[String]) -> Option<String> {
+    let expects = |token: &str| expected.iter().any(|e| e == token);
+    let found = &source[span.clone()];
+
+    if found.starts_with('"') || (found.is_empty() && source[span.start..].starts_with('"')) {
+        return Some("quoted terminals must be closed on the same line, and cannot be empty".to_string());
+    }
+    if found.starts_with('\'') {
+        return Some("terminals are quoted with double quotes, eg `\"module\"`".to_string());
+    }
+    if expects("`;`") {
+        if span.start == source.len() {
+            return Some("did you forget `;` at the end of the last rule?".to_string());
+        }
+        let before = &source[..span.start];
+        let gap = &before[before.trim_end().len()..];
+        if gap.contains('\n') {
+            return Some("did you forget `;` after the previous rule?".to_string());
+        }
+    }
+    if found == "|" && (expects("`}`") || expects("`]`")) {
+        return Some("alternatives inside `{ }` and `[ ]` must be grouped, eg `{ ( a | b ) }`".to_string());
+    }
+    None
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let number = self.line.to_string();
+        let margin = " ".repeat(number.len());
+
+        let rest: String = self.source_line.chars().skip(self.column - 1).collect();
+        let width = rest.char_indices().take_while(|(i, _)| *i < self.span.len()).count();
+        let underline = "^".repeat(width.max(1));
+        let indent: String = self
+            .source_line
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        writeln!(f, "{}", self.message)?;
+        writeln!(f, "{margin}--> {}:{}:{}", self.file, self.line, self.column)?;
+        writeln!(f, "{margin} |")?;
+        writeln!(f, "{number} | {}", self.source_line)?;
+        write!(f, "{margin} | {indent}{underline}")?;
+        if !self.expected.is_empty() {
+            write!(f, "\n{margin} = expected one of: {}", self.expected.join(", "))?;
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, "\n{margin} = hint: {hint}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::{HashMap, HashSet};
+
+use parsing::lr0::{Machine, ParseTable};
+use tokenizer::{FirrtlLexer, LineStart};
+
+/// Feeds the tokens of a FIRRTL file to the parser, keeping their text and span.
+pub struct FirrtlTokens<'a, 's> {
+    lex: FirrtlLexer<'s>,
+    source: &'s str,
+    terminals: HashMap<String, parsing::Symbol<'a>>,
+}
+
+impl<'a, 's> FirrtlTokens<'a, 's> {
+    pub fn new(grammar: &'a parsing::Grammar, source: &'s str) -> FirrtlTokens<'a, 's> {
+        FirrtlTokens::with_lexer(grammar, source, FirrtlLexer::new(source))
+    }
+
+    /// Tokens of `source` from `line` on, the same as those `new` would produce from there.
+    pub fn resume(grammar: &'a parsing::Grammar, source: &'s str, line: &LineStart) -> FirrtlTokens<'a, 's> {
+        FirrtlTokens::with_lexer(grammar, source, FirrtlLexer::resume(source, line))
+    }
+
+    fn with_lexer(grammar: &'a parsing::Grammar, source: &'s str, lex: FirrtlLexer<'s>) -> FirrtlTokens<'a, 's> {
+        // The same as `grammar.terminals()`, without looking through the rules for each symbol.
+        let nonterminals: HashSet<_> = grammar.rules().iter().map(|rule| rule.lhs()).collect();
+        let terminals = grammar
+            .symbols()
+            .into_iter()
+            .filter(|symbol| !nonterminals.contains(symbol))
+            .map(|symbol| (symbol.as_str().to_string(), symbol))
+            .collect();
+        FirrtlTokens {
+            lex,
+            source,
+            terminals,
+        }
+    }
+
+    /// Where the next token starts, if it starts a line.
+    pub fn line_start(&self) -> Option<LineStart> {
+        self.lex.line_start()
+    }
+}
+
+impl<'a, 's> FirrtlTokens<'a, 's> {
+    /// The next token, or an error for text which is not a token of the grammar.
+    pub fn try_next(&mut self) -> Option<Result<parsing::Token<'a, 's>, LexError>> {
+        let token = self.lex.next()?;
+        let span = match token {
+            // Indents and dedents are synthesized after the newline which precedes them.
+            Ok(tokenizer::Token::Indent | tokenizer::Token::Dedent) => {
+                let end = self.lex.span().end;
+                end..end
+            }
+            _ => self.lex.span(),
+        };
+        let text = &self.source[span.clone()];
+        let error = |message: String| {
+            Some(Err(LexError {
+                span: span.clone(),
+                message,
+            }))
+        };
+        let Ok(token) = token else {
+            return error(format!("`{text}` is not a token"));
+        };
+        let name = terminal_name(token);
+        match self.terminals.get(name) {
+            Some(terminal) => Some(Ok(parsing::Token::new(*terminal, text, span))),
+            None => error(format!("the grammar has no terminal {name} for `{text}`")),
+        }
+    }
+}
+
+impl<'a, 's> parsing::TokenSource<'a, 's> for FirrtlTokens<'a, 's> {
+    type Error = LexError;
+
+    fn next_token(&mut self) -> Option<Result<parsing::Token<'a, 's>, LexError>> {
+        self.try_next()
+    }
+}
+
+/// Text in a FIRRTL file which could not be turned into a token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexError {
+    pub span: parsing::Span,
+    pub message: String,
+}
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}..{}", self.message, self.span.start, self.span.end)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// Every token of `source`, or the first error.
+pub fn tokenize<'a, 's>(grammar: &'a parsing::Grammar, source: &'s str) -> Result<Vec<parsing::Token<'a, 's>>, LexError> {
+    let mut tokens = FirrtlTokens::new(grammar, source);
+    std::iter::from_fn(|| tokens.try_next()).collect()
+}
+
+/// The keywords which can be typed at byte `offset` of `source`, completing the word the cursor is in, if any.
+/// `table` must parse whole files. There are no completions if the text before the word does not lex or parse,
+/// or if `offset` is past the end of `source` or inside a character.
+pub fn completions(table: &ParseTable, source: &str, offset: usize) -> Vec<String> {
+    let Some(before) = source.get(..offset) else {
+        return vec![];
+    };
+    let word_start = before.trim_end_matches(|c: char| c.is_ascii_alphanumeric() || c == '_').len();
+    let prefix = &before[word_start..];
+
+    // The lexer decides the indentation of a line when it lexes the newline before it,
+    // but only emits its dedents at the end of the file once the input runs out.
+    // So a word is put in the cursor's place, and the tokens before it are the ones the machine sees.
+    let probe = format!("{}x", &source[..word_start]);
+    let mut tokens = FirrtlTokens::new(table.grammar(), &probe);
+    let mut machine = Machine::new(table);
+    while let Some(token) = tokens.try_next() {
+        let Ok(token) = token else { return vec![] };
+        if token.span.start >= word_start && !token.span.is_empty() {
+            break;
+        }
+        if machine.feed(token).is_err() {
+            return vec![];
+        }
+    }
+
+    let mut keywords: Vec<String> = machine
+        .expected_terminals()
+        .iter()
+        .filter_map(|terminal| terminal.as_str().strip_prefix('"')?.strip_suffix('"').map(str::to_string))
+        .filter(|keyword| keyword.starts_with(|c: char| c.is_ascii_alphabetic()) && keyword.starts_with(prefix))
+        .collect();
+    keywords.sort();
+    keywords
+}
+
+include!(concat!(env!("OUT_DIR"), "/terminals.rs"));
+
+/// The grammar terminal a token from the tokenizer corresponds to.
+pub fn terminal_name(token: tokenizer::Token) -> &'static str {
+    match token {
+        tokenizer::Token::Lex(lex_token) => lex_terminal_name(lex_token),
+        tokenizer::Token::Newline => "newline",
+        tokenizer::Token::Indent => "indent",
+        tokenizer::Token::Dedent => "dedent",
+    }
+}
+
+/// Terminals of `grammar` which the tokenizer can never produce,
+/// and terminals the tokenizer produces which `grammar` never uses.
+pub fn unmatched_terminals(grammar: &parsing::Grammar) -> (Vec<String>, Vec<&'static str>) {
+    let tokens: Vec<&'static str> = LEX_TERMINALS
+        .iter()
+        .map(|(_variant, terminal)| *terminal)
+        .chain(LAYOUT_TERMINALS.iter().copied())
+        .collect();
+    let terminals: Vec<String> = grammar.terminals().iter().map(|symbol| symbol.as_str().to_string()).collect();
+
+    let known: HashSet<&str> = tokens.iter().copied().collect();
+    let used: HashSet<&str> = terminals.iter().map(String::as_str).collect();
+    let unused = tokens.iter().copied().filter(|token| !used.contains(token)).collect();
+    let missing = terminals.into_iter().filter(|terminal| !known.contains(terminal.as_str())).collect();
+    (missing, unused)
+}
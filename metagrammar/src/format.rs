@@ -0,0 +1,216 @@
+use std::collections::HashSet;
+
+use parsing::Span;
+use parsing::lr0::Node;
+
+use crate::syntax::{build_alt, build_item, list};
+use crate::*;
+
+/// Lines are wrapped to fit in this many columns, where they can be.
+const WIDTH: usize = 100;
+/// Where alternatives, and the lines sequences are wrapped onto, start.
+const INDENT: &str = "    ";
+
+/// Format the text of a grammar file in the canonical style, keeping its comments.
+///
+/// A rule goes on one line if it fits, and has one alternative or only single-symbol alternatives.
+/// Otherwise each alternative goes on a line of its own, with the `|`s aligned,
+/// and sequences too long for one line are wrapped after a `,`.
+/// Blank lines between items are kept, but runs of them are collapsed into one.
+///
+/// Comments between items stay where they are.
+/// A comment inside a rule moves to the end of the alternative it is in, and one before the first alternative to after the `=`.
+pub fn format(file: &str, source: &str) -> Result<String, Error> {
+    syntax::parse(file, source).map_err(Error::Syntax)?;
+    let (items, comments) = syntax::parse_tree(file, source).map_err(Error::Syntax)?;
+    let mut comments = comments.into_iter().peekable();
+
+    let mut out = String::new();
+    // The end of the last item or comment written.
+    let mut end = 0;
+    for node in list(&items) {
+        let span = node.span().unwrap();
+        while let Some(comment) = comments.next_if(|comment| comment.start < span.start) {
+            push_comment(&mut out, source, end, &comment);
+            end = comment.end;
+        }
+
+        if !out.is_empty() && is_blank_between(source, end, span.start) {
+            out.push('\n');
+        }
+        let mut inner = vec![];
+        while let Some(comment) = comments.next_if(|comment| comment.start < span.end) {
+            inner.push(comment);
+        }
+        out.push_str(&format_item(node, source, &inner));
+        out.push('\n');
+        end = span.end;
+    }
+    for comment in comments {
+        push_comment(&mut out, source, end, &comment);
+        end = comment.end;
+    }
+    Ok(out)
+}
+
+fn is_blank_between(source: &str, end: usize, start: usize) -> bool {
+    source[end..start].matches('\n').count() > 1
+}
+
+/// Write a comment which is outside any item, on the end of the last line if it was there in the source.
+fn push_comment(out: &mut String, source: &str, end: usize, comment: &Span) {
+    if !out.is_empty() && !source[end..comment.start].contains('\n') {
+        out.pop();
+        out.push(' ');
+    } else if !out.is_empty() && is_blank_between(source, end, comment.start) {
+        out.push('\n');
+    }
+    out.push_str(&source[comment.clone()]);
+    out.push('\n');
+}
+
+fn format_item(node: &Node, source: &str, comments: &[Span]) -> String {
+    let texts = |comments: &[Span]| comments.iter().map(|comment| &source[comment.clone()]).collect::<Vec<_>>();
+    let line = match build_item(node).expect("the file has already been parsed") {
+        Item::Import(Import { path, alias: None }) => format!("import \"{path}\" ;"),
+        Item::Import(Import { path, alias: Some(alias) }) => format!("import \"{path}\" as {alias} ;"),
+        Item::Decl(Decl::Token(names)) => format!("%token {} ;", names.join(" ")),
+        Item::Decl(Decl::Recursion(Recursion::Left)) => "%recursion left ;".to_string(),
+        Item::Decl(Decl::Recursion(Recursion::Right)) => "%recursion right ;".to_string(),
+        Item::Rule(kind, rule) => {
+            let rhs = &node.children[node.children.len() - 2];
+            let nodes = list(rhs);
+            let mut alternatives: Vec<(Vec<String>, Vec<&str>)> =
+                nodes.iter().map(|alt| (elements(&build_alt(alt)), vec![])).collect();
+
+            let mut header_comments = vec![];
+            for comment in comments {
+                let text = &source[comment.clone()];
+                match nodes.iter().rposition(|alt| alt.span().unwrap().start <= comment.start) {
+                    Some(i) => alternatives[i].1.push(text),
+                    None => header_comments.push(text),
+                }
+            }
+            return format_rule(&header(kind, &rule), &header_comments, &alternatives);
+        }
+    };
+    [vec![line.as_str()], texts(comments)].concat().join(" ")
+}
+
+/// Everything before the right-hand side of a rule, eg `@inline expr =`.
+fn header(kind: RuleKind, rule: &Rule) -> String {
+    let mut header = String::new();
+    if kind == RuleKind::Override {
+        header.push_str("%override ");
+    }
+    for annotation in &rule.annotations {
+        header.push_str(&format!("{annotation:?} "));
+    }
+    header.push_str(&rule.lhs);
+    header.push_str(if kind == RuleKind::Extend { " |=" } else { " =" });
+    header
+}
+
+/// Format a rule from its header and its alternatives, each a sequence with the comments to put after it.
+fn format_rule(header: &str, header_comments: &[&str], alternatives: &[(Vec<String>, Vec<&str>)]) -> String {
+    let commented = !header_comments.is_empty() || alternatives.iter().any(|(_, comments)| !comments.is_empty());
+    let simple = alternatives.len() == 1 || alternatives.iter().all(|(elements, _)| elements.len() == 1);
+    let rhs: Vec<String> = alternatives.iter().map(|(elements, _)| elements.join(" , ")).collect();
+    let line = format!("{header} {} ;", rhs.join(" | "));
+    if !commented && simple && width(&line) <= WIDTH {
+        return line;
+    }
+
+    let mut out = [vec![header], header_comments.to_vec()].concat().join(" ");
+    for (i, (elements, comments)) in alternatives.iter().enumerate() {
+        let prefix = if i == 0 { INDENT } else { "  | " };
+        let end = if i + 1 == alternatives.len() { " ;" } else { "" };
+        out.push('\n');
+        out.push_str(&wrap(prefix, elements, end));
+        for comment in comments {
+            out.push(' ');
+            out.push_str(comment);
+        }
+    }
+    out
+}
+
+/// Join a sequence with `,`, breaking it onto new lines where it would be too wide.
+fn wrap(prefix: &str, elements: &[String], end: &str) -> String {
+    let mut lines = vec![];
+    let mut line = prefix.to_string();
+    for (i, element) in elements.iter().enumerate() {
+        let piece = format!("{element}{}", if i + 1 == elements.len() { end } else { " ," });
+        if i == 0 {
+            line.push_str(&piece);
+        } else if width(&line) + 1 + width(&piece) > WIDTH {
+            lines.push(line);
+            line = format!("{INDENT}{piece}");
+        } else {
+            line.push(' ');
+            line.push_str(&piece);
+        }
+    }
+    lines.push(line);
+    lines.join("\n")
+}
+
+fn width(s: &str) -> usize {
+    s.chars().count()
+}
+
+/// The things in sequence in one alternative, each formatted.
+fn elements(alternative: &SymbolExpr) -> Vec<String> {
+    match alternative {
+        SymbolExpr::Seq(es) => es.iter().map(format_expr).collect(),
+        e => vec![format_expr(e)],
+    }
+}
+
+/// Format an expression as it would be written in a grammar file.
+pub fn format_expr(e: &SymbolExpr) -> String {
+    let join = |es: &[SymbolExpr], sep: &str| es.iter().map(format_expr).collect::<Vec<_>>().join(sep);
+    match e {
+        SymbolExpr::Alt(es) => join(es, " | "),
+        SymbolExpr::Seq(es) => join(es, " , "),
+        SymbolExpr::Term(s) | SymbolExpr::Nonterm(s) => s.clone(),
+        SymbolExpr::Star(e) => format!("{{ {} }}", format_expr(e)),
+        // `[ ... ]` holds a sequence, where `?` follows a single atom.
+        SymbolExpr::Opt(e) if matches!(**e, SymbolExpr::Seq(_)) => format!("[ {} ]", format_expr(e)),
+        SymbolExpr::Opt(e) => format!("{}?", format_expr(e)),
+        SymbolExpr::Plus(e) => format!("{}+", format_expr(e)),
+        SymbolExpr::Group(e) => format!("( {} )", format_expr(e)),
+        SymbolExpr::Sep(e, sep) => format!("sep({}, {})", format_expr(e), format_expr(sep)),
+        SymbolExpr::Annotated(annotations, e) => {
+            let annotations: Vec<String> = annotations.iter().map(|annotation| format!("{annotation:?}")).collect();
+            format!("{} {}", annotations.join(" "), format_expr(e))
+        }
+    }
+}
+
+/// Writes the grammar back out in the canonical style.
+/// The rules of a nonterminal after its first are written as extensions, with `|=`.
+impl std::fmt::Display for Grammar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.tokens.is_empty() {
+            writeln!(f, "%token {} ;", self.tokens.join(" "))?;
+        }
+        if self.recursion == Recursion::Right {
+            writeln!(f, "%recursion right ;")?;
+        }
+        if !self.tokens.is_empty() || self.recursion == Recursion::Right {
+            writeln!(f)?;
+        }
+
+        let mut defined = HashSet::new();
+        for rule in &self.rules {
+            let kind = if defined.insert(&rule.lhs) { RuleKind::Define } else { RuleKind::Extend };
+            let alternatives: Vec<(Vec<String>, Vec<&str>)> = match &rule.rhs {
+                SymbolExpr::Alt(es) => es.iter().map(|e| (elements(e), vec![])).collect(),
+                e => vec![(elements(e), vec![])],
+            };
+            writeln!(f, "{}", format_rule(&header(kind, rule), &[], &alternatives))?;
+        }
+        Ok(())
+    }
+}
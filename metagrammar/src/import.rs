@@ -0,0 +1,257 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::*;
+
+/// The contents of one grammar file, before its imports are resolved.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct File {
+    pub imports: Vec<Import>,
+    pub tokens: Vec<Symbol>,
+    pub recursion: Option<Recursion>,
+    pub rules: Vec<(RuleKind, Rule)>,
+}
+
+/// `import "path" as alias ;`
+#[derive(Debug, PartialEq)]
+pub(crate) struct Import {
+    pub path: String,
+    pub alias: Option<Symbol>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RuleKind {
+    /// `a = ... ;`
+    Define,
+    /// `%override a = ... ;` replaces every alternative of `a`.
+    Override,
+    /// `a |= ... ;` adds alternatives to `a`.
+    Extend,
+}
+
+/// An item at the top level of a grammar file.
+pub(crate) enum Item {
+    Import(Import),
+    Decl(Decl),
+    Rule(RuleKind, Rule),
+}
+
+struct Loaded {
+    path: PathBuf,
+    /// The name other files qualify this file's rules with, eg `types.type`.
+    namespace: Symbol,
+    file: File,
+    /// The files this one imports, by the namespace it imports them under.
+    imports: Vec<(Symbol, usize)>,
+}
+
+/// Loads a grammar file and everything it imports, then merges them into one `Grammar`.
+///
+/// Each file is a namespace.
+/// An unqualified name refers to the file's own rule of that name, or else the one rule of that name among its imports.
+/// A qualified name, `ns.name`, refers to the rule in the file imported as `ns`.
+/// Rules keep their own names in the merged grammar unless two files define the same one,
+/// in which case the imported rules are qualified by their namespace.
+pub(crate) struct Loader<'r> {
+    read: &'r mut dyn FnMut(&Path) -> std::io::Result<String>,
+    files: Vec<Loaded>,
+    /// File indices, with every file after the files it imports.
+    postorder: Vec<usize>,
+}
+
+impl<'r> Loader<'r> {
+    pub fn new(read: &'r mut dyn FnMut(&Path) -> std::io::Result<String>) -> Loader<'r> {
+        Loader {
+            read,
+            files: vec![],
+            postorder: vec![],
+        }
+    }
+
+    /// Load the file at `path` and its imports, using `source` as its text if given.
+    /// A file imported more than once (or in a cycle) is only loaded once.
+    pub fn load(&mut self, path: &Path, source: Option<String>) -> Result<usize, Error> {
+        let path = normalize(path);
+        if let Some(index) = self.files.iter().position(|loaded| loaded.path == path) {
+            return Ok(index);
+        }
+
+        let name = path.display().to_string();
+        let source = match source {
+            Some(source) => source,
+            None => (self.read)(&path).map_err(|e| Error::Io {
+                path: name.clone(),
+                message: e.to_string(),
+            })?,
+        };
+        let mut file = syntax::parse(&name, &source).map_err(Error::Syntax)?;
+
+        let index = self.files.len();
+        let imports = std::mem::take(&mut file.imports);
+        self.files.push(Loaded {
+            namespace: path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default(),
+            path: path.clone(),
+            file,
+            imports: vec![],
+        });
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for import in imports {
+            let target_path = dir.join(&import.path);
+            let target = self.load(&target_path, None)?;
+            let namespace = import.alias.unwrap_or_else(|| self.files[target].namespace.clone());
+            if self.files[index].imports.iter().any(|(other, _)| *other == namespace) {
+                return Err(self.error(index, format!("namespace {namespace} is imported twice")));
+            }
+            self.files[index].imports.push((namespace, target));
+        }
+
+        self.postorder.push(index);
+        Ok(index)
+    }
+
+    fn error(&self, file: usize, message: String) -> Error {
+        Error::Import {
+            file: self.files[file].path.display().to_string(),
+            message,
+        }
+    }
+
+    fn defines(&self, file: usize, name: &str) -> bool {
+        self.files[file].file.rules.iter().any(|(kind, rule)| *kind == RuleKind::Define && rule.lhs == name)
+    }
+
+    /// The file which defines the rule `name` refers to from within `file`, and the rule's unqualified name.
+    /// Returns `None` if no rule is in scope, as for a `%token`.
+    fn resolve(&self, file: usize, name: &str) -> Result<Option<(usize, Symbol)>, Error> {
+        if let Some((namespace, local)) = name.split_once('.') {
+            let Some((_, target)) = self.files[file].imports.iter().find(|(ns, _)| ns == namespace) else {
+                return Err(self.error(file, format!("{name} refers to {namespace}, which is not imported")));
+            };
+            if !self.defines(*target, local) {
+                return Err(self.error(file, format!("{name} is not defined in {}", self.files[*target].path.display())));
+            }
+            return Ok(Some((*target, local.to_string())));
+        }
+
+        if self.defines(file, name) {
+            return Ok(Some((file, name.to_string())));
+        }
+        let candidates: Vec<&(Symbol, usize)> =
+            self.files[file].imports.iter().filter(|(_, target)| self.defines(*target, name)).collect();
+        match candidates.as_slice() {
+            [] => Ok(None),
+            [(_, target)] => Ok(Some((*target, name.to_string()))),
+            _ => {
+                let namespaces: Vec<String> = candidates.iter().map(|(ns, _)| format!("{ns}.{name}")).collect();
+                Err(self.error(file, format!("{name} is ambiguous, it could be any of {}", namespaces.join(", "))))
+            }
+        }
+    }
+
+    /// The name the rule `name` from `file` has in the merged grammar.
+    fn merged_name(&self, file: usize, name: &str) -> Symbol {
+        let clashes = (0..self.files.len()).any(|other| other != file && self.defines(other, name));
+        if file == 0 || !clashes {
+            name.to_string()
+        } else {
+            format!("{}.{name}", self.files[file].namespace)
+        }
+    }
+
+    /// Rewrite the names used in `expr`, which appears in `file`, to their merged names.
+    /// Names with no rule in scope are left alone, to be checked against the `%token`s later.
+    fn rename(&self, file: usize, expr: &mut SymbolExpr) -> Result<(), Error> {
+        let mut result = Ok(());
+        expr.visit_names(&mut |name| {
+            match self.resolve(file, name) {
+                Ok(Some((target, local))) => *name = self.merged_name(target, &local),
+                Ok(None) => {
+                    if let Some(other) = (0..self.files.len()).find(|other| self.defines(*other, name)) {
+                        let path = self.files[other].path.display();
+                        result = Err(self.error(file, format!("{name} is defined in {path}, which is not imported")));
+                    }
+                }
+                Err(e) => result = Err(e),
+            }
+        });
+        result
+    }
+
+    /// Merge every loaded file into one grammar.
+    pub fn into_grammar(self) -> Result<Grammar, Error> {
+        let mut tokens: Vec<Symbol> = vec![];
+        for loaded in &self.files {
+            for token in &loaded.file.tokens {
+                if !tokens.contains(token) {
+                    tokens.push(token.clone());
+                }
+            }
+        }
+
+        let mut rules: Vec<Rule> = vec![];
+        for (file, loaded) in self.files.iter().enumerate() {
+            for (kind, rule) in &loaded.file.rules {
+                if *kind == RuleKind::Define {
+                    let mut rhs = rule.rhs.clone();
+                    self.rename(file, &mut rhs)?;
+                    rules.push(Rule {
+                        lhs: self.merged_name(file, &rule.lhs),
+                        rhs,
+                        annotations: rule.annotations.clone(),
+                    });
+                }
+            }
+        }
+
+        // Overrides and extensions from a file apply after those from the files it imports.
+        for &file in &self.postorder {
+            for (kind, rule) in &self.files[file].file.rules {
+                if *kind == RuleKind::Define {
+                    continue;
+                }
+                let Some((target, local)) = self.resolve(file, &rule.lhs)? else {
+                    return Err(self.error(file, format!("{} is not defined, so it cannot be overridden or extended", rule.lhs)));
+                };
+                let lhs = self.merged_name(target, &local);
+                let mut rhs = rule.rhs.clone();
+                self.rename(file, &mut rhs)?;
+
+                let first = rules.iter().position(|r| r.lhs == lhs).unwrap();
+                let last = rules.iter().rposition(|r| r.lhs == lhs).unwrap();
+                let rule = Rule {
+                    lhs: lhs.clone(),
+                    rhs,
+                    annotations: rule.annotations.clone(),
+                };
+                if *kind == RuleKind::Override {
+                    rules.retain(|r| r.lhs != lhs);
+                    rules.insert(first, rule);
+                } else {
+                    rules.insert(last + 1, rule);
+                }
+            }
+        }
+
+        Ok(Grammar {
+            tokens,
+            rules,
+            helpers: vec![],
+            recursion: self.files[0].file.recursion.unwrap_or_default(),
+        })
+    }
+}
+
+/// Remove `.` and `..` components, so the same file imported by different routes is recognized.
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir if matches!(result.components().next_back(), Some(Component::Normal(_))) => {
+                result.pop();
+            }
+            component => result.push(component),
+        }
+    }
+    result
+}
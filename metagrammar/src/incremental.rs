@@ -0,0 +1,428 @@
+//! Incremental reparsing of FIRRTL files, for editors which reparse on every change.
+//!
+//! A `Document` keeps its tokens, where each of its lines starts, and a concrete syntax tree
+//! whose nodes record the state the parser shifted them in.
+//!
+//! After an edit, lexing resumes at the start of the line the edit begins in, with the indentation open there,
+//! and stops at the first line start after the edit which the old text had too, with the same indentation,
+//! since every token from there on is unchanged.
+//!
+//! The parser, an `lr0::Machine` building `CstNode`s, then runs over the new tokens, but shifts a subtree of the old tree whole where it would build
+//! the same subtree again: where the parser is in the state the subtree was shifted in,
+//! and neither the subtree's tokens nor the token after it, which decided its last reductions, have changed.
+//! So the tree is the one a full reparse would build.
+
+use std::ops::Range;
+use std::rc::Rc;
+
+use parsing::lr0::{Limit, Limits, Machine, ParseError, ParseTable, StateIndex, TreeBuilder};
+use parsing::{Rule, RuleIndex, Span, Symbol, Token};
+use tokenizer::LineStart;
+
+use crate::firrtl::{FirrtlTokens, LexError};
+
+/// A node of the concrete syntax tree.
+/// It counts the tokens it covers rather than recording where they are,
+/// so that the subtrees after an edit can be reused as they are.
+#[derive(PartialEq, Eq)]
+pub struct CstNode<'t> {
+    pub symbol: Symbol<'t>,
+    /// The state the parser was in when it shifted this node.
+    pub state: StateIndex,
+    /// The rule this node was reduced by. `None` for tokens.
+    pub rule: Option<RuleIndex>,
+    /// How many tokens are under this node.
+    pub len: usize,
+    pub children: Vec<Rc<CstNode<'t>>>,
+}
+
+/// Trees of long statement lists are as deep as the file, so they are torn down without recursing.
+impl<'t> Drop for CstNode<'t> {
+    fn drop(&mut self) {
+        let mut orphans = std::mem::take(&mut self.children);
+        while let Some(node) = orphans.pop() {
+            if let Ok(mut node) = Rc::try_unwrap(node) {
+                orphans.append(&mut node.children);
+            }
+        }
+    }
+}
+
+/// Prints the tree as an s-expression, the same as `lr0::Node`.
+impl<'t> std::fmt::Debug for CstNode<'t> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.rule.is_none() {
+            write!(f, "{}", self.symbol)
+        } else {
+            write!(f, "({}", self.symbol)?;
+            for child in &self.children {
+                write!(f, " {child:?}")?;
+            }
+            write!(f, ")")
+        }
+    }
+}
+
+/// Has the machine build `CstNode`s.
+#[derive(Debug, Clone)]
+struct CstBuilder;
+
+impl<'t, 's> TreeBuilder<'t, 's> for CstBuilder {
+    type Node = Rc<CstNode<'t>>;
+
+    fn symbol(node: &Rc<CstNode<'t>>) -> Symbol<'t> {
+        node.symbol
+    }
+
+    fn leaf(token: Token<'t, 's>, state: StateIndex) -> Rc<CstNode<'t>> {
+        Rc::new(CstNode {
+            symbol: token.terminal,
+            state,
+            rule: None,
+            len: 1,
+            children: vec![],
+        })
+    }
+
+    fn branch(rule: Rule<'t>, children: Vec<Rc<CstNode<'t>>>, state: StateIndex) -> Rc<CstNode<'t>> {
+        Rc::new(CstNode {
+            symbol: rule.lhs(),
+            state,
+            rule: Some(rule.index()),
+            len: children.iter().map(|child| child.len).sum(),
+            children,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lexeme<'t> {
+    pub terminal: Symbol<'t>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error<'t> {
+    Lex(LexError),
+    /// There is no action for the token at `span` in `state`, or, if it is `None`, for the end of input.
+    Parse {
+        state: StateIndex,
+        symbol: Option<Symbol<'t>>,
+        span: Option<Span>,
+    },
+    /// The parse went past one of the document's `Limits`.
+    LimitExceeded(Limit),
+    /// An edit's range is backwards, past the end of the source, or starts or ends inside a character.
+    InvalidRange(Range<usize>),
+}
+
+impl<'t> std::fmt::Display for Error<'t> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Lex(e) => write!(f, "{e}"),
+            Error::Parse { state, symbol: Some(symbol), span: Some(span) } => {
+                write!(f, "unexpected {symbol} at {}..{} in state {state}", span.start, span.end)
+            }
+            Error::Parse { state, .. } => write!(f, "unexpected end of input in state {state}"),
+            Error::LimitExceeded(limit) => write!(f, "exceeded {limit}"),
+            Error::InvalidRange(range) => write!(f, "cannot edit {}..{}", range.start, range.end),
+        }
+    }
+}
+
+impl<'t> std::error::Error for Error<'t> {}
+
+/// Where a line starts, and the index of the first token after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Line {
+    token: usize,
+    start: LineStart,
+}
+
+/// A FIRRTL file, kept parsed as it is edited.
+pub struct Document<'t> {
+    table: &'t ParseTable,
+    limits: Limits,
+    source: String,
+    tokens: Vec<Lexeme<'t>>,
+    /// Empty if the source did not lex, in which case the next edit lexes it again from the start.
+    lines: Vec<Line>,
+    tree: Option<Rc<CstNode<'t>>>,
+    error: Option<Error<'t>>,
+    /// How many tokens the last parse took from the old tree.
+    reused: usize,
+}
+
+impl<'t> Document<'t> {
+    /// Parse `source` with `table`, which must start from `circuit`.
+    pub fn new(table: &'t ParseTable, source: impl Into<String>) -> Document<'t> {
+        Document::with_limits(table, source, Limits::new())
+    }
+
+    /// Parse `source` with `table`, and it again after each edit, within `limits`.
+    pub fn with_limits(table: &'t ParseTable, source: impl Into<String>, limits: Limits) -> Document<'t> {
+        let mut document = Document {
+            table,
+            limits,
+            source: source.into(),
+            tokens: vec![],
+            lines: vec![],
+            tree: None,
+            error: None,
+            reused: 0,
+        };
+        let line = Line {
+            token: 0,
+            start: LineStart {
+                offset: 0,
+                indents: vec![],
+            },
+        };
+        let result = document.relex(0, &line, 0, 0).and_then(|_relexed| document.parse(None));
+        document.error = result.err();
+        document
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn tokens(&self) -> &[Lexeme<'t>] {
+        &self.tokens
+    }
+
+    /// The tree, if the source parsed.
+    pub fn tree(&self) -> Option<&Rc<CstNode<'t>>> {
+        self.tree.as_ref()
+    }
+
+    /// Why the source did not parse.
+    pub fn error(&self) -> Option<&Error<'t>> {
+        self.error.as_ref()
+    }
+
+    /// How many tokens the last parse took from the tree before the edit, rather than parsing them again.
+    pub fn reused(&self) -> usize {
+        self.reused
+    }
+
+    /// Replace `range` of the source with `text`, and reparse what it changed.
+    /// An invalid range leaves the document as it was.
+    pub fn edit(&mut self, range: Range<usize>, text: &str) -> Result<(), Error<'t>> {
+        if self.source.get(range.clone()).is_none() {
+            return Err(Error::InvalidRange(range));
+        }
+        self.source.replace_range(range.clone(), text);
+        let old_tree = self.tree.take();
+        let result = if self.lines.is_empty() {
+            *self = Document::with_limits(self.table, std::mem::take(&mut self.source), self.limits.clone());
+            self.error.clone().map_or(Ok(()), Err)
+        } else {
+            self.reparse(range, text.len(), old_tree)
+        };
+        self.error = result.clone().err();
+        result
+    }
+
+    fn reparse(&mut self, range: Range<usize>, inserted: usize, old_tree: Option<Rc<CstNode<'t>>>) -> Result<(), Error<'t>> {
+        // The newline token before a line takes the spaces after it, so an edit at the very start of a line,
+        // which may add or remove some, is lexed again from the line before.
+        let kept = self.lines.partition_point(|line| line.start.offset < range.start).max(1);
+        let line = self.lines[kept - 1].clone();
+        let old_next = self.tokens.get(line.token).map(|token| token.terminal);
+
+        let delta = inserted as isize - range.len() as isize;
+        let (relexed, old_suffix) = match self.relex(kept, &line, range.start + inserted, delta) {
+            Ok(relexed) => relexed,
+            Err(e) => {
+                self.tokens.clear();
+                self.lines.clear();
+                return Err(e);
+            }
+        };
+
+        let reuse = old_tree.map(|root| Reuse {
+            cursor: Cursor { stack: vec![(root, 0)] },
+            prefix: line.token,
+            same_after_prefix: old_next == self.tokens.get(line.token).map(|token| token.terminal),
+            old_suffix,
+            new_suffix: line.token + relexed,
+        });
+        self.parse(reuse)
+    }
+
+    /// Lex the source from `line`, the last of the first `kept` lines, up to the first line start at or after `end`
+    /// where the old tokens can be picked up again, and splice the new tokens and lines in.
+    /// The text after `end` moved by `delta` bytes.
+    /// Returns how many tokens were lexed, and the index of the first old token kept after them.
+    fn relex(&mut self, kept: usize, line: &Line, end: usize, delta: isize) -> Result<(usize, usize), Error<'t>> {
+        let mut lexer = FirrtlTokens::resume(self.table.grammar(), &self.source, &line.start);
+        let mut tokens = vec![];
+        let mut lines = vec![];
+        // The first line, unless it is new.
+        let mut at_line = kept > 0;
+        let resume = loop {
+            if let Some(start) = lexer.line_start() {
+                let old = self.lines[kept..].binary_search_by_key(&(start.offset as isize - delta), |old| old.start.offset as isize);
+                match old {
+                    Ok(old) if start.offset >= end && self.lines[kept + old].start.indents == start.indents => break Some(kept + old),
+                    _ if !at_line => lines.push(Line {
+                        token: line.token + tokens.len(),
+                        start,
+                    }),
+                    _ => {}
+                }
+            }
+            at_line = false;
+            match lexer.try_next() {
+                Some(Ok(token)) => tokens.push(Lexeme {
+                    terminal: token.terminal,
+                    span: token.span,
+                }),
+                Some(Err(e)) => return Err(Error::Lex(e)),
+                None => break None,
+            }
+        };
+
+        let (old_suffix, old_lines) = match resume {
+            Some(old) => (self.lines[old].token, old),
+            None => (self.tokens.len(), self.lines.len()),
+        };
+        let relexed = tokens.len();
+        let shift = relexed as isize - (old_suffix - line.token) as isize;
+        self.tokens.splice(line.token..old_suffix, tokens);
+        for token in &mut self.tokens[line.token + relexed..] {
+            token.span = moved(token.span.start, delta)..moved(token.span.end, delta);
+        }
+        let new_lines = lines.len();
+        self.lines.splice(kept..old_lines, lines);
+        for line in &mut self.lines[kept + new_lines..] {
+            line.token = moved(line.token, shift);
+            line.start.offset = moved(line.start.offset, delta);
+        }
+        Ok((relexed, old_suffix))
+    }
+
+    /// Parse the tokens, taking subtrees from the old tree where `reuse` allows.
+    fn parse(&mut self, mut reuse: Option<Reuse<'t>>) -> Result<(), Error<'t>> {
+        let table = self.table;
+        let mut machine: Machine<'t, '_, CstBuilder> = Machine::with_builder(table, self.limits.clone());
+        let mut next = 0;
+        self.reused = 0;
+
+        let result = loop {
+            let Some(lexeme) = self.tokens.get(next) else {
+                break machine.finish();
+            };
+            let token = Token::new(lexeme.terminal, &self.source[lexeme.span.clone()], lexeme.span.clone());
+            let mut taken = None;
+            let fed = machine.feed_or_reuse(token, |state| {
+                let node = reuse.as_mut()?.take(table, next, state)?;
+                taken = Some(node.len);
+                Some(node)
+            });
+            if let Err(e) = fed {
+                break Err(e);
+            }
+            self.reused += taken.unwrap_or(0);
+            next += taken.unwrap_or(1);
+        };
+
+        let state = match result {
+            Ok(()) => {
+                self.tree = machine.tree().cloned();
+                return Ok(());
+            }
+            Err(ParseError::LimitExceeded { limit, .. }) => return Err(Error::LimitExceeded(limit)),
+            Err(ParseError::UnexpectedSymbol { state, .. }) => state,
+            // The machine halted before the end of the input.
+            Err(_) => machine.state(),
+        };
+        Err(Error::Parse {
+            state,
+            symbol: self.tokens.get(next).map(|token| token.terminal),
+            span: self.tokens.get(next).map(|token| token.span.clone()),
+        })
+    }
+}
+
+fn moved(offset: usize, delta: isize) -> usize {
+    offset.checked_add_signed(delta).expect("only text after an edit moves")
+}
+
+/// The old tree, and which of its tokens are unchanged.
+struct Reuse<'t> {
+    cursor: Cursor<'t>,
+    /// The tokens before this were not lexed again.
+    prefix: usize,
+    /// Whether the token at `prefix`, or the end of input, is the same terminal after the edit.
+    same_after_prefix: bool,
+    /// The first of the old tokens after those lexed again, and where it is now.
+    old_suffix: usize,
+    new_suffix: usize,
+}
+
+impl<'t> Reuse<'t> {
+    /// The largest old subtree the parser can shift whole at token `next` in `state`.
+    fn take(&mut self, table: &ParseTable, next: usize, state: StateIndex) -> Option<Rc<CstNode<'t>>> {
+        let position = if next < self.prefix {
+            next
+        } else if next >= self.new_suffix {
+            next - self.new_suffix + self.old_suffix
+        } else {
+            return None;
+        };
+
+        while let Some((node, start)) = self.cursor.seek(position) {
+            // Tokens are shifted from the new tokens.
+            node.rule?;
+            let end = start + node.len;
+            let unchanged = end < self.prefix || (end == self.prefix && self.same_after_prefix) || start >= self.old_suffix;
+            if unchanged && node.state == state && table.goto(state, node.symbol).is_some() {
+                self.cursor.stack.pop();
+                return Some(node);
+            }
+            self.cursor.descend();
+        }
+        None
+    }
+}
+
+/// Walks the old tree from left to right.
+struct Cursor<'t> {
+    /// The nodes still to visit, with the index of their first token, the next last.
+    stack: Vec<(Rc<CstNode<'t>>, usize)>,
+}
+
+impl<'t> Cursor<'t> {
+    /// The largest node starting at token `position`, which must not be before the last position sought.
+    fn seek(&mut self, position: usize) -> Option<(Rc<CstNode<'t>>, usize)> {
+        while let Some((node, start)) = self.stack.last().cloned() {
+            if start == position && node.len > 0 {
+                return Some((node, start));
+            }
+            if start > position {
+                return None;
+            }
+            self.stack.pop();
+            if start + node.len > position {
+                self.push_children(&node, start);
+            }
+        }
+        None
+    }
+
+    /// Visit the children of the node `seek` returned, rather than it.
+    fn descend(&mut self) {
+        let (node, start) = self.stack.pop().unwrap();
+        self.push_children(&node, start);
+    }
+
+    fn push_children(&mut self, node: &CstNode<'t>, start: usize) {
+        let mut end = start + node.len;
+        for child in node.children.iter().rev() {
+            end -= child.len;
+            self.stack.push((child.clone(), end));
+        }
+    }
+}
@@ -0,0 +1,591 @@
+use std::collections::{HashMap, HashSet};
+
+use std::path::Path;
+
+pub use diagnostic::SyntaxError;
+pub(crate) use import::{File, Import, Item, RuleKind};
+
+pub mod corpus;
+pub mod debugger;
+mod diagnostic;
+pub mod firrtl;
+pub mod format;
+mod import;
+pub mod incremental;
+pub mod suite;
+pub mod syntax;
+pub mod tree;
+#[cfg(test)]
+mod test;
+
+// The lalrpop parser the self-hosted one in `syntax` replaced, kept to test against.
+#[cfg(all(test, feature = "lalrpop-oracle"))]
+lalrpop_util::lalrpop_mod!(metagrammar);
+
+#[derive(Debug, Clone)]
+pub struct Grammar {
+    /// Named terminals declared with `%token`.
+    tokens: Vec<Symbol>,
+    rules: Vec<Rule>,
+    /// The nonterminals introduced by `split`.
+    helpers: Vec<Helper>,
+    recursion: Recursion,
+}
+
+/// A nonterminal introduced by `Grammar::split` to stand in for an EBNF construct.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Helper {
+    pub name: Symbol,
+    /// The rule the construct was first found in.
+    pub rule: Symbol,
+    /// The construct itself.
+    pub expr: SymbolExpr,
+}
+
+impl Grammar {
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn tokens(&self) -> &[Symbol] {
+        &self.tokens
+    }
+
+    pub fn recursion(&self) -> Recursion {
+        self.recursion
+    }
+
+    /// Choose how `split` expands repetitions, overriding any `%recursion` declaration.
+    pub fn set_recursion(&mut self, recursion: Recursion) {
+        self.recursion = recursion;
+    }
+
+    pub fn helpers(&self) -> &[Helper] {
+        &self.helpers
+    }
+
+    /// Where the helper nonterminal `name` came from, if it is one.
+    pub fn helper(&self, name: &str) -> Option<&Helper> {
+        self.helpers.iter().find(|helper| helper.name == name)
+    }
+
+    /// The terminals of the grammar: every quoted literal used in a rule, followed by the `%token` names.
+    pub fn terminals(&self) -> Vec<Symbol> {
+        let mut terminals = vec![];
+        for rule in &self.rules {
+            rule.rhs.literals(&mut terminals);
+        }
+        for token in &self.tokens {
+            if !terminals.contains(token) {
+                terminals.push(token.clone());
+            }
+        }
+        terminals
+    }
+
+    /// Check every name is either defined by a rule or declared with `%token`, but not both.
+    pub fn check(&self) -> Result<(), Error> {
+        let nonterminals = self.nonterminals();
+
+        let mut redefined: Vec<Symbol> = self.tokens.iter().filter(|token| nonterminals.contains(*token)).cloned().collect();
+        if !redefined.is_empty() {
+            redefined.sort();
+            redefined.dedup();
+            return Err(Error::Redefined(redefined));
+        }
+
+        let mut names = vec![];
+        for rule in &self.rules {
+            rule.rhs.names(&mut names);
+        }
+        let mut undefined: Vec<Symbol> = names
+            .into_iter()
+            .filter(|name| !nonterminals.contains(name) && !self.tokens.contains(name))
+            .collect();
+        if !undefined.is_empty() {
+            undefined.sort();
+            undefined.dedup();
+            return Err(Error::Undefined(undefined));
+        }
+        Ok(())
+    }
+
+    /// Lower the EBNF constructs in every rule into plain BNF rules.
+    ///
+    /// Rules keep their source order, and the rules for each helper nonterminal follow the rule which introduced it.
+    /// Helpers are named after that rule and the kind of construct, eg `decl_module__star1`.
+    /// The same construct used in several places shares a single helper.
+    pub fn split(&mut self) {
+        let rules = std::mem::take(&mut self.rules);
+
+        let mut splitter = Splitter {
+            names: rules.iter().map(|rule| rule.lhs.clone()).chain(self.tokens.iter().cloned()).collect(),
+            recursion: self.recursion,
+            counts: HashMap::new(),
+            helpers: std::mem::take(&mut self.helpers),
+            rules: vec![],
+        };
+        for rule in &rules {
+            splitter.split(&rule.lhs, rule);
+        }
+
+        self.rules = splitter.rules;
+        self.helpers = splitter.helpers;
+    }
+
+    /// The nonterminals, in the order they are first defined.
+    pub fn nonterminals(&self) -> Vec<Symbol> {
+        let mut result = vec![];
+        for rule in &self.rules {
+            if !result.contains(&rule.lhs) {
+                result.push(rule.lhs.clone());
+            }
+        }
+        result
+    }
+}
+
+struct Splitter {
+    /// Every name in use, so helpers never shadow a name from the source.
+    names: HashSet<Symbol>,
+    recursion: Recursion,
+    /// How many helpers have been named after each rule.
+    counts: HashMap<Symbol, usize>,
+    helpers: Vec<Helper>,
+    rules: Vec<Rule>,
+}
+
+impl Splitter {
+    /// Split `rule`, which came from the source rule for `origin`.
+    fn split(&mut self, origin: &Symbol, rule: &Rule) {
+        if rule.is_simple() {
+            self.rules.push(rule.clone());
+            return;
+        }
+
+        let mut definitions = vec![];
+        for seq in rule.rhs.alternatives() {
+            let es: Vec<SymbolExpr> = seq.into_iter().map(|e| {
+                if e.is_symbol() {
+                    e
+                } else if let SymbolExpr::Annotated(annotations, e) = e {
+                    SymbolExpr::Annotated(annotations, Box::new(self.helper_for(origin, *e, &mut definitions)))
+                } else {
+                    self.helper_for(origin, e, &mut definitions)
+                }
+            }).collect();
+            self.rules.push(Rule {
+                lhs: rule.lhs.clone(),
+                rhs: SymbolExpr::Seq(es),
+                annotations: rule.annotations.clone(),
+            });
+        }
+
+        for definition in &definitions {
+            self.split(origin, definition);
+        }
+    }
+
+    /// The helper nonterminal standing in for `e`, adding the rules to define it if it is new.
+    fn helper_for(&mut self, origin: &Symbol, e: SymbolExpr, definitions: &mut Vec<Rule>) -> SymbolExpr {
+        let name = match self.helpers.iter().find(|helper| helper.expr == e) {
+            Some(helper) => helper.name.clone(),
+            None => {
+                let name = self.fresh_name(origin, &e);
+                definitions.extend(e.definition(&name, self.recursion));
+                self.helpers.push(Helper {
+                    name: name.clone(),
+                    rule: origin.clone(),
+                    expr: e,
+                });
+                name
+            }
+        };
+        SymbolExpr::Nonterm(name)
+    }
+
+    fn fresh_name(&mut self, origin: &Symbol, e: &SymbolExpr) -> Symbol {
+        let kind = match e {
+            SymbolExpr::Star(_) => "star",
+            SymbolExpr::Plus(_) => "plus",
+            SymbolExpr::Sep(_, _) => "sep",
+            SymbolExpr::Opt(_) => "opt",
+            _ => "alt",
+        };
+        loop {
+            let count = self.counts.entry(origin.clone()).or_default();
+            *count += 1;
+            let name = format!("{origin}__{kind}{count}");
+            if self.names.insert(name.clone()) {
+                return name;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub struct Rule {
+    pub lhs: Symbol,
+    pub rhs: SymbolExpr,
+    pub annotations: Vec<Annotation>,
+}
+
+impl std::fmt::Debug for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for annotation in &self.annotations {
+            write!(f, "{annotation:?} ")?;
+        }
+        write!(f, "{} -> {:?}", &self.lhs, &self.rhs)
+    }
+}
+
+/// Shapes the tree built from a rule (`@inline`, `@node`) or from a symbol in a rule (`@skip`, `@name`).
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub enum Annotation {
+    /// The rule's node is dissolved, and its children spliced into its parent.
+    Inline,
+    /// The rule's node has this kind, rather than the name of the rule.
+    Node(Symbol),
+    /// The symbol is dropped from the tree.
+    Skip,
+    /// The symbol's nodes are labelled with this field name.
+    Name(Symbol),
+}
+
+impl std::fmt::Debug for Annotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Annotation::Inline => write!(f, "@inline"),
+            Annotation::Node(kind) => write!(f, "@node({kind})"),
+            Annotation::Skip => write!(f, "@skip"),
+            Annotation::Name(field) => write!(f, "@name({field})"),
+        }
+    }
+}
+
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub enum SymbolExpr {
+    Alt(Vec<SymbolExpr>),
+    Seq(Vec<SymbolExpr>),
+    Term(Symbol),
+    Nonterm(Symbol),
+    Star(Box<SymbolExpr>),
+    /// One or more.
+    Plus(Box<SymbolExpr>),
+    Opt(Box<SymbolExpr>),
+    Group(Box<SymbolExpr>),
+    /// One or more of the first expression, separated by the second.
+    Sep(Box<SymbolExpr>, Box<SymbolExpr>),
+    Annotated(Vec<Annotation>, Box<SymbolExpr>),
+}
+
+/// Whether repetitions expand into left-recursive rules (`A -> A x`) or right-recursive ones (`A -> x A`).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Recursion {
+    #[default]
+    Left,
+    Right,
+}
+
+/// A declaration at the top of a grammar file.
+pub(crate) enum Decl {
+    Token(Vec<Symbol>),
+    Recursion(Recursion),
+}
+
+impl std::fmt::Debug for SymbolExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |es: &[SymbolExpr], sep: &str| es.iter().map(|e| format!("{e:?}")).collect::<Vec<_>>().join(sep);
+        match self {
+            SymbolExpr::Alt(es) => write!(f, "{}", join(es, " | ")),
+            SymbolExpr::Seq(es) if es.is_empty() => write!(f, "ε"),
+            SymbolExpr::Seq(es) => write!(f, "{}", join(es, " , ")),
+            SymbolExpr::Term(s) | SymbolExpr::Nonterm(s) => write!(f, "{s}"),
+            SymbolExpr::Star(e) => write!(f, "{{ {e:?} }}"),
+            SymbolExpr::Plus(e) => write!(f, "{e:?}+"),
+            SymbolExpr::Sep(e, sep) => write!(f, "sep({e:?}, {sep:?})"),
+            SymbolExpr::Annotated(annotations, e) => {
+                for annotation in annotations {
+                    write!(f, "{annotation:?} ")?;
+                }
+                write!(f, "{e:?}")
+            }
+            SymbolExpr::Opt(e) => write!(f, "[ {e:?} ]"),
+            SymbolExpr::Group(e) => write!(f, "( {e:?} )"),
+        }
+    }
+}
+
+impl SymbolExpr {
+    /// The alternatives of this expression, each flattened into a sequence.
+    fn alternatives(&self) -> Vec<Vec<SymbolExpr>> {
+        match self {
+            SymbolExpr::Alt(es) => es.iter().map(|e| e.sequence()).collect(),
+            SymbolExpr::Group(e) => e.alternatives(),
+            _ => vec![self.sequence()],
+        }
+    }
+
+    /// This expression as a flat sequence.
+    /// Nested sequences, and groups with only one alternative, are spliced into the sequence.
+    fn sequence(&self) -> Vec<SymbolExpr> {
+        match self {
+            SymbolExpr::Seq(es) => es.iter().flat_map(|e| e.sequence()).collect(),
+            SymbolExpr::Alt(es) if es.len() == 1 => es[0].sequence(),
+            SymbolExpr::Group(e) if e.alternatives().len() == 1 => e.sequence(),
+            // Annotations on a sequence apply to each thing in it.
+            SymbolExpr::Annotated(annotations, e) => e.sequence().into_iter().map(|e| e.annotated(annotations)).collect(),
+            _ => vec![self.clone()],
+        }
+    }
+
+    fn annotated(self, annotations: &[Annotation]) -> SymbolExpr {
+        match self {
+            SymbolExpr::Annotated(inner, e) => SymbolExpr::Annotated([annotations, &inner].concat(), e),
+            e => SymbolExpr::Annotated(annotations.to_vec(), Box::new(e)),
+        }
+    }
+
+    /// The annotations on this expression, and the expression they apply to.
+    pub fn annotations(&self) -> (&[Annotation], &SymbolExpr) {
+        match self {
+            SymbolExpr::Annotated(annotations, e) => (annotations, e),
+            e => (&[], e),
+        }
+    }
+
+    fn is_symbol(&self) -> bool {
+        matches!(self.annotations().1, SymbolExpr::Term(_) | SymbolExpr::Nonterm(_))
+    }
+
+    /// Whether this expression is already a plain BNF right-hand side.
+    fn is_bnf(&self) -> bool {
+        match self {
+            SymbolExpr::Seq(es) => es.iter().all(|e| e.is_symbol()),
+            _ => self.is_symbol(),
+        }
+    }
+
+    /// The rules defining the helper nonterminal `lhs` which stands in for this expression.
+    /// Repetitions expand into left- or right-recursive rules according to `recursion`.
+    /// The right-hand sides may still need splitting.
+    fn definition(&self, lhs: &str, recursion: Recursion) -> Vec<Rule> {
+        let this = || SymbolExpr::Nonterm(lhs.to_string());
+        let recurse = |before: Vec<SymbolExpr>, after: Vec<SymbolExpr>| match recursion {
+            Recursion::Left => [vec![this()], before].concat(),
+            Recursion::Right => [after, vec![this()]].concat(),
+        };
+        let rhss = match self {
+            SymbolExpr::Alt(_) | SymbolExpr::Group(_) => self.alternatives(),
+            SymbolExpr::Opt(e) => vec![vec![], vec![*e.clone()]],
+            SymbolExpr::Star(e) => vec![vec![], recurse(vec![*e.clone()], vec![*e.clone()])],
+            SymbolExpr::Plus(e) => vec![vec![*e.clone()], recurse(vec![*e.clone()], vec![*e.clone()])],
+            SymbolExpr::Sep(e, sep) => vec![
+                vec![*e.clone()],
+                recurse(vec![*sep.clone(), *e.clone()], vec![*e.clone(), *sep.clone()]),
+            ],
+            SymbolExpr::Term(_) | SymbolExpr::Nonterm(_) | SymbolExpr::Seq(_) | SymbolExpr::Annotated(_, _) => {
+                unreachable!("{self:?} does not need a helper")
+            }
+        };
+        rhss.into_iter()
+            .map(|rhs| Rule {
+                lhs: lhs.to_string(),
+                rhs: SymbolExpr::Seq(rhs),
+                annotations: vec![],
+            })
+            .collect()
+    }
+
+    /// Collect the quoted literals in this expression, in order of first appearance.
+    fn literals(&self, result: &mut Vec<Symbol>) {
+        match self {
+            SymbolExpr::Term(s) => {
+                if !result.contains(s) {
+                    result.push(s.clone());
+                }
+            }
+            SymbolExpr::Nonterm(_) => (),
+            SymbolExpr::Alt(es) | SymbolExpr::Seq(es) => es.iter().for_each(|e| e.literals(result)),
+            SymbolExpr::Star(e) | SymbolExpr::Plus(e) | SymbolExpr::Opt(e) | SymbolExpr::Group(e) => e.literals(result),
+            SymbolExpr::Annotated(_, e) => e.literals(result),
+            SymbolExpr::Sep(e, sep) => {
+                e.literals(result);
+                sep.literals(result);
+            }
+        }
+    }
+
+    /// Call `f` on every unquoted name referenced in this expression, allowing it to be rewritten.
+    fn visit_names(&mut self, f: &mut dyn FnMut(&mut Symbol)) {
+        match self {
+            SymbolExpr::Term(_) => (),
+            SymbolExpr::Nonterm(s) => f(s),
+            SymbolExpr::Alt(es) | SymbolExpr::Seq(es) => es.iter_mut().for_each(|e| e.visit_names(f)),
+            SymbolExpr::Star(e) | SymbolExpr::Plus(e) | SymbolExpr::Opt(e) | SymbolExpr::Group(e) => e.visit_names(f),
+            SymbolExpr::Annotated(_, e) => e.visit_names(f),
+            SymbolExpr::Sep(e, sep) => {
+                e.visit_names(f);
+                sep.visit_names(f);
+            }
+        }
+    }
+
+    /// Collect the unquoted names referenced in this expression.
+    fn names(&self, result: &mut Vec<Symbol>) {
+        match self {
+            SymbolExpr::Term(_) => (),
+            SymbolExpr::Nonterm(s) => result.push(s.clone()),
+            SymbolExpr::Alt(es) | SymbolExpr::Seq(es) => es.iter().for_each(|e| e.names(result)),
+            SymbolExpr::Star(e) | SymbolExpr::Plus(e) | SymbolExpr::Opt(e) | SymbolExpr::Group(e) => e.names(result),
+            SymbolExpr::Annotated(_, e) => e.names(result),
+            SymbolExpr::Sep(e, sep) => {
+                e.names(result);
+                sep.names(result);
+            }
+        }
+    }
+
+    fn to_vec(&self) -> Vec<&str> {
+        let mut result = vec![];
+        if let SymbolExpr::Term(e) = self {
+            result.push(e.as_str());
+        } else if let SymbolExpr::Nonterm(e) = self {
+            result.push(e.as_str());
+        } else if let SymbolExpr::Seq(es) = self {
+            for e in es {
+                result.extend(e.to_vec());
+            }
+        } else if let SymbolExpr::Annotated(_, e) = self {
+            result.extend(e.to_vec());
+        } else {
+            panic!("Can't to_vec: {self:?}");
+        }
+        result
+    }
+}
+
+impl Rule {
+    fn is_simple(&self) -> bool {
+        self.rhs.is_bnf()
+    }
+}
+
+pub type Symbol = String;
+
+#[derive(Debug)]
+pub enum Error {
+    Syntax(Box<SyntaxError>),
+    Io {
+        path: String,
+        message: String,
+    },
+    /// A problem resolving names across imported files.
+    Import {
+        file: String,
+        message: String,
+    },
+    UnknownStart(Symbol),
+    /// A grammar symbol named `START`, which is reserved for the synthetic start rule.
+    Reserved(Symbol),
+    /// Names used in a rule which are neither defined by a rule nor declared with `%token`.
+    Undefined(Vec<Symbol>),
+    /// Names declared with `%token` which are also defined by a rule.
+    Redefined(Vec<Symbol>),
+    /// A mistake in the layout of a grammar test file.
+    Suite {
+        file: String,
+        line: usize,
+        message: String,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Syntax(error) => write!(f, "{error}"),
+            Error::Io { path, message } => write!(f, "Could not read {path}: {message}"),
+            Error::Import { file, message } => write!(f, "{file}: {message}"),
+            Error::UnknownStart(start) => write!(f, "Start symbol {start} is not defined"),
+            Error::Reserved(symbol) => write!(f, "{symbol} is reserved for the synthetic start rule"),
+            Error::Undefined(symbols) => {
+                write!(f, "Referenced but neither defined nor declared with %token: {}", symbols.join(", "))
+            }
+            Error::Redefined(symbols) => {
+                write!(f, "Declared with %token but also defined by a rule: {}", symbols.join(", "))
+            }
+            Error::Suite { file, line, message } => write!(f, "{file}:{line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Parse the text of a grammar file.
+/// Any imports are read relative to the current directory.
+pub fn parse(source: &str) -> Result<Grammar, Error> {
+    parse_named("<input>", source)
+}
+
+/// Parse the text of a grammar file, naming it `file` in any errors.
+pub fn parse_named(file: &str, source: &str) -> Result<Grammar, Error> {
+    let mut read = |path: &Path| std::fs::read_to_string(path);
+    let mut loader = import::Loader::new(&mut read);
+    loader.load(Path::new(file), Some(source.to_string()))?;
+    loader.into_grammar()
+}
+
+/// Read and parse a grammar file, along with the files it imports.
+pub fn parse_file(path: impl AsRef<Path>) -> Result<Grammar, Error> {
+    load(path, |path| std::fs::read_to_string(path))
+}
+
+/// Parse the grammar file at `path`, using `read` to read it and the files it imports.
+pub fn load(path: impl AsRef<Path>, mut read: impl FnMut(&Path) -> std::io::Result<String>) -> Result<Grammar, Error> {
+    let mut loader = import::Loader::new(&mut read);
+    loader.load(path.as_ref(), None)?;
+    loader.into_grammar()
+}
+
+impl Grammar {
+    /// Build a `parsing::Grammar` from the desugared rules.
+    /// A synthetic `START -> start` rule is added as the first rule, so the grammar may not use `START` itself.
+    /// If `split` has not been run, a copy of the grammar is split first.
+    pub fn to_parsing_grammar(&self, start: &str) -> Result<parsing::Grammar, Error> {
+        if !self.rules.iter().all(Rule::is_simple) {
+            let mut grammar = self.clone();
+            grammar.split();
+            return grammar.to_parsing_grammar(start);
+        }
+        self.check()?;
+
+        let nonterminals = self.nonterminals();
+        if !nonterminals.iter().any(|nonterminal| nonterminal == start) {
+            return Err(Error::UnknownStart(start.to_string()));
+        }
+        let terminals = self.terminals();
+        if nonterminals.iter().chain(&terminals).any(|symbol| symbol == "START") {
+            return Err(Error::Reserved("START".to_string()));
+        }
+
+        let mut grammar = parsing::Grammar::new();
+
+        grammar = grammar.symbol("START");
+
+        for nonterminal in &nonterminals {
+            grammar = grammar.symbol(nonterminal.as_str());
+        }
+
+        for terminal in terminals {
+            grammar = grammar.symbol(terminal);
+        }
+
+        grammar = grammar.rule("START", &[start]);
+
+        for rule in &self.rules {
+            grammar = grammar.rule(&rule.lhs, &rule.rhs.to_vec());
+        }
+
+        Ok(grammar.build())
+    }
+}
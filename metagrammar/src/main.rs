@@ -1,548 +1,296 @@
-use parsing::lr0;
-use tokenizer::FirrtlLexer;
-use std::collections::HashSet;
-
-use metagrammar::GrammarParser;
-
-lalrpop_util::lalrpop_mod!(metagrammar);
-
-#[derive(Debug)]
-pub struct Grammar {
-    rules: Vec<Rule>,
-}
+use std::path::{Path, PathBuf};
 
-impl Grammar {
-    fn split(&mut self) {
-        let mut rules_left = vec![];
-        std::mem::swap(&mut self.rules, &mut rules_left);
-
-        let mut rules = HashSet::new();
+use clap::{Parser, Subcommand, ValueEnum};
+use parsing::lr0;
 
-        while let Some(rule) = rules_left.pop() {
-            if rule.is_simple() {
-                rules.insert(rule);
-            } else {
-                for rule in rule.split() {
-                    rules_left.push(rule);
-                }
-            }
-        }
+#[derive(Parser)]
+#[command(about = "Build and inspect parsers from EBNF grammar files")]
+struct Cli {
+    /// The grammar file to load.
+    #[arg(short, long, global = true, default_value = "GRAMMAR")]
+    grammar: PathBuf,
 
-        let mut rules: Vec<_> = rules.into_iter().collect();
-        std::mem::swap(&mut self.rules, &mut rules);
-    }
+    /// The nonterminal to start parsing from (default `circuit`, or `statement` for `corpus`).
+    #[arg(short, long, global = true)]
+    start: Option<String>,
 
-    fn nonterminals(&self) -> HashSet<Symbol> {
-        let mut result = HashSet::new();
-        for rule in &self.rules {
-            result.insert(rule.lhs.clone());
-        }
-        result
-    }
-}
+    /// Write a trace of table construction and parsing to this file, filtered by `RUST_LOG` (default `debug`).
+    #[arg(long, global = true)]
+    trace: Option<PathBuf>,
 
-#[derive(Clone, Hash, PartialEq, Eq)]
-pub struct Rule {
-    lhs: Symbol,
-    rhs: SymbolExpr,
+    #[command(subcommand)]
+    command: Command,
 }
 
-impl std::fmt::Debug for Rule {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} -> {:?}", &self.lhs, &self.rhs)
-    }
+#[derive(Subcommand)]
+enum Command {
+    /// Load the grammar and report its size and number of conflicts.
+    Check,
+    /// Print the desugared rules and every state of the parse table.
+    Table,
+    /// Print the conflicts in the parse table.
+    Conflicts,
+    /// Parse a FIRRTL file and print its tree, shaped by the grammar's annotations.
+    Parse {
+        file: PathBuf,
+
+        /// Print the parse tree as the parser built it, with every helper nonterminal.
+        #[arg(long)]
+        raw: bool,
+
+        /// Fail if the parser's stack grows deeper than this.
+        #[arg(long)]
+        max_depth: Option<usize>,
+
+        /// Fail if the file has more tokens than this.
+        #[arg(long)]
+        max_tokens: Option<usize>,
+
+        /// Fail if the parser takes more steps than this.
+        #[arg(long)]
+        max_steps: Option<usize>,
+
+        /// Fail if parsing takes longer than this many milliseconds.
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+    /// Rewrite the grammar file in the canonical style.
+    Fmt {
+        /// Report whether the file is formatted instead of rewriting it.
+        #[arg(long)]
+        check: bool,
+    },
+    /// Run grammar test files through every backend, and report the cases which fail.
+    Test {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Parse each line of a corpus of FIRRTL statements, and report how many of each kind pass.
+    Corpus {
+        #[arg(default_value = "tests/statements.txt")]
+        file: PathBuf,
+
+        /// Compare the report against this file, failing if it differs.
+        #[arg(long, default_value = "tests/statements.snapshot")]
+        snapshot: PathBuf,
+
+        /// Write the report to the snapshot file instead of comparing against it.
+        #[arg(long)]
+        update: bool,
+    },
+    /// Print the steps of the last parse recorded with `--trace`, with the items of the state each was taken in.
+    Replay { file: PathBuf },
+    /// Write the parse table to a file.
+    Export {
+        #[arg(short, long, value_enum, default_value = "json")]
+        format: Format,
+
+        /// Where to write the table. Defaults to stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
-#[derive(Clone, Hash, PartialEq, Eq)]
-pub enum SymbolExpr {
-    Alt(Vec<SymbolExpr>),
-    Seq(Vec<SymbolExpr>),
-    Term(Symbol),
-    Nonterm(Symbol),
-    Star(Box<SymbolExpr>),
-    Opt(Box<SymbolExpr>),
-    Group(Box<SymbolExpr>),
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Json,
+    Binary,
 }
 
-impl std::fmt::Debug for SymbolExpr {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", match self {
-            SymbolExpr::Alt(es) => es.into_iter().cloned().map(|e| format!("{e:?}")).collect::<Vec<_>>().join(" | "),
-            SymbolExpr::Seq(es) => {
-                if es.is_empty() {
-                    format!("SEQ()")
-                } else {
-                    format!("SEQ({})", es.into_iter().cloned().map(|e| format!("{e:?}")).collect::<Vec<_>>().join(" , "))
-                }
-            }
-            SymbolExpr::Term(s) => s.clone(),
-            SymbolExpr::Nonterm(s) => s.clone(),
-            SymbolExpr::Star(e) => format!("{{ {e:?} }}"),
-            SymbolExpr::Opt(e) => format!("[ {e:?} ]"),
-            SymbolExpr::Group(e) => format!("({e:?})"),
-        })
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(&cli) {
+        eprintln!("ERROR: {e}");
+        std::process::exit(1);
     }
 }
 
-impl SymbolExpr {
-    // seqs of alts
-    fn alt_of_seqs(&self) -> Vec<SymbolExpr> {
-        match self {
-            SymbolExpr::Alt(es) => {
-                es.into_iter().cloned().map(|e| {
-                    e
-                }).collect()
-            }
-            SymbolExpr::Seq(es) => vec![self.clone()],
-            _ => vec![SymbolExpr::Seq(vec![self.clone()])],
-        }
+fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(path) = &cli.trace {
+        let filter = std::env::var("RUST_LOG").unwrap_or_else(|_| "debug".to_string());
+        parsing::trace::install(path, &filter)?;
     }
 
-    fn needs_split(&self) -> bool {
-        match self {
-            SymbolExpr::Term(_) => false,
-            SymbolExpr::Nonterm(_) => false,
-            SymbolExpr::Alt(_) => true,
-            SymbolExpr::Star(_) => true,
-            SymbolExpr::Opt(_) => true,
-            SymbolExpr::Group(e) => e.needs_split(),
-            SymbolExpr::Seq(es) => es.iter().any(|e| e.needs_split()),
+    match &cli.command {
+        Command::Fmt { check } => return fmt(&cli.grammar, *check),
+        Command::Test { files } => return test(files),
+        Command::Corpus { file, snapshot, update } => {
+            let start = cli.start.as_deref().unwrap_or(metagrammar::corpus::START);
+            return corpus(&cli.grammar, start, file, snapshot, *update);
         }
+        _ => {}
     }
 
-    fn is_compound(&self) -> bool {
-        match self {
-            SymbolExpr::Term(_) => false,
-            SymbolExpr::Nonterm(_) => false,
-            SymbolExpr::Alt(_) => true,
-            SymbolExpr::Star(_) => true,
-            SymbolExpr::Opt(_) => true,
-            SymbolExpr::Group(e) => true,
-            SymbolExpr::Seq(es) => es.iter().any(|e| e.needs_split()),
+    let (ebnf, table) = load_table(cli)?;
+    let grammar = table.grammar();
+
+    match &cli.command {
+        Command::Check => {
+            let conflicts = table.conflicts();
+            println!("{}: OK", cli.grammar.display());
+            println!("    {} rules", grammar.rules().len());
+            println!("    {} terminals", grammar.terminals().len());
+            println!("    {} nonterminals", grammar.nonterminals().len());
+            println!("    {} states", table.states.len());
+            println!("    {} conflicts", conflicts.len());
+
+            let (missing, unused) = metagrammar::firrtl::unmatched_terminals(grammar);
+            for terminal in missing {
+                println!("terminal {terminal} has no token in the tokenizer");
+            }
+            for token in unused {
+                println!("token {token} has no terminal in the grammar");
+            }
         }
-    }
-
-    fn definition(&self) -> Vec<Rule> {
-        let lhs = format!("<{self:?}>");
-        match self {
-            SymbolExpr::Alt(es) => todo!(),
-            SymbolExpr::Seq(_) => todo!("definition for {self:?}"),
-            SymbolExpr::Term(_) => todo!(),
-            SymbolExpr::Nonterm(_) => todo!(),
-            SymbolExpr::Star(e) => vec![
-                Rule {
-                    lhs: lhs.clone(),
-                    rhs: Self::Seq(vec![]),
-                },
-                Rule {
-                    lhs: lhs.clone(),
-                    rhs: Self::Seq(vec![SymbolExpr::Nonterm(lhs), *e.clone()]),
+        Command::Table => {
+            println!("{grammar:?}");
+            println!();
+            for helper in ebnf.helpers() {
+                println!("{} is {:?} from {}", helper.name, helper.expr, helper.rule);
+            }
+            println!();
+            for state in 0..table.states.len() {
+                println!("State {state}");
+                for line in format!("{:?}", table.state(state)).lines() {
+                    println!("    {line}");
                 }
-            ],
-            SymbolExpr::Opt(e) => vec![
-                Rule {
-                    lhs: lhs.clone(),
-                    rhs: Self::Seq(vec![]),
-                },
-                Rule {
-                    lhs,
-                    rhs: *e.clone(),
+                for symbol in grammar.symbols() {
+                    let actions = table.actions(state, Some(symbol));
+                    if !actions.is_empty() {
+                        println!("    on {symbol} => {}", format_actions(&table, actions));
+                    }
                 }
-            ],
-            SymbolExpr::Group(e) => vec![
-                Rule {
-                    lhs,
-                    rhs: *e.clone(),
+                let actions = table.actions(state, None);
+                if !actions.is_empty() {
+                    println!("    on $ => {}", format_actions(&table, actions));
                 }
-            ],
+                println!();
+            }
         }
-    }
-
-    fn to_vec(&self) -> Vec<&'static str> {
-        let mut result = vec![];
-        if let SymbolExpr::Term(e) = self {
-            result.push(e.clone().leak() as &'static str);
-        } else if let SymbolExpr::Nonterm(e) = self {
-            result.push(e.clone().leak() as &'static str);
-        } else if let SymbolExpr::Seq(es) = self {
-            for e in es {
-                result.extend(e.to_vec());
+        Command::Conflicts => {
+            for conflict in table.conflicts() {
+                println!("{conflict:?}");
             }
-        } else {
-            panic!("Can't to_vec: {self:?}");
         }
-        result
-    }
-}
-
-impl Rule {
-    fn is_simple(&self) -> bool {
-        !self.rhs.is_compound()
-    }
-
-    fn split(&self) -> Vec<Rule> {
-        let mut result = vec![];
-
-        for seq in self.rhs.alt_of_seqs() {
-            let SymbolExpr::Seq(es) = &seq else { unreachable!() };
-            let es: Vec<SymbolExpr> = es.iter().cloned().map(|e| {
-                if e.is_compound() {
-                    let new_e = format!("<{e:?}>");
-                    result.extend(e.definition());
-                    SymbolExpr::Nonterm(new_e)
-                } else {
-                    e
+        Command::Parse { file, raw, max_depth, max_tokens, max_steps, timeout } => {
+            let source = std::fs::read_to_string(file)?;
+            let mut input = metagrammar::firrtl::FirrtlTokens::new(grammar, &source);
+            let mut limits = lr0::Limits::new();
+            if let Some(depth) = max_depth {
+                limits = limits.max_depth(*depth);
+            }
+            if let Some(tokens) = max_tokens {
+                limits = limits.max_tokens(*tokens);
+            }
+            if let Some(steps) = max_steps {
+                limits = limits.max_steps(*steps);
+            }
+            if let Some(timeout) = timeout {
+                limits = limits.time_budget(std::time::Duration::from_millis(*timeout), 1024);
+            }
+            let mut machine = lr0::Machine::with_limits(&table, limits);
+            machine.run(&mut input).map_err(|e| e.to_string())?;
+            let tree = machine.tree().unwrap();
+            if *raw {
+                println!("{tree:?}");
+            } else {
+                println!("{:?}", metagrammar::tree::TreeBuilder::new(&ebnf).build(tree));
+            }
+        }
+        Command::Replay { file } => {
+            let events = parsing::trace::read(std::io::BufReader::new(std::fs::File::open(file)?))?;
+            let parses = parsing::trace::parses(&events);
+            for step in parses.last().ok_or("the trace has no parse steps")? {
+                if step.state >= table.states.len() {
+                    return Err(format!("step {} is in state {}, which the table does not have", step.step, step.state).into());
                 }
-            }).collect();
-            let rule = Rule {
-                lhs: self.lhs.clone(),
-                rhs: SymbolExpr::Seq(es),
+                println!("Step {}: state {}, on {} => {}, depth {}", step.step, step.state, step.lookahead, step.action, step.depth);
+                for line in format!("{:?}", table.state(step.state)).lines() {
+                    println!("    {line}");
+                }
+            }
+        }
+        Command::Fmt { .. } | Command::Test { .. } | Command::Corpus { .. } => unreachable!(),
+        Command::Export { format, output } => {
+            let bytes = match format {
+                Format::Json => table.to_json().into_bytes(),
+                Format::Binary => table.to_bytes(),
             };
-            result.push(rule);
+            match output {
+                Some(path) => std::fs::write(path, bytes)?,
+                None => std::io::Write::write_all(&mut std::io::stdout(), &bytes)?,
+            }
         }
-
-        result
     }
+    Ok(())
 }
 
-pub type Symbol = String;
-
-fn pos_to_line(s: &str, pos: usize) -> usize {
-    let mut line = 1;
-    for ch in s.chars().take(pos) {
-        if ch == '\n' {
-            line += 1;
-        }
+fn fmt(path: &Path, check: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let source = std::fs::read_to_string(path)?;
+    let formatted = metagrammar::format::format(&path.display().to_string(), &source)?;
+    if formatted == source {
+        return Ok(());
     }
-    line
+    if !check {
+        std::fs::write(path, formatted)?;
+        return Ok(());
+    }
+
+    let line = source.lines().zip(formatted.lines()).take_while(|(a, b)| a == b).count() + 1;
+    Err(format!("{} is not formatted, starting at line {line}; run `metagrammar fmt` to fix it", path.display()).into())
 }
 
-fn main() {
-    let grammar_str = include_str!("../../GRAMMAR");
-    let mut grammar_data = match GrammarParser::new().parse(&grammar_str) {
-        Ok(g) => g,
-        Err(e) => {
-            eprintln!("{e:?}");
-            match e {
-                lalrpop_util::ParseError::InvalidToken { location } => todo!(),
-                lalrpop_util::ParseError::UnrecognizedEof { location, expected } => todo!(),
-                lalrpop_util::ParseError::UnrecognizedToken { token, expected } => {
-                    let (start, token_text, end) = token;
-                    let line_start = pos_to_line(grammar_str, start);
-                    let line_end = pos_to_line(grammar_str, end);
-                    eprintln!("Line: {line_start} (until {line_end}) token = {token_text:?}");
-                }
-                lalrpop_util::ParseError::ExtraToken { token } => todo!(),
-                lalrpop_util::ParseError::User { error } => todo!(),
+fn test(files: &[PathBuf]) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut runs, mut failures) = (0, 0);
+    for file in files {
+        let suite = metagrammar::suite::Suite::load(file)?;
+        for outcome in suite.run() {
+            runs += 1;
+            if let Err(message) = outcome.result {
+                failures += 1;
+                let case = &suite.cases[outcome.case];
+                println!("{}:{}: {} failed: {message}", file.display(), case.line, outcome.backend);
             }
-            panic!();
         }
-    };
-//    dbg!(&grammar);
-//    dbg!(&grammar.rules[0]);
-//    dbg!(grammar.rules[0].rhs.alt_of_seqs());
-//    dbg!(grammar.rules[0].split());
-    grammar_data.split();
-    dbg!(&grammar_data);
-
-    let mut grammar = parsing::Grammar::new();
-
-    grammar = grammar.symbol("START");
-
-    for nonterminal in grammar_data.nonterminals() {
-        eprintln!("{nonterminal:?}");
-        grammar = grammar.symbol(nonterminal);
     }
-    eprintln!();
 
-    grammar = grammar.symbol("id");
-    grammar = grammar.symbol("newline");
-    grammar = grammar.symbol("indent");
-    grammar = grammar.symbol("dedent");
-    grammar = grammar.symbol(r#"".""#);
-    grammar = grammar.symbol(r#"",""#);
-    grammar = grammar.symbol(r#""(""#);
-    grammar = grammar.symbol(r#"")""#);
-    grammar = grammar.symbol(r#""<""#);
-    grammar = grammar.symbol(r#"">""#);
-    grammar = grammar.symbol(r#""{|""#);
-    grammar = grammar.symbol(r#""|}""#);
-    grammar = grammar.symbol(r#""[""#);
-    grammar = grammar.symbol(r#""]""#);
-    grammar = grammar.symbol(r#""{""#);
-    grammar = grammar.symbol(r#""}""#);
-    grammar = grammar.symbol(r#"":""#);
-    grammar = grammar.symbol(r#""=""#);
-    grammar = grammar.symbol(r#""=>""#);
-    grammar = grammar.symbol(r#""<=""#);
-    grammar = grammar.symbol(r#""data-type""#);
-    grammar = grammar.symbol(r#""read-latency""#);
-    grammar = grammar.symbol(r#""write-latency""#);
-    grammar = grammar.symbol(r#""read-under-write""#);
-    grammar = grammar.symbol(r#""readwriter""#);
-    grammar = grammar.symbol(r#""writer""#);
-    grammar = grammar.symbol(r#""reader""#);
-    grammar = grammar.symbol(r#""formal""#);
-    grammar = grammar.symbol(r#""layer""#);
-    grammar = grammar.symbol(r#""attach""#);
-    grammar = grammar.symbol(r#""depth""#);
-    grammar = grammar.symbol(r#""invalidate""#);
-    grammar = grammar.symbol(r#""invalid""#);
-    grammar = grammar.symbol(r#""connect""#);
-    grammar = grammar.symbol(r#""undefined""#);
-    grammar = grammar.symbol(r#""new""#);
-    grammar = grammar.symbol(r#""old""#);
-    grammar = grammar.symbol(r#""mem""#);
-    grammar = grammar.symbol(r#""enablelayer""#);
-    grammar = grammar.symbol(r#""Probe""#);
-    grammar = grammar.symbol(r#""RWProbe""#);
-    grammar = grammar.symbol(r#""flip""#);
-    grammar = grammar.symbol(r#""UInt""#);
-    grammar = grammar.symbol(r#""SInt""#);
-    grammar = grammar.symbol(r#""Analog""#);
-    grammar = grammar.symbol(r#""Clock""#);
-    grammar = grammar.symbol(r#""Reset""#);
-    grammar = grammar.symbol(r#""AsyncReset""#);
-    grammar = grammar.symbol(r#""Integer""#);
-    grammar = grammar.symbol(r#""String""#);
-    grammar = grammar.symbol(r#""List""#);
-    grammar = grammar.symbol(r#""probe""#);
-    grammar = grammar.symbol(r#""rwprobe""#);
-    grammar = grammar.symbol(r#""read""#);
-    grammar = grammar.symbol(r#""force""#);
-    grammar = grammar.symbol(r#""force_initial""#);
-    grammar = grammar.symbol(r#""release""#);
-    grammar = grammar.symbol(r#""release_initial""#);
-    grammar = grammar.symbol(r#""mux""#);
-    grammar = grammar.symbol(r#""read""#);
-    grammar = grammar.symbol(r#""stop""#);
-    grammar = grammar.symbol(r#""assert""#);
-    grammar = grammar.symbol(r#""printf""#);
-    grammar = grammar.symbol(r#""fprintf""#);
-    grammar = grammar.symbol(r#""fflush""#);
-    grammar = grammar.symbol(r#""const""#);
-    grammar = grammar.symbol(r#""intrinsic""#);
-    grammar = grammar.symbol(r#""skip""#);
-    grammar = grammar.symbol(r#""layerblock""#);
-    grammar = grammar.symbol(r#""module""#);
-    grammar = grammar.symbol(r#""parameter""#);
-    grammar = grammar.symbol(r#""defname""#);
-    grammar = grammar.symbol(r#""extmodule""#);
-    grammar = grammar.symbol(r#""of""#);
-    grammar = grammar.symbol(r#""public""#);
-    grammar = grammar.symbol(r#""type""#);
-    grammar = grammar.symbol(r#""inst""#);
-    grammar = grammar.symbol(r#""wire""#);
-    grammar = grammar.symbol(r#""reg""#);
-    grammar = grammar.symbol(r#""bits""#);
-    grammar = grammar.symbol(r#""regreset""#);
-    grammar = grammar.symbol(r#""node""#);
-    grammar = grammar.symbol(r#""is""#);
-    grammar = grammar.symbol(r#""input""#);
-    grammar = grammar.symbol(r#""output""#);
-    grammar = grammar.symbol(r#""cover""#);
-    grammar = grammar.symbol(r#""assume""#);
-    grammar = grammar.symbol(r#""match""#);
-    grammar = grammar.symbol(r#""else""#);
-    grammar = grammar.symbol(r#""when""#);
-    grammar = grammar.symbol(r#""propassign""#);
-    grammar = grammar.symbol(r#""define""#);
-    grammar = grammar.symbol(r#""circuit""#);
-    grammar = grammar.symbol("property_primop_varexpr_keyword");
-    grammar = grammar.symbol("property_primop_2expr_keyword");
-    grammar = grammar.symbol("primop_1expr2int_keyword");
-    grammar = grammar.symbol("primop_1expr1int_keyword");
-    grammar = grammar.symbol("primop_1expr_keyword");
-    grammar = grammar.symbol("primop_2expr_keyword");
-    grammar = grammar.symbol("type_constable");
-    grammar = grammar.symbol("int");
-    grammar = grammar.symbol("info");
-    grammar = grammar.symbol("string_dq");
-    grammar = grammar.symbol("string_sq");
-    grammar = grammar.symbol("string");
-    grammar = grammar.symbol("version");
-    grammar = grammar.symbol("annotations");
-    grammar = grammar.symbol(r#""neg""#);
-    grammar = grammar.symbol(r#""not""#);
-    grammar = grammar.symbol(r#""andr""#);
-    grammar = grammar.symbol(r#""orr""#);
-    grammar = grammar.symbol(r#""xorr""#);
-    grammar = grammar.symbol(r#""add""#);
-    grammar = grammar.symbol(r#""sub""#);
-    grammar = grammar.symbol(r#""mul""#);
-    grammar = grammar.symbol(r#""div""#);
-    grammar = grammar.symbol(r#""rem""#);
-    grammar = grammar.symbol(r#""lt""#);
-    grammar = grammar.symbol(r#""leq""#);
-    grammar = grammar.symbol(r#""gt""#);
-    grammar = grammar.symbol(r#""geq""#);
-    grammar = grammar.symbol(r#""eq""#);
-    grammar = grammar.symbol(r#""neq""#);
-    grammar = grammar.symbol(r#""dshl""#);
-    grammar = grammar.symbol(r#""dshr""#);
-    grammar = grammar.symbol(r#""and""#);
-    grammar = grammar.symbol(r#""or""#);
-    grammar = grammar.symbol(r#""xor""#);
-    grammar = grammar.symbol(r#""cat""#);
-    grammar = grammar.symbol(r#""asUInt""#);
-    grammar = grammar.symbol(r#""asSInt""#);
-    grammar = grammar.symbol(r#""asClock""#);
-    grammar = grammar.symbol(r#""asAsyncReset""#);
-    grammar = grammar.symbol(r#""cvt""#);
-    grammar = grammar.symbol(r#""intrinsic""#);
-    grammar = grammar.symbol(r#""pad""#);
-    grammar = grammar.symbol(r#""shl""#);
-    grammar = grammar.symbol(r#""shr""#);
-    grammar = grammar.symbol(r#""head""#);
-    grammar = grammar.symbol(r#""tail""#);
-    grammar = grammar.symbol(r#""cmem""#);
-    grammar = grammar.symbol(r#""infer""#);
-    grammar = grammar.symbol(r#""mport""#);
-    grammar = grammar.symbol(r#""Probe""#);
-    grammar = grammar.symbol(r#""RWProbe""#);
-
-    grammar = grammar.rule("START", &["circuit"]);
-
-    for rule in &grammar_data.rules {
-//        dbg!(&rule);
-//        assert!(rule.is_simple());
-        grammar = grammar.rule(&rule.lhs.clone(), &rule.rhs.to_vec());
+    println!("{} of {runs} runs passed", runs - failures);
+    if failures > 0 {
+        return Err(format!("{failures} runs failed").into());
     }
+    Ok(())
+}
 
-    let grammar = grammar.build();
-
-    eprintln!("GRAMMAR:");
-    eprintln!("{grammar:?}");
-    eprintln!();
-
-    dbg!(grammar.symbol("decl").unwrap().firsts());
-
-    let table = lr0::ParseTable::new(&grammar);
-
-    for conflict in table.conflicts() {
-        eprintln!("CONFLICT: {conflict:?}");
+fn corpus(grammar: &Path, start: &str, file: &Path, snapshot: &Path, update: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut grammar = metagrammar::parse_file(grammar)?;
+    grammar.split();
+    let table = lr0::ParseTable::new(grammar.to_parsing_grammar(start)?);
+
+    let report = metagrammar::corpus::run(&table, &std::fs::read_to_string(file)?).to_string();
+    print!("{report}");
+    if update {
+        std::fs::write(snapshot, report)?;
+    } else if std::fs::read_to_string(snapshot)? != report {
+        return Err(format!("the report differs from {}; run with --update to accept it", snapshot.display()).into());
     }
+    Ok(())
+}
 
-    let mut machine = lr0::Machine::new(&table);
-
-    let source = std::fs::read_to_string(&std::env::args().skip(1).next().unwrap()).unwrap();
-
-    let lex = tokenizer::FirrtlLexer::new(&source);
-    let mut input = massage_tokens(&grammar, lex.into_iter());
-    machine.run(&mut input);
+fn load_table(cli: &Cli) -> Result<(metagrammar::Grammar, lr0::ParseTable), Box<dyn std::error::Error>> {
+    let mut grammar = metagrammar::parse_file(&cli.grammar)?;
+    grammar.split();
 
-    eprintln!("DONE");
+    let table = lr0::ParseTable::new(grammar.to_parsing_grammar(cli.start.as_deref().unwrap_or("circuit"))?);
+    Ok((grammar, table))
 }
 
-fn massage_tokens<'a>(grammar: &'a parsing::Grammar, lex: FirrtlLexer) -> impl Iterator<Item=parsing::Symbol<'a>> {
-    lex
-        .into_iter()
-        .map(|token| {
-            let token = token.unwrap();
-            let s = match token {
-                tokenizer::Token::Lex(lex_token) => {
-                    match lex_token {
-                        tokenizer::LexToken::Version => "version",
-                        tokenizer::LexToken::KwCircuit => r#""circuit""#,
-                        tokenizer::LexToken::Id => "id",
-                        tokenizer::LexToken::Int => "int",
-                        tokenizer::LexToken::Info => "info",
-                        tokenizer::LexToken::CurlyLeft => r#""{""#,
-                        tokenizer::LexToken::CurlyRight => r#""}""#,
-                        tokenizer::LexToken::BracketLeft => r#""[""#,
-                        tokenizer::LexToken::BracketRight => r#""]""#,
-                        tokenizer::LexToken::ParenLeft => r#""(""#,
-                        tokenizer::LexToken::ParenRight => r#"")""#,
-                        tokenizer::LexToken::AngLeft => r#""<""#,
-                        tokenizer::LexToken::AngRight => r#"">""#,
-                        tokenizer::LexToken::Comma => r#"",""#,
-                        tokenizer::LexToken::Colon => r#"":""#,
-                        tokenizer::LexToken::Eq => r#""=""#,
-                        tokenizer::LexToken::RevFatArrow => r#""<=""#,
-                        tokenizer::LexToken::Dot => r#"".""#,
-                        tokenizer::LexToken::String => "string",
-                        tokenizer::LexToken::KwModule => r#""module""#,
-                        tokenizer::LexToken::KwSkip => r#""skip""#,
-                        tokenizer::LexToken::KwInput => r#""input""#,
-                        tokenizer::LexToken::KwOutput => r#""output""#,
-                        tokenizer::LexToken::Comment => unreachable!(),
-                        tokenizer::LexToken::Newline(_) => unreachable!(),
-                        tokenizer::LexToken::KwUInt => r#""UInt""#,
-                        tokenizer::LexToken::KwSInt => r#""SInt""#,
-                        tokenizer::LexToken::KwClock => r#""Clock""#,
-                        tokenizer::LexToken::KwReset => r#""Reset""#,
-                        tokenizer::LexToken::KwFlip => r#""flip""#,
-                        tokenizer::LexToken::KwWire => r#""wire""#,
-                        tokenizer::LexToken::KwReg => r#""reg""#,
-                        tokenizer::LexToken::KwBits => r#""bits""#,
-                        tokenizer::LexToken::KwRegReset => r#""regreset""#,
-                        tokenizer::LexToken::KwNode => r#""node""#,
-                        tokenizer::LexToken::KwIs => r#""is""#,
-                        tokenizer::LexToken::KwInvalid => r#""invalid""#,
-                        tokenizer::LexToken::KwInvalidate => r#""invalidate""#,
-                        tokenizer::LexToken::KwAsClock => r#""asClock""#,
-                        tokenizer::LexToken::KwasUInt => r#""asUInt""#,
-                        tokenizer::LexToken::KwAsSInt => r#""asSInt""#,
-                        tokenizer::LexToken::KwAsClock => r#""asClock""#,
-                        tokenizer::LexToken::KwAsAsyncReset => r#""asAsyncReset""#,
-                        tokenizer::LexToken::KwCvt => r#""cvt""#,
-                        tokenizer::LexToken::KwAsAsyncReset => r#""asAsyncReset""#,
-                        tokenizer::LexToken::KwInst => r#""inst""#,
-                        tokenizer::LexToken::KwOf => r#""of""#,
-                        tokenizer::LexToken::KwExtModule => r#""extmodule""#,
-                        tokenizer::LexToken::KwDefName => r#""defname""#,
-                        tokenizer::LexToken::KwDataType => r#""data-type""#,
-                        tokenizer::LexToken::KwReadLatency => r#""read-latency""#,
-                        tokenizer::LexToken::KwWriteLatency => r#""write-latency""#,
-                        tokenizer::LexToken::KwLayer => r#""layer""#,
-                        tokenizer::LexToken::KwLayerBlock => r#""layerblock""#,
-                        tokenizer::LexToken::KwConnect => r#""connect""#,
-                        tokenizer::LexToken::KwWhen => r#""when""#,
-                        tokenizer::LexToken::KwElse => r#""else""#,
-                        tokenizer::LexToken::KwAdd => r#""add""#,
-                        tokenizer::LexToken::KwSub => r#""sub""#,
-                        tokenizer::LexToken::KwMul => r#""mul""#,
-                        tokenizer::LexToken::KwDiv => r#""div""#,
-                        tokenizer::LexToken::KwRem => r#""rem""#,
-                        tokenizer::LexToken::KwLt => r#""lt""#,
-                        tokenizer::LexToken::KwLeq => r#""leq""#,
-                        tokenizer::LexToken::KwGt => r#""gt""#,
-                        tokenizer::LexToken::KwGeq => r#""geq""#,
-                        tokenizer::LexToken::KwEq => r#""eq""#,
-                        tokenizer::LexToken::KwNeq => r#""neq""#,
-                        tokenizer::LexToken::KwDshl => r#""dshl""#,
-                        tokenizer::LexToken::KwDshr => r#""dshr""#,
-                        tokenizer::LexToken::KwAnd => r#""and""#,
-                        tokenizer::LexToken::KwOr => r#""or""#,
-                        tokenizer::LexToken::KwXor => r#""xor""#,
-                        tokenizer::LexToken::KwCat => r#""cat""#,
-                        tokenizer::LexToken::KwNeg => r#""neg""#,
-                        tokenizer::LexToken::KwNot => r#""not""#,
-                        tokenizer::LexToken::KwAndr => r#""andr""#,
-                        tokenizer::LexToken::KwOrr => r#""orr""#,
-                        tokenizer::LexToken::KwXorr => r#""xorr""#,
-                        tokenizer::LexToken::KwIntrinsic => r#""intrinsic""#,
-                        tokenizer::LexToken::KwPad => r#""pad""#,
-                        tokenizer::LexToken::KwShl => r#""shl""#,
-                        tokenizer::LexToken::KwShr => r#""shr""#,
-                        tokenizer::LexToken::KwHead => r#""head""#,
-                        tokenizer::LexToken::KwTail => r#""tail""#,
-                        tokenizer::LexToken::KwMux => r#""mux""#,
-                        tokenizer::LexToken::KwRead => r#""read""#,
-                        tokenizer::LexToken::KwCmem => r#""cmem""#,
-                        tokenizer::LexToken::KwInfer => r#""infer""#,
-                        tokenizer::LexToken::KwMport => r#""mport""#,
-                        tokenizer::LexToken::KwProbe2 => r#""Probe""#,
-                        tokenizer::LexToken::KwRwProbe2 => r#""RWProbe""#,
-                        tokenizer::LexToken::KwDefine => r#""define""#,
-                        tokenizer::LexToken::KwProbe => r#""probe""#,
-                        tokenizer::LexToken::KwRwProbe => r#""rwprobe""#,
-                    }
-                },
-                tokenizer::Token::Newline => "newline",
-                tokenizer::Token::Indent => "indent",
-                tokenizer::Token::Dedent => "dedent",
-            };
-            grammar.symbol(s).expect(&format!("Could not find symbol {s:?}"))
+fn format_actions(table: &lr0::ParseTable, actions: &[lr0::Action]) -> String {
+    let actions: Vec<String> = actions
+        .iter()
+        .map(|action| match action {
+            lr0::Action::Reduce(rule) => format!("Reduce({:?})", table.grammar().rule_at(*rule)),
+            action => format!("{action:?}"),
         })
+        .collect();
+    actions.join(", ")
 }
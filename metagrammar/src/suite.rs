@@ -0,0 +1,349 @@
+//! Grammar test files: a grammar, followed by inputs it should accept, reject, or parse into a given tree.
+//!
+//! ```text
+//! === grammar start=statement lexer=firrtl
+//! import "../../GRAMMAR" ;
+//!
+//! === accept
+//! skip
+//!
+//! === reject
+//! skip skip
+//!
+//! === tree: (stmt_node "node" "x" "=" (expr_reference "y"))
+//! node x = y
+//! ```
+//!
+//! Each section starts with a `===` line.
+//! The first is the grammar, in the EBNF of grammar files, whose imports are read relative to the test file.
+//! It may name the start symbol, which otherwise is the first rule's,
+//! and the lexer: `firrtl`, or `words` (the default), which splits the input at whitespace
+//! and lexes each word as the quoted literal for it, the terminal named after it, or else `id`.
+//!
+//! Every other section is a case, whose input is the lines up to the next section,
+//! with trailing blank lines dropped and a newline at the end.
+//! A `tree:` case compares the tree shaped by the grammar's annotations, printed as by `SyntaxNode`'s `Debug`,
+//! against the s-expression, which may start on the next line, and continue until its parentheses balance.
+//!
+//! Every case is run through every `Backend`.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use parsing::Token;
+use parsing::lr0::{Machine, Node, ParseTable};
+use parsing::{earley, sppf};
+
+use crate::tree::TreeBuilder;
+use crate::*;
+
+/// A way of parsing a grammar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Lr0,
+    Slr,
+    Lalr,
+    Earley,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 4] = [Backend::Lr0, Backend::Slr, Backend::Lalr, Backend::Earley];
+
+    /// The parse table for this backend, if it uses one.
+    pub fn table(self, grammar: impl Into<Arc<parsing::Grammar>>) -> Option<ParseTable> {
+        match self {
+            Backend::Lr0 => Some(ParseTable::new(grammar)),
+            Backend::Slr => Some(ParseTable::slr(grammar)),
+            Backend::Lalr => Some(ParseTable::lalr(grammar)),
+            Backend::Earley => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::Lr0 => write!(f, "LR(0)"),
+            Backend::Slr => write!(f, "SLR"),
+            Backend::Lalr => write!(f, "LALR"),
+            Backend::Earley => write!(f, "Earley"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lexer {
+    Firrtl,
+    Words,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expect {
+    Accept,
+    Reject,
+    /// The s-expression of the tree, normalized by `normalize`.
+    Tree(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    /// The line of the case's `===`, counting from 1.
+    pub line: usize,
+    pub expect: Expect,
+    pub input: String,
+}
+
+/// A loaded test file.
+#[derive(Debug)]
+pub struct Suite {
+    pub path: PathBuf,
+    pub start: Symbol,
+    pub lexer: Lexer,
+    pub cases: Vec<Case>,
+    ebnf: Grammar,
+    grammar: Arc<parsing::Grammar>,
+}
+
+/// The result of running one case through one backend. `Err` holds why the case failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    /// The index of the case in `Suite::cases`.
+    pub case: usize,
+    pub backend: Backend,
+    pub result: Result<(), String>,
+}
+
+impl Suite {
+    pub fn load(path: impl AsRef<Path>) -> Result<Suite, Error> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| Error::Io {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        })?;
+        Suite::parse(path, &text)
+    }
+
+    /// Parse the text of the test file at `path`.
+    pub fn parse(path: &Path, text: &str) -> Result<Suite, Error> {
+        let file = path.display().to_string();
+        let invalid = |line: usize, message: String| Error::Suite {
+            file: file.clone(),
+            line,
+            message,
+        };
+
+        let lines: Vec<&str> = text.lines().collect();
+        let headers: Vec<usize> = (0..lines.len()).filter(|i| lines[*i].starts_with("===")).collect();
+        let Some(&first) = headers.first() else {
+            return Err(invalid(1, "expected a `=== grammar` section".to_string()));
+        };
+
+        let mut options = lines[first].trim_start_matches('=').split_whitespace();
+        if options.next() != Some("grammar") {
+            return Err(invalid(first + 1, "the first section must be `=== grammar`".to_string()));
+        }
+        let mut start = None;
+        let mut lexer = Lexer::Words;
+        for option in options {
+            match option.split_once('=') {
+                Some(("start", name)) => start = Some(name.to_string()),
+                Some(("lexer", "firrtl")) => lexer = Lexer::Firrtl,
+                Some(("lexer", "words")) => lexer = Lexer::Words,
+                _ => return Err(invalid(first + 1, format!("unknown option `{option}`"))),
+            }
+        }
+
+        // The grammar is read from the test file, with every line outside its section blanked,
+        // so that errors in it point at the right line.
+        let end = headers.get(1).copied().unwrap_or(lines.len());
+        let source: String = lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| if first < i && i < end { format!("{line}\n") } else { "\n".to_string() })
+            .collect();
+        let mut ebnf = crate::parse_named(&file, &source)?;
+
+        let start = match start {
+            Some(start) => start,
+            None => match ebnf.rules().first() {
+                Some(rule) => rule.lhs.clone(),
+                None => return Err(invalid(first + 1, "the grammar has no rules".to_string())),
+            },
+        };
+        ebnf.split();
+        let grammar = Arc::new(ebnf.to_parsing_grammar(&start)?);
+
+        let mut cases = vec![];
+        for (n, &header) in headers.iter().enumerate().skip(1) {
+            let end = headers.get(n + 1).copied().unwrap_or(lines.len());
+            let line = lines[header].trim_start_matches('=').trim();
+            let (expect, mut body) = match line {
+                "accept" => (Expect::Accept, header + 1),
+                "reject" => (Expect::Reject, header + 1),
+                _ if line.starts_with("tree:") => {
+                    let mut sexpr = line["tree:".len()..].to_string();
+                    let mut next = header + 1;
+                    while (sexpr.trim().is_empty() || depth(&sexpr) > 0) && next < end {
+                        sexpr.push(' ');
+                        sexpr.push_str(lines[next]);
+                        next += 1;
+                    }
+                    if sexpr.trim().is_empty() || depth(&sexpr) != 0 {
+                        return Err(invalid(header + 1, "the tree is missing, or its parentheses do not balance".to_string()));
+                    }
+                    (Expect::Tree(normalize(&sexpr)), next)
+                }
+                _ => return Err(invalid(header + 1, format!("expected `accept`, `reject`, or `tree:`, found `{line}`"))),
+            };
+
+            let mut input = String::new();
+            while body < end {
+                input.push_str(lines[body]);
+                input.push('\n');
+                body += 1;
+            }
+            let input = format!("{}\n", input.trim_end_matches('\n'));
+            cases.push(Case {
+                line: header + 1,
+                expect,
+                input,
+            });
+        }
+
+        Ok(Suite {
+            path: path.to_path_buf(),
+            start,
+            lexer,
+            cases,
+            ebnf,
+            grammar,
+        })
+    }
+
+    /// Run every case through every backend.
+    pub fn run(&self) -> Vec<Outcome> {
+        let mut outcomes = vec![];
+        for backend in Backend::ALL {
+            let table = backend.table(self.grammar.clone());
+            for (index, case) in self.cases.iter().enumerate() {
+                outcomes.push(Outcome {
+                    case: index,
+                    backend,
+                    result: self.run_case(case, table.as_ref()),
+                });
+            }
+        }
+        outcomes
+    }
+
+    /// Run one case, through the machine for `table` if there is one, or else through the Earley parser.
+    fn run_case(&self, case: &Case, table: Option<&ParseTable>) -> Result<(), String> {
+        let tokens = self.lex(&case.input)?;
+        let parsed = match table {
+            Some(table) => {
+                let mut machine = Machine::new(table);
+                machine
+                    .run(&mut tokens.iter().cloned())
+                    .map(|()| machine.tree().unwrap().clone())
+                    .map_err(|e| e.to_string())
+            }
+            None => self.parse_earley(&tokens, matches!(case.expect, Expect::Tree(_))),
+        };
+
+        match (&case.expect, parsed) {
+            (Expect::Accept, Ok(_)) | (Expect::Reject, Err(_)) => Ok(()),
+            (Expect::Reject, Ok(_)) => Err("accepted".to_string()),
+            (_, Err(e)) => Err(e),
+            (Expect::Tree(expected), Ok(tree)) => {
+                let actual = format!("{:?}", TreeBuilder::new(&self.ebnf).build(&tree));
+                if normalize(&actual) == *expected {
+                    Ok(())
+                } else {
+                    Err(format!("expected {expected}\n       got {actual}"))
+                }
+            }
+        }
+    }
+
+    /// Parse with the Earley parser. If `unambiguous`, more than one tree is an error.
+    fn parse_earley<'g, 's>(&'g self, tokens: &[Token<'g, 's>], unambiguous: bool) -> Result<Node<'g, 's>, String> {
+        let terminals: Vec<_> = tokens.iter().map(|token| token.terminal).collect();
+        let (forest, root) = earley::parse(&self.grammar, &terminals).map_err(|e| e.to_string())?;
+        match forest.count_derivations(root) {
+            Some(1) => {}
+            _ if !unambiguous => {}
+            Some(count) => return Err(format!("ambiguous, with {count} trees")),
+            None => return Err("ambiguous, with infinitely many trees, or too many to count".to_string()),
+        }
+        let tree = forest.pick(root, &sppf::Disambiguator::new()).ok_or("no tree")?;
+        Ok(tree.to_node(tokens))
+    }
+
+    fn lex<'s>(&self, input: &'s str) -> Result<Vec<Token<'_, 's>>, String> {
+        let grammar = self.grammar.as_ref();
+        match self.lexer {
+            Lexer::Firrtl => firrtl::tokenize(grammar, input).map_err(|e| e.to_string()),
+            Lexer::Words => {
+                let mut tokens = vec![];
+                for word in input.split_whitespace() {
+                    let start = word.as_ptr() as usize - input.as_ptr() as usize;
+                    let terminal = [format!("\"{word}\""), word.to_string(), "id".to_string()]
+                        .iter()
+                        .find_map(|name| grammar.symbol(name).filter(|symbol| symbol.is_terminal()))
+                        .ok_or_else(|| format!("no terminal for `{word}`"))?;
+                    tokens.push(Token::new(terminal, word, start..start + word.len()));
+                }
+                Ok(tokens)
+            }
+        }
+    }
+}
+
+/// How many more `(` than `)` there are outside string literals.
+fn depth(sexpr: &str) -> isize {
+    let mut depth = 0;
+    let mut chars = sexpr.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '"' => skip_string(&mut chars, &mut String::new()),
+            _ => {}
+        }
+    }
+    depth
+}
+
+/// Consume the rest of a string literal after its opening quote, appending it to `out`.
+fn skip_string(chars: &mut std::str::Chars, out: &mut String) {
+    while let Some(c) = chars.next() {
+        out.push(c);
+        match c {
+            '\\' => out.extend(chars.next()),
+            '"' => return,
+            _ => {}
+        }
+    }
+}
+
+/// Respace an s-expression: one space between things, and none inside parentheses.
+pub fn normalize(sexpr: &str) -> String {
+    let mut out = String::new();
+    let mut space = false;
+    let mut chars = sexpr.chars();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if space && c != ')' && !out.is_empty() && !out.ends_with('(') {
+            out.push(' ');
+        }
+        space = false;
+        out.push(c);
+        if c == '"' {
+            skip_string(&mut chars, &mut out);
+        }
+    }
+    out
+}
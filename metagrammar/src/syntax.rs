@@ -0,0 +1,156 @@
+use parsing::lr0::Node;
+
+use crate::diagnostic::UserError;
+use crate::*;
+
+mod parser;
+
+pub use parser::{grammar, table};
+pub(crate) use parser::parse_tree;
+
+/// Parse the text of one grammar file. `file` is only used to report errors.
+pub(crate) fn parse(file: &str, source: &str) -> Result<File, Box<SyntaxError>> {
+    let (items, _comments) = parse_tree(file, source)?;
+    build_file(&items).map_err(|(start, end, message)| {
+        Box::new(SyntaxError::new(file, source, start..end, message.to_string(), vec![]))
+    })
+}
+
+// The semantic actions, which turn the parse tree into a `File`.
+
+/// The symbols on the right-hand side of the rule `node` was reduced by.
+fn shape<'n>(node: &'n Node) -> Vec<&'n str> {
+    node.children.iter().map(|child| child.symbol.as_str()).collect()
+}
+
+/// The elements of a left-recursive list, such as `Items` or `Rhs`, with any separators dropped.
+pub(crate) fn list<'n, 'a, 's>(node: &'n Node<'a, 's>) -> Vec<&'n Node<'a, 's>> {
+    match node.children.as_slice() {
+        [] => vec![],
+        [element] => vec![element],
+        [init, .., element] => {
+            let mut elements = list(init);
+            elements.push(element);
+            elements
+        }
+    }
+}
+
+fn text<'s>(node: &Node<'_, 's>) -> &'s str {
+    node.token.as_ref().unwrap().text
+}
+
+/// The text of a `name` token, or of a `Name` node.
+fn name(node: &Node) -> Symbol {
+    match &node.token {
+        Some(token) => token.text.to_string(),
+        None => name(&node.children[0]),
+    }
+}
+
+fn build_file(items: &Node) -> Result<File, UserError> {
+    let mut file = File::default();
+    for item in list(items) {
+        match build_item(item)? {
+            Item::Import(import) => file.imports.push(import),
+            Item::Decl(Decl::Token(names)) => file.tokens.extend(names),
+            Item::Decl(Decl::Recursion(r)) => file.recursion = Some(r),
+            Item::Rule(kind, rule) => file.rules.push((kind, rule)),
+        }
+    }
+    Ok(file)
+}
+
+pub(crate) fn build_item(node: &Node) -> Result<Item, UserError> {
+    let children = &node.children;
+    let item = match shape(node).as_slice() {
+        ["import", "term", ";"] | ["import", "term", "as", "name", ";"] => {
+            let path = text(&children[1]);
+            Item::Import(Import {
+                path: path[1..path.len() - 1].to_string(),
+                alias: children.get(3).map(name),
+            })
+        }
+        ["%token", "Names", ";"] => Item::Decl(Decl::Token(list(&children[1]).into_iter().map(name).collect())),
+        ["%recursion", "name", ";"] => match text(&children[1]) {
+            "left" => Item::Decl(Decl::Recursion(Recursion::Left)),
+            "right" => Item::Decl(Decl::Recursion(Recursion::Right)),
+            _ => {
+                let span = children[1].span().unwrap();
+                return Err((span.start, span.end, "expected `left` or `right` after %recursion"));
+            }
+        },
+        ["%override", ..] => build_rule(RuleKind::Override, children),
+        [.., "|=", "Rhs", ";"] => build_rule(RuleKind::Extend, children),
+        _ => build_rule(RuleKind::Define, children),
+    };
+    Ok(item)
+}
+
+/// `[%override] [RuleAnnotations] lhs (= | |=) Rhs ;`
+fn build_rule(kind: RuleKind, children: &[Node]) -> Item {
+    let [.., lhs, _, rhs, _] = children else { unreachable!() };
+    let annotations = children
+        .iter()
+        .find(|child| child.symbol.as_str() == "RuleAnnotations")
+        .map_or(vec![], |annotations| list(annotations).into_iter().map(build_annotation).collect());
+    Item::Rule(kind, Rule {
+        lhs: name(lhs),
+        rhs: build_rhs(rhs),
+        annotations,
+    })
+}
+
+fn build_annotation(node: &Node) -> Annotation {
+    match shape(node).as_slice() {
+        ["@inline"] => Annotation::Inline,
+        ["@node", ..] => Annotation::Node(name(&node.children[2])),
+        ["@skip"] => Annotation::Skip,
+        ["@name", ..] => Annotation::Name(name(&node.children[2])),
+        shape => unreachable!("{shape:?}"),
+    }
+}
+
+pub(crate) fn build_rhs(node: &Node) -> SymbolExpr {
+    SymbolExpr::Alt(list(node).into_iter().map(build_alt).collect())
+}
+
+pub(crate) fn build_alt(node: &Node) -> SymbolExpr {
+    SymbolExpr::Seq(list(node).into_iter().map(build_expr).collect())
+}
+
+fn build_expr(node: &Node) -> SymbolExpr {
+    match node.children.as_slice() {
+        [postfix] => build_postfix(postfix),
+        [annotations, postfix] => SymbolExpr::Annotated(
+            list(annotations).into_iter().map(build_annotation).collect(),
+            Box::new(build_postfix(postfix)),
+        ),
+        _ => unreachable!(),
+    }
+}
+
+fn build_postfix(node: &Node) -> SymbolExpr {
+    let atom = build_atom(&node.children[0]);
+    match shape(node).as_slice() {
+        ["Atom"] => atom,
+        ["Atom", "+"] => SymbolExpr::Plus(Box::new(atom)),
+        ["Atom", "?"] => SymbolExpr::Opt(Box::new(atom)),
+        shape => unreachable!("{shape:?}"),
+    }
+}
+
+fn build_atom(node: &Node) -> SymbolExpr {
+    let children = &node.children;
+    match shape(node).as_slice() {
+        ["term"] => SymbolExpr::Term(text(&children[0]).to_string()),
+        ["Name"] => SymbolExpr::Nonterm(name(&children[0])),
+        ["[", "Alt", "]"] => SymbolExpr::Opt(Box::new(build_alt(&children[1]))),
+        ["{", "Alt", "}"] => SymbolExpr::Star(Box::new(build_alt(&children[1]))),
+        ["(", "Rhs", ")"] => SymbolExpr::Group(Box::new(build_rhs(&children[1]))),
+        ["sep", "(", "Expr", ",", "Expr", ")"] => {
+            SymbolExpr::Sep(Box::new(build_expr(&children[2])), Box::new(build_expr(&children[4])))
+        }
+        shape => unreachable!("{shape:?}"),
+    }
+}
@@ -0,0 +1,211 @@
+//! Parsing grammar files into trees.
+//! This uses nothing else from the crate but `diagnostic`, so the build script can include it too.
+
+use std::sync::OnceLock;
+
+use parsing::lr0::{Machine, Node, ParseTable};
+use parsing::{Span, Token};
+
+use crate::diagnostic::SyntaxError;
+
+const NONTERMINALS: &[&str] = &[
+    "START",
+    "Items",
+    "Item",
+    "RuleAnnotations",
+    "RuleAnnotation",
+    "SymbolAnnotations",
+    "SymbolAnnotation",
+    "Names",
+    "Rhs",
+    "Alt",
+    "Expr",
+    "Postfix",
+    "Atom",
+    "Name",
+];
+
+/// `name`, `qname` (a name qualified by a namespace), and `term` (a quoted terminal) are lexed by pattern.
+/// The rest are spelled as they are named.
+const TERMINALS: &[&str] = &[
+    "name", "qname", "term", "import", "as", "sep", "%token", "%recursion", "%override", "@inline", "@node", "@skip",
+    "@name", "=", "|=", ";", ",", "|", "(", ")", "[", "]", "{", "}", "+", "?",
+];
+
+/// The grammar of grammar files.
+///
+/// Lists are left-recursive and every state the machine can reduce in is free of reduce/reduce conflicts,
+/// so the LR(0) machine, which prefers to shift, parses it deterministically.
+const RULES: &[(&str, &[&str])] = &[
+    ("START", &["Items"]),
+    ("Items", &[]),
+    ("Items", &["Items", "Item"]),
+    ("Item", &["import", "term", ";"]),
+    ("Item", &["import", "term", "as", "name", ";"]),
+    ("Item", &["%token", "Names", ";"]),
+    ("Item", &["%recursion", "name", ";"]),
+    ("Item", &["name", "=", "Rhs", ";"]),
+    ("Item", &["RuleAnnotations", "name", "=", "Rhs", ";"]),
+    ("Item", &["%override", "Name", "=", "Rhs", ";"]),
+    ("Item", &["%override", "RuleAnnotations", "Name", "=", "Rhs", ";"]),
+    ("Item", &["Name", "|=", "Rhs", ";"]),
+    ("Item", &["RuleAnnotations", "Name", "|=", "Rhs", ";"]),
+    ("RuleAnnotations", &["RuleAnnotation"]),
+    ("RuleAnnotations", &["RuleAnnotations", "RuleAnnotation"]),
+    ("RuleAnnotation", &["@inline"]),
+    ("RuleAnnotation", &["@node", "(", "name", ")"]),
+    ("SymbolAnnotations", &["SymbolAnnotation"]),
+    ("SymbolAnnotations", &["SymbolAnnotations", "SymbolAnnotation"]),
+    ("SymbolAnnotation", &["@skip"]),
+    ("SymbolAnnotation", &["@name", "(", "name", ")"]),
+    ("Names", &["name"]),
+    ("Names", &["Names", "name"]),
+    ("Rhs", &["Alt"]),
+    ("Rhs", &["Rhs", "|", "Alt"]),
+    ("Alt", &["Expr"]),
+    ("Alt", &["Alt", ",", "Expr"]),
+    ("Expr", &["Postfix"]),
+    ("Expr", &["SymbolAnnotations", "Postfix"]),
+    ("Postfix", &["Atom"]),
+    ("Postfix", &["Atom", "+"]),
+    ("Postfix", &["Atom", "?"]),
+    ("Atom", &["term"]),
+    ("Atom", &["Name"]),
+    ("Atom", &["[", "Alt", "]"]),
+    ("Atom", &["{", "Alt", "}"]),
+    ("Atom", &["(", "Rhs", ")"]),
+    ("Atom", &["sep", "(", "Expr", ",", "Expr", ")"]),
+    ("Name", &["name"]),
+    ("Name", &["qname"]),
+];
+
+/// The metagrammar, as a `parsing::Grammar`.
+pub fn grammar() -> parsing::Grammar {
+    let mut builder = parsing::Grammar::new();
+    for symbol in NONTERMINALS.iter().chain(TERMINALS) {
+        builder = builder.symbol(*symbol);
+    }
+    for (lhs, rhs) in RULES {
+        builder = builder.rule(lhs, rhs);
+    }
+    builder.build()
+}
+
+/// The parse table for the metagrammar, built on first use.
+pub fn table() -> &'static ParseTable {
+    static TABLE: OnceLock<ParseTable> = OnceLock::new();
+    TABLE.get_or_init(|| ParseTable::new(grammar()))
+}
+
+/// Parse the text of one grammar file into its `Items` node, and the spans of its comments.
+pub(crate) fn parse_tree<'s>(file: &str, source: &'s str) -> Result<(Node<'static, 's>, Vec<Span>), Box<SyntaxError>> {
+    let table = table();
+    let grammar = table.grammar();
+
+    let tokens = lex(source).map_err(|start| {
+        let len = source[start..].chars().next().map_or(0, char::len_utf8);
+        Box::new(SyntaxError::new(file, source, start..start + len, "invalid token".to_string(), vec![]))
+    })?;
+
+    let (comments, tokens): (Vec<_>, Vec<_>) = tokens.into_iter().partition(|(terminal, _)| *terminal == "comment");
+
+    // Feeds tokens to `machine`, returning the index of the first it fails on.
+    let feed = |machine: &mut Machine<'static, 's>, tokens: &[(&str, Span)]| {
+        tokens.iter().position(|(terminal, span)| {
+            let token = Token::new(grammar.symbol(terminal).unwrap(), &source[span.clone()], span.clone());
+            machine.feed(token).is_err()
+        })
+    };
+    // A failed step may already have made reductions, so the machine the error is reported from
+    // is rebuilt from the tokens before it, rather than copied before every token in case of an error.
+    let expected_after = |count: usize| {
+        let mut machine = Machine::new(table);
+        feed(&mut machine, &tokens[..count]);
+        expected(&machine)
+    };
+
+    let mut machine = Machine::new(table);
+    if let Some(failed) = feed(&mut machine, &tokens) {
+        let span = tokens[failed].1.clone();
+        let message = format!("unexpected `{}`", &source[span.clone()]);
+        return Err(Box::new(SyntaxError::new(file, source, span, message, expected_after(failed))));
+    }
+    if machine.finish().is_err() {
+        let end = tokens.last().map_or(0, |(_, span)| span.end);
+        let message = "unexpected end of file".to_string();
+        return Err(Box::new(SyntaxError::new(file, source, end..end, message, expected_after(tokens.len()))));
+    }
+
+    let start = machine.tree().unwrap();
+    Ok((start.children[0].clone(), comments.into_iter().map(|(_, span)| span).collect()))
+}
+
+/// Split `source` into terminals, skipping whitespace.
+/// `// line` comments and `(* block *)` comments are kept as `comment`s, which the parser never sees.
+/// On failure, returns the position of the first character which doesn't start a token.
+fn lex(source: &str) -> Result<Vec<(&'static str, Span)>, usize> {
+    let is_name_start = |c: char| c.is_ascii_alphabetic() || c == '_';
+    let name_len = |s: &str| s.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(s.len());
+
+    let mut tokens = vec![];
+    let mut start = 0;
+    while let Some(c) = source[start..].chars().next() {
+        let rest = &source[start..];
+        let (terminal, len) = if c.is_whitespace() {
+            start += c.len_utf8();
+            continue;
+        } else if rest.starts_with("//") {
+            ("comment", rest.find('\n').unwrap_or(rest.len()))
+        } else if let Some(len) = rest.strip_prefix("(*").and_then(|comment| comment.find("*)")) {
+            ("comment", len + 4)
+        } else if c == '"' {
+            match rest[1..].find('"') {
+                Some(len) if len > 0 => ("term", len + 2),
+                _ => return Err(start),
+            }
+        } else if is_name_start(c) {
+            let len = name_len(rest);
+            match &rest[..len] {
+                "import" => ("import", len),
+                "as" => ("as", len),
+                "sep" => ("sep", len),
+                _ => match rest[len..].strip_prefix('.') {
+                    Some(local) if local.starts_with(is_name_start) => ("qname", len + 1 + name_len(local)),
+                    _ => ("name", len),
+                },
+            }
+        } else if c == '%' || c == '@' {
+            let len = 1 + name_len(&rest[1..]);
+            match TERMINALS.iter().find(|terminal| **terminal == &rest[..len]) {
+                Some(terminal) => (*terminal, len),
+                None => return Err(start),
+            }
+        } else if rest.starts_with("|=") {
+            ("|=", 2)
+        } else {
+            match TERMINALS.iter().find(|terminal| terminal.len() == 1 && terminal.starts_with(c)) {
+                Some(terminal) => (*terminal, 1),
+                None => return Err(start),
+            }
+        };
+        tokens.push((terminal, start..start + len));
+        start += len;
+    }
+    Ok(tokens)
+}
+
+/// The terminals `machine` can accept next, described for an error message.
+fn expected(machine: &Machine) -> Vec<String> {
+    let mut expected: Vec<String> = machine
+        .expected_terminals()
+        .iter()
+        .map(|terminal| match terminal.as_str() {
+            "name" | "qname" => "a name".to_string(),
+            "term" => "a quoted terminal".to_string(),
+            terminal => format!("`{terminal}`"),
+        })
+        .collect();
+    expected.sort();
+    expected.dedup();
+    expected
+}
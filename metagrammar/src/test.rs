@@ -0,0 +1,1121 @@
+use std::collections::{BTreeSet, HashMap};
+
+use super::*;
+
+/// Quoted literals are terminals without having to be declared.
+#[test]
+fn test_terminal_inference() {
+    let mut grammar = parse(r#"
+        %token id newline ;
+        %token int ;
+        stmt = "node" , id , "=" , expr , newline | "skip" , newline ;
+        expr = id | "UInt" , "(" , int , ")" ;
+    "#).unwrap();
+
+    assert_eq!(grammar.tokens(), &["id", "newline", "int"]);
+    assert_eq!(
+        grammar.terminals(),
+        vec![r#""node""#, r#""=""#, r#""skip""#, r#""UInt""#, r#""(""#, r#"")""#, "id", "newline", "int"],
+    );
+
+    grammar.split();
+    let grammar = grammar.to_parsing_grammar("stmt").unwrap();
+    for name in [r#""node""#, "id", "int"] {
+        assert!(grammar.symbol(name).unwrap().is_terminal());
+    }
+    assert!(grammar.symbol("expr").unwrap().is_nonterminal());
+}
+
+/// Literals used more than once are only declared once.
+#[test]
+fn test_terminal_inference_duplicates() {
+    let grammar = parse(r#"
+        a = "read" , "(" , b , ")" ;
+        b = "read" | "(" , ")" ;
+    "#).unwrap();
+
+    assert_eq!(grammar.terminals(), vec![r#""read""#, r#""(""#, r#"")""#]);
+}
+
+#[test]
+fn test_undefined_names() {
+    let grammar = parse(r#"
+        %token id ;
+        a = id , b , [ c ] , ( d | id ) ;
+        b = "x" ;
+    "#).unwrap();
+
+    match grammar.to_parsing_grammar("a") {
+        Err(Error::Undefined(names)) => assert_eq!(names, vec!["c", "d"]),
+        other => panic!("Expected undefined names, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_reserved_start() {
+    for source in [r#"START = "x" ; a = START ;"#, r#"%token START ; a = START ;"#] {
+        match parse(source).unwrap().to_parsing_grammar("a") {
+            Err(Error::Reserved(name)) => assert_eq!(name, "START"),
+            other => panic!("Expected START to be reserved in {source}, got {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn test_token_redefined() {
+    let grammar = parse(r#"
+        %token id ;
+        id = "x" ;
+    "#).unwrap();
+
+    assert!(matches!(grammar.check(), Err(Error::Redefined(names)) if names == vec!["id"]));
+}
+
+/// The tokenizer's literals and regexes each map to a terminal of the same spelling.
+#[test]
+fn test_firrtl_terminal_names() {
+    use tokenizer::{LexToken, Token};
+
+    assert_eq!(firrtl::terminal_name(Token::Lex(LexToken::KwAsClock)), r#""asClock""#);
+    assert_eq!(firrtl::terminal_name(Token::Lex(LexToken::KwRwProbe2)), r#""RWProbe""#);
+    assert_eq!(firrtl::terminal_name(Token::Lex(LexToken::RevFatArrow)), r#""<=""#);
+    assert_eq!(firrtl::terminal_name(Token::Lex(LexToken::Id)), "id");
+    assert_eq!(firrtl::terminal_name(Token::Lex(LexToken::Newline(4))), "newline");
+    assert_eq!(firrtl::terminal_name(Token::Indent), "indent");
+    assert!(!firrtl::LEX_TERMINALS.iter().any(|(variant, _terminal)| *variant == "Comment"));
+}
+
+/// Terminals missing from the tokenizer and tokens missing from the grammar are both reported.
+#[test]
+fn test_firrtl_unmatched_terminals() {
+    let mut grammar = parse(r#"
+        %token id newline ;
+        stmt = "skip" , newline | "frobnicate" , id , newline ;
+    "#).unwrap();
+    grammar.split();
+    let grammar = grammar.to_parsing_grammar("stmt").unwrap();
+
+    let (missing, unused) = firrtl::unmatched_terminals(&grammar);
+    assert_eq!(missing, vec![r#""frobnicate""#]);
+    assert!(unused.contains(&r#""module""#));
+    assert!(unused.contains(&"indent"));
+    assert!(!unused.contains(&r#""skip""#));
+}
+
+type Sentence = Vec<String>;
+
+/// Sentences longer than this are left out when comparing languages.
+const MAX_SENTENCE: usize = 6;
+
+fn concat(lhs: &BTreeSet<Sentence>, rhs: &BTreeSet<Sentence>) -> BTreeSet<Sentence> {
+    let mut result = BTreeSet::new();
+    for a in lhs {
+        for b in rhs {
+            if a.len() + b.len() <= MAX_SENTENCE {
+                result.insert(a.iter().chain(b).cloned().collect());
+            }
+        }
+    }
+    result
+}
+
+/// The sentences of `expr`, given the sentences found so far for each nonterminal.
+/// Names which are not nonterminals stand for themselves.
+fn expr_language(
+    expr: &SymbolExpr,
+    nonterminals: &[Symbol],
+    languages: &HashMap<Symbol, BTreeSet<Sentence>>,
+) -> BTreeSet<Sentence> {
+    let empty = BTreeSet::from([vec![]]);
+    match expr {
+        SymbolExpr::Term(s) => BTreeSet::from([vec![s.clone()]]),
+        SymbolExpr::Nonterm(s) if nonterminals.contains(s) => languages.get(s).cloned().unwrap_or_default(),
+        SymbolExpr::Nonterm(s) => BTreeSet::from([vec![s.clone()]]),
+        SymbolExpr::Seq(es) => es.iter().fold(empty, |acc, e| concat(&acc, &expr_language(e, nonterminals, languages))),
+        SymbolExpr::Alt(es) => es.iter().flat_map(|e| expr_language(e, nonterminals, languages)).collect(),
+        SymbolExpr::Group(e) | SymbolExpr::Annotated(_, e) => expr_language(e, nonterminals, languages),
+        SymbolExpr::Opt(e) => {
+            let mut result = expr_language(e, nonterminals, languages);
+            result.insert(vec![]);
+            result
+        }
+        SymbolExpr::Star(e) => {
+            let once = expr_language(e, nonterminals, languages);
+            repeat(BTreeSet::from([vec![]]), &once)
+        }
+        SymbolExpr::Plus(e) => {
+            let once = expr_language(e, nonterminals, languages);
+            repeat(once.clone(), &once)
+        }
+        SymbolExpr::Sep(e, sep) => {
+            let once = expr_language(e, nonterminals, languages);
+            let sep = expr_language(sep, nonterminals, languages);
+            repeat(once.clone(), &concat(&sep, &once))
+        }
+    }
+}
+
+/// `first`, followed by any number of `more`.
+fn repeat(first: BTreeSet<Sentence>, more: &BTreeSet<Sentence>) -> BTreeSet<Sentence> {
+    let mut result = first;
+    loop {
+        let mut next = concat(&result, more);
+        next.extend(result.iter().cloned());
+        if next == result {
+            break result;
+        }
+        result = next;
+    }
+}
+
+/// Every sentence of at most `MAX_SENTENCE` symbols derived from `start`.
+fn language(grammar: &Grammar, start: &str) -> BTreeSet<Sentence> {
+    let nonterminals = grammar.nonterminals();
+    let mut languages: HashMap<Symbol, BTreeSet<Sentence>> = HashMap::new();
+    loop {
+        let mut changed = false;
+        for rule in grammar.rules() {
+            let sentences = expr_language(&rule.rhs, &nonterminals, &languages);
+            let language = languages.entry(rule.lhs.clone()).or_default();
+            let before = language.len();
+            language.extend(sentences);
+            changed |= language.len() != before;
+        }
+        if !changed {
+            break;
+        }
+    }
+    languages.remove(start).unwrap_or_default()
+}
+
+/// Desugaring leaves only BNF rules, and derives the same sentences as the EBNF it came from.
+fn assert_roundtrip(source: &str, start: &str) {
+    assert_roundtrip_with(source, start, Recursion::Left);
+    assert_roundtrip_with(source, start, Recursion::Right);
+}
+
+fn assert_roundtrip_with(source: &str, start: &str, recursion: Recursion) {
+    let mut grammar = parse(source).unwrap();
+    grammar.set_recursion(recursion);
+    let before = language(&grammar, start);
+    assert!(!before.is_empty());
+
+    grammar.split();
+    for rule in grammar.rules() {
+        assert!(rule.is_simple(), "{rule:?} was not desugared");
+    }
+    assert_eq!(language(&grammar, start), before);
+    grammar.to_parsing_grammar(start).unwrap();
+}
+
+#[test]
+fn test_desugar_seq_and_alt() {
+    assert_roundtrip(r#"
+        %token id int ;
+        type_property = "Integer" | "List" , "<" , type_property , ">" | id , int ;
+    "#, "type_property");
+}
+
+#[test]
+fn test_desugar_group() {
+    assert_roundtrip(r#"
+        %token id ;
+        port = ( "input" | "output" ) , id , ":" , ( id | "bits" ) ;
+        type = ( id ) | "Clock" ;
+    "#, "port");
+}
+
+#[test]
+fn test_desugar_optional() {
+    assert_roundtrip(r#"
+        %token id info newline ;
+        stmt = "skip" , [ info ] , [ ":" , id ] , newline ;
+    "#, "stmt");
+}
+
+#[test]
+fn test_desugar_repetition() {
+    assert_roundtrip(r#"
+        %token id newline ;
+        module = "module" , { port , newline } , { "skip" } ;
+        port = "input" , id ;
+    "#, "module");
+}
+
+#[test]
+fn test_desugar_nested() {
+    assert_roundtrip(r#"
+        %token id ;
+        layer = "layer" , id , [ ":" , { ( "layer" | id ) , [ "," ] } ] ;
+    "#, "layer");
+    assert_roundtrip(r#"
+        %token id ;
+        a = [ ( "x" | "y" , [ "z" ] ) , { ( id ) } ] ;
+    "#, "a");
+}
+
+/// Every rule of the FIRRTL grammar desugars into BNF.
+#[test]
+fn test_desugar_grammar_file() {
+    let mut grammar = parse(include_str!("../../GRAMMAR")).unwrap();
+    grammar.split();
+    for rule in grammar.rules() {
+        assert!(rule.is_simple(), "{rule:?} was not desugared");
+    }
+    grammar.to_parsing_grammar("circuit").unwrap();
+}
+
+/// A grammar which has not been split converts the same as once it has.
+#[test]
+fn test_parsing_grammar_unsplit() {
+    let grammar = parse(r#"
+        %token id newline ;
+        stmt = "skip" , [ id ] , { "," , id } , newline ;
+    "#).unwrap();
+    let unsplit = grammar.to_parsing_grammar("stmt").unwrap();
+    let mut split = grammar.clone();
+    split.split();
+    assert_eq!(unsplit.fingerprint(), split.to_parsing_grammar("stmt").unwrap().fingerprint());
+}
+
+/// Helpers are named after the rule they come from, and rules stay in source order.
+#[test]
+fn test_split_naming() {
+    let mut grammar = parse(r#"
+        %token id info newline ;
+        module = "module" , id , [ info ] , { port , newline } ;
+        port = ( "input" | "output" ) , id , [ info ] ;
+    "#).unwrap();
+    grammar.split();
+
+    let rules: Vec<String> = grammar.rules().iter().map(|rule| format!("{rule:?}")).collect();
+    assert_eq!(rules, vec![
+        r#"module -> "module" , id , module__opt1 , module__star2"#,
+        "module__opt1 -> ε",
+        "module__opt1 -> info",
+        "module__star2 -> ε",
+        "module__star2 -> module__star2 , port , newline",
+        r#"port -> port__alt1 , id , module__opt1"#,
+        r#"port__alt1 -> "input""#,
+        r#"port__alt1 -> "output""#,
+    ]);
+
+    let helper = grammar.helper("module__star2").unwrap();
+    assert_eq!(helper.rule, "module");
+    assert_eq!(format!("{:?}", helper.expr), "{ port , newline }");
+    assert_eq!(grammar.helpers().len(), 3);
+}
+
+/// Helper names never collide with names from the source.
+#[test]
+fn test_split_hygiene() {
+    let mut grammar = parse(r#"
+        a = [ "x" ] , a__opt1 ;
+        a__opt1 = "y" ;
+    "#).unwrap();
+    grammar.split();
+
+    assert_eq!(grammar.helpers()[0].name, "a__opt2");
+    assert_eq!(grammar.nonterminals(), vec!["a", "a__opt2", "a__opt1"]);
+}
+
+/// Splitting gives the same rules every time.
+#[test]
+fn test_split_deterministic() {
+    let split = || {
+        let mut grammar = parse(include_str!("../../GRAMMAR")).unwrap();
+        grammar.split();
+        grammar.rules().to_vec()
+    };
+    let first = split();
+    for _ in 0..4 {
+        assert_eq!(split(), first);
+    }
+}
+
+#[test]
+fn test_desugar_plus_and_optional() {
+    assert_roundtrip(r#"
+        %token id newline indent dedent ;
+        block = indent , ( statement , newline )+ , dedent , "else"? ;
+        statement = "skip" | id ;
+    "#, "block");
+}
+
+#[test]
+fn test_desugar_sep() {
+    assert_roundtrip(r#"
+        %token id ;
+        args = "(" , [ sep(expr, ",") ] , ")" ;
+        expr = id | "x" , sep(( "a" | "b" ), ( "," | ";" ))? ;
+    "#, "args");
+}
+
+/// `%recursion` picks which way repetitions recurse.
+#[test]
+fn test_recursion() {
+    let rules = |source: &str| {
+        let mut grammar = parse(source).unwrap();
+        grammar.split();
+        grammar.rules().iter().map(|rule| format!("{rule:?}")).collect::<Vec<_>>()
+    };
+
+    assert_eq!(rules(r#"a = sep("x", ",") ;"#), vec![
+        "a -> a__sep1",
+        r#"a__sep1 -> "x""#,
+        r#"a__sep1 -> a__sep1 , "," , "x""#,
+    ]);
+    assert_eq!(rules(r#"%recursion right ; a = "x"+ ;"#), vec![
+        "a -> a__plus1",
+        r#"a__plus1 -> "x""#,
+        r#"a__plus1 -> "x" , a__plus1"#,
+    ]);
+    assert!(matches!(parse(r#"%recursion up ; a = "x" ;"#), Err(Error::Syntax(_))));
+}
+
+#[test]
+fn test_comments() {
+    let grammar = parse(r#"
+        (* A statement,
+           which may be "skipped". *)
+        %token id ;
+        // The only rule.
+        stmt = "skip" (* inline *) | id ; // trailing
+    "#).unwrap();
+
+    assert_eq!(grammar.rules().len(), 1);
+    assert_eq!(grammar.terminals(), vec![r#""skip""#, "id"]);
+}
+
+fn syntax_error(source: &str) -> SyntaxError {
+    match parse_named("test.ebnf", source) {
+        Err(Error::Syntax(error)) => *error,
+        other => panic!("Expected a syntax error, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_syntax_error_missing_semicolon() {
+    let error = syntax_error("a = b , c\nc = \"x\" ;\n");
+    assert_eq!((error.line, error.column), (2, 1));
+    assert_eq!(error.source_line, "c = \"x\" ;");
+    assert!(error.expected.contains(&"`;`".to_string()));
+    assert_eq!(error.hint.as_deref(), Some("did you forget `;` after the previous rule?"));
+    assert_eq!(error.to_string(), [
+        "unexpected `c`",
+        " --> test.ebnf:2:1",
+        "  |",
+        "2 | c = \"x\" ;",
+        "  | ^",
+        "  = expected one of: `+`, `,`, `;`, `?`, `|`",
+        "  = hint: did you forget `;` after the previous rule?",
+    ].join("\n"));
+
+    let error = syntax_error("a = \"x\"");
+    assert_eq!(error.hint.as_deref(), Some("did you forget `;` at the end of the last rule?"));
+}
+
+#[test]
+fn test_syntax_error_underline() {
+    let error = syntax_error("a = \"x\" ;\nb = \"y\" , %token ;\n");
+    assert_eq!((error.line, error.column), (2, 11));
+    assert!(error.to_string().contains("2 | b = \"y\" , %token ;\n  |           ^^^^^^\n"));
+
+    let error = syntax_error("a = { \"x\" | \"y\" } ;");
+    assert!(error.hint.unwrap().contains("must be grouped"));
+
+    let error = syntax_error("%recursion up ;");
+    assert_eq!(error.message, "expected `left` or `right` after %recursion");
+    assert_eq!(error.span, 11..13);
+}
+
+/// Load `root` from a set of in-memory files.
+fn load_files(root: &str, files: &[(&str, &str)]) -> Result<Grammar, Error> {
+    let files: HashMap<&str, &str> = files.iter().copied().collect();
+    load(root, |path| {
+        let path = path.to_str().unwrap();
+        files
+            .get(path)
+            .map(|source| source.to_string())
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, path))
+    })
+}
+
+fn rule_strings(grammar: &Grammar) -> Vec<String> {
+    grammar.rules().iter().map(|rule| format!("{rule:?}")).collect()
+}
+
+#[test]
+fn test_import() {
+    let grammar = load_files("firrtl/main.ebnf", &[
+        ("firrtl/main.ebnf", r#"
+            import "types.ebnf" ;
+            import "exprs/exprs.ebnf" ;
+            %token id ;
+            port = "input" , id , ":" , type ;
+        "#),
+        ("firrtl/types.ebnf", r#"
+            type = "UInt" | "Clock" | "Probe" , "<" , type , ">" ;
+        "#),
+        ("firrtl/exprs/exprs.ebnf", r#"
+            import "../types.ebnf" ;
+            %token int ;
+            expr = "UInt" , "(" , int , ")" | "cast" , "<" , type , ">" ;
+        "#),
+    ]).unwrap();
+
+    assert_eq!(grammar.tokens(), &["id", "int"]);
+    assert_eq!(grammar.nonterminals(), vec!["port", "type", "expr"]);
+    grammar.check().unwrap();
+}
+
+/// Rules with the same name in different files are kept apart, and qualified names pick between them.
+#[test]
+fn test_import_namespaces() {
+    let grammar = load_files("main.ebnf", &[
+        ("main.ebnf", r#"
+            import "a.ebnf" ;
+            import "b.ebnf" as vendor ;
+            top = a.args | vendor.args | args ;
+            args = "(" , ")" ;
+        "#),
+        ("a.ebnf", r#" args = "a" ; "#),
+        ("b.ebnf", r#" args = "b" , more ; more = "!" ; "#),
+    ]).unwrap();
+
+    // Merged names use the file's own namespace rather than the alias it was imported under.
+    assert_eq!(rule_strings(&grammar), vec![
+        "top -> a.args | b.args | args",
+        r#"args -> "(" , ")""#,
+        r#"a.args -> "a""#,
+        r#"b.args -> "b" , more"#,
+        r#"more -> "!""#,
+    ]);
+
+    let error = load_files("main.ebnf", &[
+        ("main.ebnf", r#" import "a.ebnf" ; import "b.ebnf" ; top = args ; "#),
+        ("a.ebnf", r#" args = "a" ; "#),
+        ("b.ebnf", r#" args = "b" ; "#),
+    ]).unwrap_err();
+    assert_eq!(error.to_string(), "main.ebnf: args is ambiguous, it could be any of a.args, b.args");
+
+    let grammar = load_files("main.ebnf", &[
+        ("main.ebnf", r#" import "a.ebnf" ; top = x ; "#),
+        ("a.ebnf", r#" import "b.ebnf" ; x = y ; "#),
+        ("b.ebnf", r#" y = "b" ; "#),
+    ]).unwrap();
+    grammar.check().unwrap();
+
+    let error = load_files("main.ebnf", &[
+        ("main.ebnf", r#" import "a.ebnf" ; import "b.ebnf" ; top = x ; "#),
+        ("a.ebnf", r#" x = y ; "#),
+        ("b.ebnf", r#" y = "b" ; "#),
+    ]).unwrap_err();
+    assert_eq!(error.to_string(), "a.ebnf: y is defined in b.ebnf, which is not imported");
+}
+
+#[test]
+fn test_override_and_extend() {
+    let grammar = load_files("vendor.ebnf", &[
+        ("vendor.ebnf", r#"
+            import "base.ebnf" ;
+            statement |= "my_intrinsic" , expr ;
+            %override expr = "0" | "1" ;
+            start = statement ;
+        "#),
+        ("base.ebnf", r#"
+            statement = "skip" | "node" , expr ;
+            expr = "x" ;
+            other = expr ;
+        "#),
+    ]).unwrap();
+
+    assert_eq!(rule_strings(&grammar), vec![
+        "start -> statement",
+        r#"statement -> "skip" | "node" , expr"#,
+        r#"statement -> "my_intrinsic" , expr"#,
+        r#"expr -> "0" | "1""#,
+        "other -> expr",
+    ]);
+
+    let error = load_files("vendor.ebnf", &[("vendor.ebnf", r#" statement |= "x" ; "#)]).unwrap_err();
+    assert!(matches!(error, Error::Import { .. }));
+}
+
+/// Files are only loaded once, even when they import each other.
+#[test]
+fn test_import_cycle() {
+    let grammar = load_files("a.ebnf", &[
+        ("a.ebnf", r#" import "b.ebnf" ; a = "a" , [ b ] ; "#),
+        ("b.ebnf", r#" import "a.ebnf" ; b = "b" , a ; "#),
+    ]).unwrap();
+    assert_eq!(rule_strings(&grammar), vec![r#"a -> "a" , [ b ]"#, r#"b -> "b" , a"#]);
+
+    let error = load_files("a.ebnf", &[("a.ebnf", r#" import "missing.ebnf" ; a = "a" ; "#)]).unwrap_err();
+    assert!(matches!(error, Error::Io { path, .. } if path == "missing.ebnf"));
+}
+
+/// Parse `source`, whose tokens are separated by spaces, with the LR(0) machine.
+/// Words which are quoted literals in the grammar lex as themselves, and anything else as `id`.
+fn parse_words<'t, 's>(table: &'t parsing::lr0::ParseTable, source: &'s str) -> parsing::lr0::Node<'t, 's> {
+    let grammar = table.grammar();
+    let mut tokens = vec![];
+    let mut start = 0;
+    for word in source.split(' ') {
+        let terminal = grammar.symbol(&format!("\"{word}\"")).unwrap_or_else(|| grammar.symbol("id").unwrap());
+        tokens.push(parsing::Token::new(terminal, word, start..start + word.len()));
+        start += word.len() + 1;
+    }
+    let mut machine = parsing::lr0::Machine::new(table);
+    machine.run(&mut tokens.into_iter()).unwrap();
+    machine.tree().unwrap().clone()
+}
+
+#[test]
+fn test_annotations() {
+    let mut grammar = parse(r#"
+        %token id ;
+        @node(Call) call = @name(callee) id , @skip "(" , [ @name(arg) sep(arg, @skip ",") ] , @skip ")" ;
+        @inline arg = id | call ;
+    "#).unwrap();
+    assert_eq!(format!("{:?}", grammar.rules()[1]), "@inline arg -> id | call");
+
+    grammar.split();
+    assert_eq!(rule_strings(&grammar)[0], r#"@node(Call) call -> @name(callee) id , @skip "(" , call__opt1 , @skip ")""#);
+
+    let table = parsing::lr0::ParseTable::new(grammar.to_parsing_grammar("call").unwrap());
+    let builder = tree::TreeBuilder::new(&grammar);
+
+    let tree = builder.build(&parse_words(&table, "f ( a , g ( ) , b )"));
+    assert_eq!(format!("{tree:?}"), r#"(Call callee="f" arg="a" arg=(Call callee="g") arg="b")"#);
+    assert_eq!(tree.span, Some(0..19));
+
+    let args: Vec<_> = tree.field("arg").map(|arg| arg.span.clone().unwrap()).collect();
+    assert_eq!(args, vec![4..5, 8..13, 16..17]);
+}
+
+/// Without annotations, only the helper nonterminals are dissolved.
+#[test]
+fn test_tree_helpers_dissolved() {
+    let mut grammar = parse(r#"
+        %token id ;
+        list = "[" , { item , [ "," ] } , "]" ;
+        item = id ;
+    "#).unwrap();
+    grammar.split();
+    let table = parsing::lr0::ParseTable::new(grammar.to_parsing_grammar("list").unwrap());
+
+    let tree = tree::TreeBuilder::new(&grammar).build(&parse_words(&table, "[ a , b ]"));
+    assert_eq!(format!("{tree:?}"), r#"(list "[" (item "a") "," (item "b") "]")"#);
+}
+
+/// The machine resolves shift/reduce conflicts by shifting, but has no way to choose between two reductions.
+#[test]
+fn test_metagrammar_table_has_no_reduce_conflicts() {
+    for conflict in syntax::table().conflicts() {
+        let reductions = conflict.actions.iter().filter(|action| matches!(action, parsing::lr0::Action::Reduce(_)));
+        assert!(reductions.count() <= 1, "{conflict:?}");
+    }
+}
+
+/// The self-hosted parser agrees with the lalrpop one, on both what it accepts and where it fails.
+#[cfg(feature = "lalrpop-oracle")]
+#[test]
+fn test_lalrpop_oracle() {
+    let grammar = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/../GRAMMAR")).unwrap();
+    let sources = [
+        grammar.as_str(),
+        "",
+        "import \"a.ebnf\" ; import \"b/c.ebnf\" as c ;",
+        "%token id int ; %recursion right ;",
+        "a = b , c | \"x\" , { d } , [ e , \"f\" ] , ( g | h )+ , i? ;",
+        "a = sep(b, \",\") , sep(( c , d ), e) ;",
+        "@inline @node(Call) call = @name(callee) id , @skip \"(\" , @skip @name(x) y ;",
+        "%override @inline ns.a = b ; ns.b |= c.d ; a |= e ;",
+        "a = b (* comment *) ; // comment\n b = c ;",
+        "a = b , c\nc = \"x\" ;\n",
+        "a = \"x\"",
+        "a = \"x\" ;\nb = \"y\" , %token ;\n",
+        "a = { \"x\" | \"y\" } ;",
+        "%recursion up ;",
+        "a = 'x' ;",
+        "a = \"\" ;",
+        "a = \"x ;",
+        "a.b = c ;",
+        "a = b ; ;",
+        "%override = b ;",
+        "a = sep(b) ;",
+    ];
+    for source in sources {
+        let expected = metagrammar::FileParser::new()
+            .parse(source)
+            .map_err(|e| SyntaxError::from_lalrpop("test.ebnf", source, e));
+        let actual = syntax::parse("test.ebnf", source);
+        match (actual, expected) {
+            (Ok(actual), Ok(expected)) => assert_eq!(actual, expected, "{source}"),
+            (Err(actual), Err(expected)) => {
+                assert_eq!((actual.span, actual.message), (expected.span, expected.message), "{source}");
+            }
+            (actual, expected) => panic!("{source}\nself-hosted: {actual:?}\nlalrpop: {expected:?}"),
+        }
+    }
+}
+
+fn format_source(source: &str) -> String {
+    format::format("test.ebnf", source).unwrap()
+}
+
+#[test]
+fn test_format_canonical() {
+    let source = r#"
+%token  id
+  int ;
+stmt="skip"|id,"=",expr ;
+
+
+expr = id|int;
+@inline  arg= sep( expr,",")
+  |  ( "(" ,  arg ,")" )+ , [id] , id? , { @skip "," ,@name(x) id } ;
+"#;
+    assert_eq!(format_source(source), r#"%token id int ;
+stmt =
+    "skip"
+  | id , "=" , expr ;
+
+expr = id | int ;
+@inline arg =
+    sep(expr, ",")
+  | ( "(" , arg , ")" )+ , [ id ] , id? , { @skip "," , @name(x) id } ;
+"#);
+}
+
+#[test]
+fn test_format_wraps_long_sequences() {
+    let names: Vec<String> = (0..30).map(|i| format!("name{i}")).collect();
+    let formatted = format_source(&format!("%token {} ;\nlong = {} ;", names.join(" "), names.join(" , ")));
+
+    let lines: Vec<&str> = formatted.lines().collect();
+    assert_eq!(lines[1], "long =");
+    assert!(lines[2..].iter().all(|line| line.starts_with("    name") && line.chars().count() <= 100));
+    assert!(lines[2].ends_with(" ,"));
+    assert!(formatted.ends_with("name29 ;\n"));
+}
+
+#[test]
+fn test_format_keeps_comments() {
+    let source = r#"
+// Statements.
+(* They end in newlines. *)
+stmt = "skip" (* inline *) | id ; // trailing
+
+// Between.
+
+expr = // header
+    id // first
+  | int ;
+// At the end.
+"#;
+    assert_eq!(format_source(source), r#"// Statements.
+(* They end in newlines. *)
+stmt =
+    "skip" (* inline *)
+  | id ; // trailing
+
+// Between.
+
+expr = // header
+    id // first
+  | int ;
+// At the end.
+"#);
+}
+
+/// Formatting changes nothing but layout, and formatted files stay as they are.
+#[test]
+fn test_format_roundtrip() {
+    let sources = [
+        "import \"a.ebnf\" as a ; %recursion right ;\n%override @node(A) a.x = y ; a.y |= z ; y = \"y\" ;".to_string(),
+        std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/../GRAMMAR")).unwrap(),
+    ];
+    for source in sources {
+        let formatted = format_source(&source);
+        assert_eq!(syntax::parse("test.ebnf", &formatted).unwrap(), syntax::parse("test.ebnf", &source).unwrap());
+        assert_eq!(format_source(&formatted), formatted);
+    }
+}
+
+/// GRAMMAR is kept in the canonical style, as `metagrammar fmt --check` would report.
+#[test]
+fn test_grammar_file_is_formatted() {
+    let source = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/../GRAMMAR")).unwrap();
+    assert!(format_source(&source) == source, "GRAMMAR is not formatted; run `metagrammar fmt`");
+}
+
+#[test]
+fn test_display_grammar() {
+    let grammar = load_files("main.ebnf", &[
+        ("main.ebnf", "import \"lib.ebnf\" ; %token id ; a = b , c ; b |= id ;"),
+        ("lib.ebnf", "%token id ; b = \"b\" ; c = [ id ] ;"),
+    ]).unwrap();
+    assert_eq!(grammar.to_string(), "%token id ;\n\na = b , c ;\nb = \"b\" ;\nb |= id ;\nc = [ id ] ;\n");
+    assert_eq!(rule_strings(&parse(&grammar.to_string()).unwrap()), rule_strings(&grammar));
+}
+
+fn load_suite(text: &str) -> suite::Suite {
+    suite::Suite::parse(Path::new("test.test"), text).unwrap()
+}
+
+#[test]
+fn test_suite_parse() {
+    let suite = load_suite(concat!(
+        "=== grammar start=list\n",
+        "%token id ;\n",
+        "list = \"[\" , { id } , \"]\" ;\n",
+        "=== accept\n",
+        "[ a b ]\n",
+        "\n",
+        "=== tree:\n",
+        "(list \"[\"\n",
+        "   \"a\" )\n",
+        "[ a\n",
+        "]\n",
+        "=== reject\n",
+    ));
+    assert_eq!((suite.start.as_str(), suite.lexer), ("list", suite::Lexer::Words));
+
+    let cases: Vec<_> = suite.cases.iter().map(|case| (case.line, case.expect.clone(), case.input.as_str())).collect();
+    assert_eq!(cases, vec![
+        (4, suite::Expect::Accept, "[ a b ]\n"),
+        (7, suite::Expect::Tree(r#"(list "[" "a")"#.to_string()), "[ a\n]\n"),
+        (12, suite::Expect::Reject, "\n"),
+    ]);
+
+    assert_eq!(suite::normalize("( a  \"b  c\"\n ( d ) )"), r#"(a "b  c" (d))"#);
+    assert_eq!(suite::normalize(r#"(a "\")" b)"#), r#"(a "\")" b)"#);
+}
+
+#[test]
+fn test_suite_errors() {
+    let error = |text: &str| suite::Suite::parse(Path::new("test.test"), text).unwrap_err().to_string();
+    assert_eq!(error("a = \"a\" ;\n"), "test.test:1: expected a `=== grammar` section");
+    assert_eq!(error("=== grammar lexer=yacc\na = \"a\" ;\n"), "test.test:1: unknown option `lexer=yacc`");
+    assert_eq!(error("=== grammar\na = \"a\" ;\n=== maybe\na\n"), "test.test:3: expected `accept`, `reject`, or `tree:`, found `maybe`");
+    assert_eq!(
+        error("=== grammar\na = \"a\" ;\n=== tree: (a\na\n"),
+        "test.test:3: the tree is missing, or its parentheses do not balance",
+    );
+
+    // Errors in the grammar point at its line in the test file.
+    let Err(Error::Syntax(e)) = suite::Suite::parse(Path::new("test.test"), "\n=== grammar\n\na = \"a\"\n=== accept\na\n") else {
+        panic!("expected a syntax error");
+    };
+    assert!(e.to_string().contains("test.test:4:"), "{e}");
+}
+
+/// Each case is run through every backend, and each failure says why.
+#[test]
+fn test_suite_run() {
+    let suite = load_suite(concat!(
+        "=== grammar\n",
+        "%token id ;\n",
+        "sum = sum , \"+\" , sum | id ;\n",
+        "=== accept\n",
+        "a + b + c\n",
+        "=== reject\n",
+        "a b\n",
+        "=== tree: (sum (sum \"a\") \"+\" (sum \"b\"))\n",
+        "a + b\n",
+        "=== tree: (sum \"a\")\n",
+        "a + b\n",
+        "=== reject\n",
+        "a + b\n",
+        "=== tree: (sum (sum \"a\") \"+\" (sum (sum \"b\") \"+\" (sum \"c\")))\n",
+        "a + b + c\n",
+    ));
+
+    let outcomes = suite.run();
+    assert_eq!(outcomes.len(), 6 * suite::Backend::ALL.len());
+    let failures = |case: usize| -> Vec<(String, String)> {
+        outcomes
+            .iter()
+            .filter(|outcome| outcome.case == case)
+            .filter_map(|outcome| Some((outcome.backend.to_string(), outcome.result.clone().err()?)))
+            .collect()
+    };
+    for case in 0..3 {
+        assert_eq!(failures(case), vec![]);
+    }
+    let expected = r#"expected (sum "a")
+       got (sum (sum "a") "+" (sum "b"))"#;
+    assert_eq!(failures(3).len(), 4);
+    assert!(failures(3).iter().all(|(_backend, message)| message == expected));
+    assert_eq!(failures(4).len(), 4);
+    assert!(failures(4).iter().all(|(_backend, message)| message == "accepted"));
+
+    // The LR machines resolve the conflict by shifting, and the Earley parser sees both trees.
+    assert_eq!(failures(5), vec![("Earley".to_string(), "ambiguous, with 2 trees".to_string())]);
+}
+
+/// The grammar test files in tests/grammar all pass.
+#[test]
+fn test_grammar_test_files() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/grammar");
+    let mut paths: Vec<_> = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    for path in paths {
+        let suite = suite::Suite::load(&path).unwrap();
+        for outcome in suite.run() {
+            let case = &suite.cases[outcome.case];
+            assert_eq!(outcome.result, Ok(()), "{}:{} with {}", path.display(), case.line, outcome.backend);
+        }
+    }
+}
+
+#[test]
+fn test_corpus_report() {
+    let mut grammar = parse(r#"
+        %token id newline ;
+        statement = ( "node" , id , "=" , id | "skip" | id , "<=" , id ) , newline ;
+    "#).unwrap();
+    grammar.split();
+    let table = parsing::lr0::ParseTable::new(grammar.to_parsing_grammar(corpus::START).unwrap());
+
+    let report = corpus::run(&table, "node a = b\nskip\n\nnode a =\nx <= y\nnode = b\nskip ?\n");
+    assert_eq!((report.passed(), report.failed()), (3, 3));
+    assert_eq!(report.to_string(), concat!(
+        "6 statements: 3 passed, 3 failed\n",
+        "\n",
+        "kind                total   passed   failed\n",
+        "node                    3        1        2\n",
+        "skip                    2        1        1\n",
+        "id                      1        1        0\n",
+        "\n",
+        "first node failure, line 4: column 9: unexpected newline `\\n`\n",
+        "    node a =\n",
+        "\n",
+        "first skip failure, line 7: column 6: `?` is not a token\n",
+        "    skip ?\n",
+    ));
+}
+
+/// Every statement in tests/statements.txt parses as it did when tests/statements.snapshot was last updated.
+#[test]
+fn test_statement_corpus_snapshot() {
+    let root = concat!(env!("CARGO_MANIFEST_DIR"), "/..");
+    let mut grammar = parse_file(format!("{root}/GRAMMAR")).unwrap();
+    grammar.split();
+    let table = parsing::lr0::ParseTable::new(grammar.to_parsing_grammar(corpus::START).unwrap());
+
+    let report = corpus::run(&table, &std::fs::read_to_string(format!("{root}/tests/statements.txt")).unwrap());
+    let snapshot = std::fs::read_to_string(format!("{root}/tests/statements.snapshot")).unwrap();
+    assert!(
+        report.to_string() == snapshot,
+        "The corpus report has changed; run `metagrammar corpus --update` if this is intended.\n{report}",
+    );
+}
+
+#[test]
+fn test_debugger() {
+    let mut grammar = parse(r#"
+        %token id newline ;
+        statement = "node" , id , "=" , id , newline ;
+    "#).unwrap();
+    grammar.split();
+    let table = parsing::lr0::ParseTable::new(grammar.to_parsing_grammar("statement").unwrap());
+    let tokens = firrtl::tokenize(table.grammar(), "node x = y\n").unwrap();
+    let mut debugger = debugger::Debugger::new(&table, tokens);
+
+    assert_eq!(debugger.execute("input 2").unwrap(), "0: \"node\" \"node\" at byte 0\n1: id \"x\" at byte 5\n... and 3 more");
+    assert_eq!(debugger.execute("step").unwrap(), "step 0: state 0, on \"node\" => Shift(1)");
+    assert!(debugger.execute("stack").unwrap().ends_with("\"node\" \"node\" => state 1\n    statement -> \"node\" . id \"=\" id newline"));
+
+    assert_eq!(debugger.execute("continue 3").unwrap(), "step 2: state 2, on \"=\" => Shift(3)\nstopped before token 3");
+    debugger.execute("break rule 1").unwrap();
+    assert_eq!(
+        debugger.execute("continue").unwrap(),
+        "step 5: state 5, on $ => Reduce(statement -> \"node\" id \"=\" id newline)\nbreakpoint 1: rule 1: statement -> \"node\" id \"=\" id newline",
+    );
+    // The reduction went straight to the state after `statement`, where reducing to the start symbol halts.
+    assert!(debugger.execute("stack").unwrap().ends_with("statement => state 6\n    START -> statement ."));
+    assert_eq!(debugger.execute("input").unwrap(), "the input is empty");
+    assert_eq!(debugger.execute("c").unwrap(), "step 6: state 6, on $ => Halt\nthe parse halted");
+    assert!(debugger.machine().is_halted());
+
+    assert!(debugger.execute("break state 99").is_err());
+    assert!(debugger.execute("frobnicate").is_err());
+}
+
+#[test]
+fn test_firrtl_lex_error() {
+    let mut grammar = parse(r#"
+        %token id newline ;
+        statement = "node" , id , "=" , id , newline ;
+    "#).unwrap();
+    grammar.split();
+    let table = parsing::lr0::ParseTable::new(grammar.to_parsing_grammar("statement").unwrap());
+    let mut machine = parsing::lr0::Machine::new(&table);
+    let error = machine.run(&mut firrtl::FirrtlTokens::new(table.grammar(), "node x = $\n")).unwrap_err();
+    assert!(matches!(error, parsing::lr0::ParseError::Input(firrtl::LexError { span, .. }) if span == (9..10)));
+}
+
+#[test]
+fn test_firrtl_completions() {
+    let root = concat!(env!("CARGO_MANIFEST_DIR"), "/..");
+    let mut grammar = parse_file(format!("{root}/GRAMMAR")).unwrap();
+    grammar.split();
+    let table = parsing::lr0::ParseTable::new(grammar.to_parsing_grammar("circuit").unwrap());
+
+    let source = "FIRRTL version 4.0.0\ncircuit Top :\n  module Top :\n    input a : UInt<1>\n    no\n";
+    let line = source.find("    no").unwrap() + 4;
+    let statements = firrtl::completions(&table, source, line);
+    for keyword in ["connect", "node", "skip", "when", "wire"] {
+        assert!(statements.contains(&keyword.to_string()), "{keyword} is missing from {statements:?}");
+    }
+    assert!(!statements.contains(&"circuit".to_string()));
+    assert_eq!(firrtl::completions(&table, source, line + 2), vec!["node"]);
+
+    // After the ports, a port can still follow, but not after a statement.
+    assert!(statements.contains(&"input".to_string()));
+    let source = "FIRRTL version 4.0.0\ncircuit Top :\n  module Top :\n    skip\n    \n";
+    let completions = firrtl::completions(&table, source, source.len() - 1);
+    assert!(completions.contains(&"node".to_string()) && !completions.contains(&"input".to_string()));
+
+    // A dedent back to the module's level expects another module.
+    let source = "FIRRTL version 4.0.0\ncircuit Top :\n  module Top :\n    skip\n  \n";
+    assert!(firrtl::completions(&table, source, source.len() - 1).contains(&"module".to_string()));
+
+    // Where a name is expected, there is no keyword to complete.
+    let source = "FIRRTL version 4.0.0\ncircuit Top :\n  module Top :\n    node node\n";
+    assert_eq!(firrtl::completions(&table, source, source.len() - 1), Vec::<String>::new());
+
+    // Offsets past the end, or inside a character, have no completions rather than panicking.
+    let source = "FIRRTL version 4.0.0\ncircuit Top :\n  module Top : ; é\n";
+    assert_eq!(firrtl::completions(&table, source, source.len() + 1), Vec::<String>::new());
+    assert_eq!(firrtl::completions(&table, source, source.find('é').unwrap() + 1), Vec::<String>::new());
+}
+
+fn firrtl_table() -> parsing::lr0::ParseTable {
+    let root = concat!(env!("CARGO_MANIFEST_DIR"), "/..");
+    let mut grammar = parse_file(format!("{root}/GRAMMAR")).unwrap();
+    grammar.split();
+    parsing::lr0::ParseTable::new(grammar.to_parsing_grammar("circuit").unwrap())
+}
+
+const INCREMENTAL_FIR: &str = include_str!("../../tests/incremental.fir");
+
+/// A document's tree is the one the machine builds.
+#[test]
+fn test_incremental_parse() {
+    let table = firrtl_table();
+    let document = incremental::Document::new(&table, INCREMENTAL_FIR);
+    assert_eq!(document.error(), None);
+
+    let tokens = firrtl::tokenize(table.grammar(), INCREMENTAL_FIR).unwrap();
+    let lexemes: Vec<_> = tokens.iter().map(|token| (token.terminal, token.span.clone())).collect();
+    let document_lexemes: Vec<_> = document.tokens().iter().map(|lexeme| (lexeme.terminal, lexeme.span.clone())).collect();
+    assert_eq!(document_lexemes, lexemes);
+
+    let mut machine = parsing::lr0::Machine::new(&table);
+    machine.run(&mut tokens.into_iter()).unwrap();
+    assert_eq!(format!("{:?}", document.tree().unwrap()), format!("{:?}", machine.tree().unwrap()));
+}
+
+/// Every edit leaves the document as parsing its new text from scratch would, reusing what it can.
+#[test]
+fn test_incremental_edits() {
+    let table = firrtl_table();
+    let mut document = incremental::Document::new(&table, INCREMENTAL_FIR);
+
+    // Renaming a node in the middle of the file parses none of the statements around it again,
+    // only the edited line and tokens which are never part of a reusable subtree, like the module's header.
+    let at = INCREMENTAL_FIR.find("node bump").unwrap() + "node ".len();
+    document.edit(at..at + "bump".len(), "increment").unwrap();
+    let total = document.tokens().len();
+    assert!(document.reused() * 4 > total * 3, "{} of {total} tokens were reused", document.reused());
+
+    let mut seed: u64 = 0x2545f4914f6cdd1d;
+    let mut random = |bound: usize| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) as usize % bound
+    };
+    // Most edits are to whole statements, which leave the file parsing more often than not.
+    let statements = ["skip", "node n = a", "connect y, tail(x, 1)", "when c :\n      skip\n    else :\n      skip"];
+    let noise = ["", "x", " ", "\n", "\n    ", ":", "("];
+    for edit in 0..300 {
+        if edit % 25 == 0 {
+            let end = document.source().len();
+            document.edit(0..end, INCREMENTAL_FIR).unwrap();
+        }
+        let source = document.source();
+        let lines: Vec<usize> = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(at, _)| at + 1))
+            .filter(|at| source[*at..].starts_with("    ") && !["input", "output"].iter().any(|port| source[*at..].trim_start().starts_with(port)))
+            .collect();
+        let line = lines[random(lines.len())];
+        let indent = source[line..].len() - source[line..].trim_start_matches(' ').len();
+        let line_end = source[line..].find('\n').map_or(source.len(), |at| line + at + 1);
+        let (start, end, text) = match random(4) {
+            0 => (line + indent, line + indent, format!("{}\n{}", statements[random(statements.len())], &source[line..line + indent])),
+            1 => (line, line_end, String::new()),
+            2 => (line + indent, line_end - 1, statements[random(statements.len())].to_string()),
+            _ => {
+                let start = random(source.len() + 1);
+                (start, (start + random(8)).min(source.len()), noise[random(noise.len())].to_string())
+            }
+        };
+        let before = document.source().to_string();
+        let mut edits = vec![(start..end, text.clone())];
+        // Undoing most edits which break the file keeps the ones after them from failing too.
+        if random(4) > 0 {
+            edits.push((start..start + text.len(), before[start..end].to_string()));
+        }
+        for (range, text) in edits {
+            let result = document.edit(range.clone(), &text);
+            let fresh = incremental::Document::new(&table, document.source());
+            let context = format!("edit {edit}: {range:?} to {text:?} in\n{before}");
+            assert_eq!(result.as_ref().err(), fresh.error(), "{context}");
+            assert_eq!(document.tokens(), fresh.tokens(), "{context}");
+            assert_eq!(document.tree(), fresh.tree(), "{context}");
+            if result.is_ok() {
+                break;
+            }
+        }
+    }
+}
+
+/// Edits past the end of the source, backwards, or inside a character are refused and change nothing.
+#[test]
+fn test_incremental_invalid_edits() {
+    let table = firrtl_table();
+    let source = format!("{INCREMENTAL_FIR}    ; é\n");
+    let mut document = incremental::Document::new(&table, source.as_str());
+    let error = document.error().cloned();
+    let tree = document.tree().cloned();
+
+    let end = source.len();
+    let inside = source.find('é').unwrap() + 1;
+    for range in [end..end + 1, end + 1..end + 2, inside..inside, inside - 1..inside, std::ops::Range { start: 3, end: 2 }] {
+        assert_eq!(document.edit(range.clone(), "x"), Err(incremental::Error::InvalidRange(range)));
+        assert_eq!(document.source(), source);
+        assert_eq!(document.error().cloned(), error);
+        assert_eq!(document.tree().cloned(), tree);
+    }
+}
+
+/// A long module makes a tree as deep as its statements, which has to be dropped without recursing,
+/// and a document is parsed within its limits.
+#[test]
+fn test_incremental_deep_tree_and_limits() {
+    let table = firrtl_table();
+    let source = format!("FIRRTL version 4.0.0\ncircuit Top :\n  module Top :\n{}", "    skip\n".repeat(300_000));
+    let mut document = incremental::Document::new(&table, source.as_str());
+    assert_eq!(document.error(), None);
+    let end = document.source().len();
+    document.edit(end..end, "    skip\n").unwrap();
+    drop(document);
+
+    let limits = parsing::lr0::Limits::new().max_tokens(100);
+    let document = incremental::Document::with_limits(&table, INCREMENTAL_FIR, limits);
+    assert_eq!(document.error(), Some(&incremental::Error::LimitExceeded(parsing::lr0::Limit::Tokens(100))));
+}
@@ -0,0 +1,163 @@
+use parsing::Span;
+use parsing::lr0::Node;
+
+use crate::*;
+
+/// A node of the tree shaped by a grammar's annotations.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SyntaxNode<'s> {
+    /// The `@node` kind, the rule name, or for a leaf, the terminal.
+    pub kind: String,
+    /// The `@name` this node was labelled with in its parent.
+    pub field: Option<String>,
+    pub children: Vec<SyntaxNode<'s>>,
+    /// The text of a leaf.
+    pub text: Option<&'s str>,
+    pub span: Option<Span>,
+}
+
+impl<'s> SyntaxNode<'s> {
+    pub fn is_leaf(&self) -> bool {
+        self.text.is_some()
+    }
+
+    /// The children labelled with `field`.
+    pub fn field(&self, field: &str) -> impl Iterator<Item = &SyntaxNode<'s>> {
+        self.children.iter().filter(move |child| child.field.as_deref() == Some(field))
+    }
+}
+
+/// Prints the tree as an s-expression, with leaves as their quoted text and fields as `field=child`.
+impl<'s> std::fmt::Debug for SyntaxNode<'s> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(field) = &self.field {
+            write!(f, "{field}=")?;
+        }
+        match self.text {
+            Some("") => write!(f, "{}", self.kind),
+            Some(text) => write!(f, "{text:?}"),
+            None => {
+                write!(f, "({}", self.kind)?;
+                for child in &self.children {
+                    write!(f, " {child:?}")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// How to build the tree for one rule of the desugared grammar.
+#[derive(Debug, Clone)]
+struct RuleShape {
+    inline: bool,
+    kind: Option<String>,
+    /// For each symbol on the right-hand side, `None` to skip it, or the field to label it with.
+    fields: Vec<Option<Option<String>>>,
+}
+
+/// Turns the parse tree from `lr0::Machine` into a tree shaped by the grammar's annotations.
+///
+/// Helper nonterminals from desugaring, and `@inline` rules, are dissolved into their parents.
+/// Symbols marked `@skip` are dropped, and those marked `@name(field)` are labelled.
+/// Rules marked `@node(Kind)` build nodes of that kind.
+pub struct TreeBuilder {
+    /// Indexed by the rules of the `parsing::Grammar`, where rule 0 is `START -> start`.
+    rules: Vec<RuleShape>,
+}
+
+impl TreeBuilder {
+    /// `grammar` must already be split, and be the one `to_parsing_grammar` built the parser's grammar from.
+    pub fn new(grammar: &Grammar) -> TreeBuilder {
+        let start = RuleShape {
+            inline: true,
+            kind: None,
+            fields: vec![Some(None)],
+        };
+
+        let mut rules = vec![start];
+        for rule in grammar.rules() {
+            let es = match &rule.rhs {
+                SymbolExpr::Seq(es) => es.clone(),
+                e => vec![e.clone()],
+            };
+            let fields = es
+                .iter()
+                .map(|e| {
+                    let (annotations, _) = e.annotations();
+                    if annotations.contains(&Annotation::Skip) {
+                        None
+                    } else {
+                        Some(annotations.iter().find_map(|annotation| match annotation {
+                            Annotation::Name(field) => Some(field.clone()),
+                            _ => None,
+                        }))
+                    }
+                })
+                .collect();
+            rules.push(RuleShape {
+                inline: rule.annotations.contains(&Annotation::Inline) || grammar.helper(&rule.lhs).is_some(),
+                kind: rule.annotations.iter().find_map(|annotation| match annotation {
+                    Annotation::Node(kind) => Some(kind.clone()),
+                    _ => None,
+                }),
+                fields,
+            });
+        }
+        TreeBuilder { rules }
+    }
+
+    /// Build the shaped tree for the parse tree rooted at `root`.
+    /// If the root dissolves into more or less than one node, they are gathered under a node for the root's symbol.
+    pub fn build<'s>(&self, root: &Node<'_, 's>) -> SyntaxNode<'s> {
+        let mut nodes = self.build_nodes(root);
+        if nodes.len() == 1 {
+            nodes.pop().unwrap()
+        } else {
+            SyntaxNode {
+                kind: root.symbol.to_string(),
+                field: None,
+                children: nodes,
+                text: None,
+                span: root.span(),
+            }
+        }
+    }
+
+    fn build_nodes<'s>(&self, node: &Node<'_, 's>) -> Vec<SyntaxNode<'s>> {
+        let Some(rule) = node.rule else {
+            let token = node.token.as_ref();
+            return vec![SyntaxNode {
+                kind: node.symbol.to_string(),
+                field: None,
+                children: vec![],
+                text: Some(token.map_or("", |token| token.text)),
+                span: token.map(|token| token.span.clone()),
+            }];
+        };
+
+        let shape = &self.rules[rule.index()];
+        let mut children = vec![];
+        for (child, field) in node.children.iter().zip(&shape.fields) {
+            let Some(field) = field else { continue };
+            for mut grandchild in self.build_nodes(child) {
+                if grandchild.field.is_none() {
+                    grandchild.field = field.clone();
+                }
+                children.push(grandchild);
+            }
+        }
+
+        if shape.inline {
+            children
+        } else {
+            vec![SyntaxNode {
+                kind: shape.kind.clone().unwrap_or_else(|| node.symbol.to_string()),
+                field: None,
+                children,
+                text: None,
+                span: node.span(),
+            }]
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Saving and loading parse tables.
+//!
+//! A table is cached without its grammar. Instead, it records the grammar's `fingerprint`,
+//! and loading a table checks it against the grammar it is being loaded for.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lr0::{Action, ParseTable, StateIndex};
+use crate::*;
+
+#[derive(Serialize, Deserialize)]
+struct CachedTable {
+    grammar_fingerprint: u64,
+    states: Vec<Vec<ItemData>>,
+    actions: Vec<(StateIndex, Option<SymbolIndex>, Vec<Action>)>,
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    Json(serde_json::Error),
+    Binary(postcard::Error),
+    /// The table was built for a different grammar.
+    GrammarMismatch {
+        expected: u64,
+        found: u64,
+    },
+    /// The table refers to a state, rule, or symbol which its grammar or the table itself does not have.
+    OutOfRange(String),
+}
+
+impl std::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheError::Json(e) => write!(f, "Could not read JSON table: {e}"),
+            CacheError::Binary(e) => write!(f, "Could not read binary table: {e}"),
+            CacheError::GrammarMismatch { expected, found } => {
+                write!(f, "Table was built for grammar {found:016x}, but the grammar is {expected:016x}")
+            }
+            CacheError::OutOfRange(what) => write!(f, "Table is corrupt: {what} is out of range"),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+impl From<serde_json::Error> for CacheError {
+    fn from(e: serde_json::Error) -> Self {
+        CacheError::Json(e)
+    }
+}
+
+impl From<postcard::Error> for CacheError {
+    fn from(e: postcard::Error) -> Self {
+        CacheError::Binary(e)
+    }
+}
+
+impl CachedTable {
+    /// Check every index in the table is in range, since the fingerprint only says which grammar it was built for.
+    fn check_ranges(&self, grammar: &Grammar) -> Result<(), CacheError> {
+        let out_of_range = |what: String| Err(CacheError::OutOfRange(what));
+        let (states, symbols, rules) = (self.states.len(), grammar.symbols.len(), grammar.rules.len());
+        if states == 0 {
+            return out_of_range("the start state".to_string());
+        }
+        for item in self.states.iter().flatten() {
+            if item.rule >= rules {
+                return out_of_range(format!("rule {} of an item", item.rule));
+            }
+            if item.pos > grammar.rules[item.rule].rhs.len() {
+                return out_of_range(format!("position {} of an item of rule {}", item.pos, item.rule));
+            }
+        }
+        for (state, symbol, actions) in &self.actions {
+            if *state >= states {
+                return out_of_range(format!("state {state} of an action"));
+            }
+            if let Some(symbol) = symbol.filter(|symbol| *symbol >= symbols) {
+                return out_of_range(format!("symbol {symbol} of an action in state {state}"));
+            }
+            for action in actions {
+                match *action {
+                    Action::Shift(to) if to >= states => return out_of_range(format!("state {to} shifted to from state {state}")),
+                    Action::Reduce(rule) if rule >= rules => return out_of_range(format!("rule {rule} reduced in state {state}")),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for ParseTable {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Sort the actions so the same table always serializes the same way.
+        let mut actions: Vec<_> = self
+            .actions
+            .iter()
+            .map(|((state, symbol), actions)| (*state, *symbol, actions.clone()))
+            .collect();
+        actions.sort_by_key(|(state, symbol, _actions)| (*state, *symbol));
+
+        CachedTable {
+            grammar_fingerprint: self.grammar.fingerprint(),
+            states: self.states.clone(),
+            actions,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl ParseTable {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        postcard::to_stdvec(self).unwrap()
+    }
+
+    /// Load a table saved with `to_json`, checking it was built for `grammar`.
+    pub fn from_json(grammar: impl Into<Arc<Grammar>>, json: &str) -> Result<ParseTable, CacheError> {
+        let cached: CachedTable = serde_json::from_str(json)?;
+        Self::from_cached(grammar.into(), cached)
+    }
+
+    /// Load a table saved with `to_bytes`, checking it was built for `grammar`.
+    pub fn from_bytes(grammar: impl Into<Arc<Grammar>>, bytes: &[u8]) -> Result<ParseTable, CacheError> {
+        let cached: CachedTable = postcard::from_bytes(bytes)?;
+        Self::from_cached(grammar.into(), cached)
+    }
+
+    fn from_cached(grammar: Arc<Grammar>, cached: CachedTable) -> Result<ParseTable, CacheError> {
+        let expected = grammar.fingerprint();
+        if cached.grammar_fingerprint != expected {
+            return Err(CacheError::GrammarMismatch {
+                expected,
+                found: cached.grammar_fingerprint,
+            });
+        }
+
+        cached.check_ranges(&grammar)?;
+
+        let actions: HashMap<_, _> = cached
+            .actions
+            .into_iter()
+            .map(|(state, symbol, actions)| ((state, symbol), actions))
+            .collect();
+        let (gotos, halts) = ParseTable::build_gotos(&grammar, cached.states.len(), &actions);
+
+        Ok(ParseTable {
+            grammar,
+            states: cached.states,
+            actions,
+            gotos,
+            halts,
+        })
+    }
+}
@@ -0,0 +1,184 @@
+//! An Earley parser, for grammars which no LR table can parse deterministically.
+//!
+//! The recognizer handles empty rules as Aycock and Horspool do: predicting a nullable nonterminal
+//! also steps over it. Every derivation of the input is then collected into a `sppf::Forest`.
+
+use std::collections::HashMap;
+
+use crate::lookahead::Analysis;
+use crate::sppf::{Forest, NodeIndex};
+use crate::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError<'a> {
+    /// No derivation of the start symbol can continue with the input at `position`.
+    /// `symbol` is `None` when the input ended too early.
+    UnexpectedSymbol { position: usize, symbol: Option<Symbol<'a>> },
+}
+
+impl<'a> std::fmt::Display for ParseError<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::UnexpectedSymbol { position, symbol: Some(symbol) } => {
+                write!(f, "Unexpected {symbol} at token {position}")
+            }
+            ParseError::UnexpectedSymbol { position, symbol: None } => {
+                write!(f, "Unexpected end of input after {position} tokens")
+            }
+        }
+    }
+}
+
+impl<'a> std::error::Error for ParseError<'a> {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Item {
+    rule: RuleIndex,
+    pos: usize,
+    origin: usize,
+}
+
+/// Parse a sequence of terminals, returning the forest of every derivation of the start rule's nonterminal,
+/// and the root node, which spans the whole input.
+/// The forest's leaves are the terminals, where the leaf spanning `i..i + 1` is `input[i]`.
+pub fn parse<'a>(grammar: &'a Grammar, input: &[Symbol<'a>]) -> Result<(Forest<'a>, NodeIndex), ParseError<'a>> {
+    let analysis = Analysis::new(grammar);
+    let input: Vec<SymbolIndex> = input.iter().map(|symbol| symbol.index()).collect();
+    let start = grammar.start_rule().lhs().index();
+
+    let mut sets: Vec<Vec<Item>> = vec![vec![]; input.len() + 1];
+    // The positions each nonterminal was completed at, by the position it was started at.
+    let mut ends: HashMap<(SymbolIndex, usize), Vec<usize>> = HashMap::new();
+
+    let add = |sets: &mut Vec<Vec<Item>>, position: usize, item: Item| {
+        if !sets[position].contains(&item) {
+            sets[position].push(item);
+        }
+    };
+    for &rule in &analysis.rules_for[start] {
+        add(&mut sets, 0, Item { rule, pos: 0, origin: 0 });
+    }
+
+    for position in 0..=input.len() {
+        let mut k = 0;
+        while k < sets[position].len() {
+            let item = sets[position][k];
+            k += 1;
+            let (lhs, rhs) = &analysis.rules[item.rule];
+            let stepped = Item { pos: item.pos + 1, ..item };
+            match rhs.get(item.pos) {
+                None => {
+                    let completed = ends.entry((*lhs, item.origin)).or_default();
+                    if !completed.contains(&position) {
+                        completed.push(position);
+                    }
+                    let waiting: Vec<Item> = sets[item.origin]
+                        .iter()
+                        .filter(|waiting| analysis.rules[waiting.rule].1.get(waiting.pos) == Some(lhs))
+                        .map(|waiting| Item { pos: waiting.pos + 1, ..*waiting })
+                        .collect();
+                    for waiting in waiting {
+                        add(&mut sets, position, waiting);
+                    }
+                }
+                Some(&next) if analysis.nonterminal[next] => {
+                    for &rule in &analysis.rules_for[next] {
+                        add(&mut sets, position, Item { rule, pos: 0, origin: position });
+                    }
+                    if analysis.nullable[next] {
+                        add(&mut sets, position, stepped);
+                    }
+                }
+                Some(&next) => {
+                    if input.get(position) == Some(&next) {
+                        add(&mut sets, position + 1, stepped);
+                    }
+                }
+            }
+        }
+
+        if position < input.len() && sets[position + 1].is_empty() {
+            return Err(ParseError::UnexpectedSymbol {
+                position,
+                symbol: Some(grammar.symbol_at(input[position])),
+            });
+        }
+    }
+
+    if !ends.get(&(start, 0)).is_some_and(|ends| ends.contains(&input.len())) {
+        return Err(ParseError::UnexpectedSymbol {
+            position: input.len(),
+            symbol: None,
+        });
+    }
+
+    let mut builder = ForestBuilder {
+        grammar,
+        analysis: &analysis,
+        input: &input,
+        ends: &ends,
+        forest: Forest::new(),
+    };
+    let root = builder.node(start, 0, input.len());
+    Ok((builder.forest, root))
+}
+
+struct ForestBuilder<'a, 'p> {
+    grammar: &'a Grammar,
+    analysis: &'p Analysis,
+    input: &'p [SymbolIndex],
+    ends: &'p HashMap<(SymbolIndex, usize), Vec<usize>>,
+    forest: Forest<'a>,
+}
+
+impl<'a, 'p> ForestBuilder<'a, 'p> {
+    /// The node for `symbol` spanning `start..end`, with a packed node for each way of deriving it.
+    fn node(&mut self, symbol: SymbolIndex, start: usize, end: usize) -> NodeIndex {
+        let grammar_symbol = self.grammar.symbol_at(symbol);
+        if let Some(node) = self.forest.find(grammar_symbol, start, end) {
+            return node;
+        }
+        let node = self.forest.insert(grammar_symbol, start, end);
+        if !self.analysis.nonterminal[symbol] {
+            return node;
+        }
+
+        for &rule in &self.analysis.rules_for[symbol] {
+            let rhs = &self.analysis.rules[rule].1;
+            for boundaries in self.splits(rhs, start, end) {
+                let mut children = vec![];
+                let mut from = start;
+                for (&child, &to) in rhs.iter().zip(&boundaries) {
+                    children.push(self.node(child, from, to));
+                    from = to;
+                }
+                self.forest.pack(node, self.grammar.rule_at(rule), children);
+            }
+        }
+        node
+    }
+
+    /// Every way of dividing `start..end` between `symbols`, given as the end of each symbol's span.
+    fn splits(&self, symbols: &[SymbolIndex], start: usize, end: usize) -> Vec<Vec<usize>> {
+        let Some((&first, rest)) = symbols.split_first() else {
+            return if start == end { vec![vec![]] } else { vec![] };
+        };
+
+        let middles = if self.analysis.nonterminal[first] {
+            self.ends.get(&(first, start)).cloned().unwrap_or_default()
+        } else if self.input.get(start) == Some(&first) {
+            vec![start + 1]
+        } else {
+            vec![]
+        };
+
+        let mut splits = vec![];
+        for middle in middles.into_iter().filter(|middle| *middle <= end) {
+            for mut split in self.splits(rest, middle, end) {
+                split.insert(0, middle);
+                splits.push(split);
+            }
+        }
+        splits
+    }
+}
@@ -38,21 +38,24 @@ impl GrammarBuilder {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Grammar {
     pub(crate) symbols: Vec<SymbolData>,
     pub(crate) rules: Vec<RuleData>,
 }
 
-type SymbolIndex = usize;
-type RuleIndex = usize;
+pub type SymbolIndex = usize;
+pub type RuleIndex = usize;
 
 #[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RuleData {
     pub(crate) lhs: SymbolIndex,
     pub(crate) rhs: Vec<SymbolIndex>,
 }
 
 #[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SymbolData {
     pub(crate) name: String,
 }
@@ -112,6 +115,14 @@ impl<'a> PartialEq for Symbol<'a> {
 impl<'a> Eq for Symbol<'a> {}
 
 impl<'a> Symbol<'a> {
+    pub fn grammar(&self) -> &'a Grammar {
+        self.grammar
+    }
+
+    pub fn index(&self) -> SymbolIndex {
+        self.index
+    }
+
     pub fn is_terminal(&self) -> bool {
         !self.grammar.rules().iter().any(|rule| rule.lhs() == *self)
     }
@@ -152,6 +163,14 @@ pub struct Item<'a> {
     pos: usize,
 }
 
+/// An `Item` which does not borrow the grammar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ItemData {
+    pub rule: RuleIndex,
+    pub pos: usize,
+}
+
 #[derive(Clone)]
 pub struct ItemSet<'a>(&'a Grammar, Vec<Item<'a>>);
 
@@ -307,6 +326,13 @@ impl<'a> Item<'a> {
     pub fn is_finished(&self) -> bool {
         self.pos() == self.rule().rhs().len()
     }
+
+    pub fn data(&self) -> ItemData {
+        ItemData {
+            rule: self.rule.index(),
+            pos: self.pos,
+        }
+    }
 }
 
 impl<'a> ItemSet<'a> {
@@ -351,6 +377,10 @@ impl<'a> ItemSet<'a> {
         self.1.as_slice()
     }
 
+    pub fn data(&self) -> Vec<ItemData> {
+        self.items().iter().map(Item::data).collect()
+    }
+
     pub(crate) fn closure(&self) -> ItemSet<'a> {
         let mut items_added = true;
         let mut nonterms_added: HashSet<Symbol<'a>> = HashSet::new();
@@ -384,6 +414,7 @@ impl<'a> ItemSet<'a> {
                 }
             }
         }
+        tracing::trace!(kernel = self.items().len(), items = itemset.len(), "closure");
         ItemSet(self.grammar(), itemset)
     }
 
@@ -400,11 +431,54 @@ impl Grammar {
         }
     }
 
-    pub fn start_rule(&self) -> Rule {
+    pub fn start_rule(&self) -> Rule<'_> {
         Rule(self, 0)
     }
 
-    pub fn symbols(&self) -> Vec<Symbol> {
+    pub fn symbol_at(&self, index: SymbolIndex) -> Symbol<'_> {
+        assert!(index < self.symbols.len());
+        Symbol {
+            grammar: self,
+            index,
+        }
+    }
+
+    pub fn rule_at(&self, index: RuleIndex) -> Rule<'_> {
+        assert!(index < self.rules.len());
+        Rule(self, index)
+    }
+
+    /// A hash of the symbols and rules of the grammar.
+    /// This is stable across runs and platforms (FNV-1a), so it can be stored on disk.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+
+        for symbol in &self.symbols {
+            write(&(symbol.name.len() as u64).to_le_bytes());
+            write(symbol.name.as_bytes());
+        }
+        for rule in &self.rules {
+            write(&(rule.lhs as u64).to_le_bytes());
+            write(&(rule.rhs.len() as u64).to_le_bytes());
+            for symbol in &rule.rhs {
+                write(&(*symbol as u64).to_le_bytes());
+            }
+        }
+        hash
+    }
+
+    pub fn item_set(&self, items: &[ItemData]) -> ItemSet<'_> {
+        let items = items.iter().map(|item| self.rule_at(item.rule).item(item.pos)).collect();
+        ItemSet(self, items)
+    }
+
+    pub fn symbols(&self) -> Vec<Symbol<'_>> {
         let mut symbols = vec![];
         for (index, symbol) in self.symbols.iter().enumerate() {
             symbols.push(Symbol {
@@ -415,15 +489,15 @@ impl Grammar {
         symbols
     }
 
-    pub fn terminals(&self) -> Vec<Symbol> {
+    pub fn terminals(&self) -> Vec<Symbol<'_>> {
         self.symbols().into_iter().filter(|symbol| symbol.is_terminal()).collect()
     }
 
-    pub fn nonterminals(&self) -> Vec<Symbol> {
+    pub fn nonterminals(&self) -> Vec<Symbol<'_>> {
         self.symbols().into_iter().filter(|symbol| symbol.is_nonterminal()).collect()
     }
 
-    pub fn symbol(&self, name: &str) -> Option<Symbol> {
+    pub fn symbol(&self, name: &str) -> Option<Symbol<'_>> {
         for (index, symbol_data) in self.symbols.iter().enumerate() {
             if &symbol_data.name == name {
                 return Some(Symbol {
@@ -436,7 +510,7 @@ impl Grammar {
         None
     }
 
-    pub fn rules(&self) -> Vec<Rule> {
+    pub fn rules(&self) -> Vec<Rule<'_>> {
         let mut rules = vec![];
         for i in 0..self.rules.len() {
             rules.push(Rule(self, i));
@@ -454,7 +528,7 @@ impl Grammar {
         rules
     }
 
-    pub fn nullables(&self) -> HashSet<Symbol> {
+    pub fn nullables(&self) -> HashSet<Symbol<'_>> {
         let mut nullables = HashSet::new();
 
         loop {
@@ -476,7 +550,7 @@ impl Grammar {
         nullables
     }
 
-    pub fn first_follows(&self) -> FirstFollows {
+    pub fn first_follows(&self) -> FirstFollows<'_> {
         let mut first_follows = FirstFollows::new(self);
         let nullables = self.nullables();
 
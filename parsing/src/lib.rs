@@ -2,6 +2,14 @@
 mod test;
 
 mod grammar;
+mod token;
 pub mod lr0;
+mod lookahead;
+pub mod earley;
+#[cfg(feature = "serde")]
+pub mod cache;
+pub mod sppf;
+pub mod trace;
 
 pub use grammar::*;
+pub use token::*;
@@ -0,0 +1,237 @@
+//! Lookahead sets for the reductions of SLR(1) and LALR(1) tables.
+//!
+//! Both start from the LR(0) table and only decide which symbols each reduction is kept on.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::lr0::{Action, ParseTable, StateIndex};
+use crate::*;
+
+/// For each state and rule reduced in it, the terminals (or `None`, the end of input) it may be reduced on.
+pub(crate) type Lookaheads = HashMap<(StateIndex, RuleIndex), HashSet<Option<SymbolIndex>>>;
+
+/// The grammar flattened to indices, with FIRST and NULLABLE of every nonterminal.
+pub(crate) struct Analysis {
+    pub(crate) rules: Vec<(SymbolIndex, Vec<SymbolIndex>)>,
+    pub(crate) rules_for: Vec<Vec<RuleIndex>>,
+    pub(crate) nonterminal: Vec<bool>,
+    pub(crate) nullable: Vec<bool>,
+    first: Vec<HashSet<SymbolIndex>>,
+}
+
+impl Analysis {
+    pub(crate) fn new(grammar: &Grammar) -> Analysis {
+        let n = grammar.symbols.len();
+        let rules: Vec<_> = grammar.rules.iter().map(|rule| (rule.lhs, rule.rhs.clone())).collect();
+        let mut rules_for = vec![vec![]; n];
+        let mut nonterminal = vec![false; n];
+        for (index, (lhs, _rhs)) in rules.iter().enumerate() {
+            rules_for[*lhs].push(index);
+            nonterminal[*lhs] = true;
+        }
+
+        let mut analysis = Analysis {
+            rules,
+            rules_for,
+            nonterminal,
+            nullable: vec![false; n],
+            first: vec![HashSet::new(); n],
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (lhs, rhs) in &analysis.rules {
+                let (first, nullable) = analysis.first_of(rhs);
+                if nullable && !analysis.nullable[*lhs] {
+                    analysis.nullable[*lhs] = true;
+                    changed = true;
+                }
+                let before = analysis.first[*lhs].len();
+                analysis.first[*lhs].extend(first);
+                changed |= analysis.first[*lhs].len() != before;
+            }
+        }
+        analysis
+    }
+
+    /// The terminals which can begin `symbols`, and whether they can derive nothing.
+    fn first_of(&self, symbols: &[SymbolIndex]) -> (HashSet<SymbolIndex>, bool) {
+        let mut first = HashSet::new();
+        for &symbol in symbols {
+            if !self.nonterminal[symbol] {
+                first.insert(symbol);
+                return (first, false);
+            }
+            first.extend(&self.first[symbol]);
+            if !self.nullable[symbol] {
+                return (first, false);
+            }
+        }
+        (first, true)
+    }
+
+    /// FOLLOW of every nonterminal, with `None` for the end of input.
+    fn follow(&self) -> Vec<HashSet<Option<SymbolIndex>>> {
+        let mut follow = vec![HashSet::new(); self.nonterminal.len()];
+        follow[self.rules[0].0].insert(None);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (lhs, rhs) in &self.rules {
+                for (i, &symbol) in rhs.iter().enumerate() {
+                    if !self.nonterminal[symbol] {
+                        continue;
+                    }
+                    let before = follow[symbol].len();
+                    let (first, nullable) = self.first_of(&rhs[i + 1..]);
+                    follow[symbol].extend(first.into_iter().map(Some));
+                    if nullable && symbol != *lhs {
+                        let lhs_follow = follow[*lhs].clone();
+                        follow[symbol].extend(lhs_follow);
+                    }
+                    changed |= follow[symbol].len() != before;
+                }
+            }
+        }
+        follow
+    }
+
+    /// The LR(1) closure of a single item, whose lookahead is `Marker::Propagate`.
+    fn closure(&self, item: ItemData) -> HashSet<(ItemData, Marker)> {
+        let mut closure = HashSet::new();
+        let mut remaining = vec![(item, Marker::Propagate)];
+        while let Some((item, marker)) = remaining.pop() {
+            if !closure.insert((item, marker)) {
+                continue;
+            }
+            let rhs = &self.rules[item.rule].1;
+            let Some(&next) = rhs.get(item.pos) else { continue };
+            if !self.nonterminal[next] {
+                continue;
+            }
+
+            let (first, nullable) = self.first_of(&rhs[item.pos + 1..]);
+            let mut markers: Vec<Marker> = first.into_iter().map(|symbol| Marker::Symbol(Some(symbol))).collect();
+            if nullable {
+                markers.push(marker);
+            }
+            for &rule in &self.rules_for[next] {
+                for &marker in &markers {
+                    remaining.push((ItemData { rule, pos: 0 }, marker));
+                }
+            }
+        }
+        closure
+    }
+}
+
+/// A lookahead in an LR(1) closure: either a symbol, or a stand-in for whatever follows the kernel item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Marker {
+    Symbol(Option<SymbolIndex>),
+    Propagate,
+}
+
+/// Reduce each rule on the FOLLOW set of its nonterminal.
+pub(crate) fn slr(table: &ParseTable) -> Lookaheads {
+    let analysis = Analysis::new(table.grammar());
+    let follow = analysis.follow();
+
+    let mut lookaheads = Lookaheads::new();
+    for (state, items) in table.states.iter().enumerate() {
+        for item in items {
+            let (lhs, rhs) = &analysis.rules[item.rule];
+            if item.pos == rhs.len() {
+                lookaheads.insert((state, item.rule), follow[*lhs].clone());
+            }
+        }
+    }
+    lookaheads
+}
+
+/// Reduce each rule on the LALR(1) lookaheads of its item,
+/// found by propagating lookaheads between kernel items as in the dragon book (section 4.7.5).
+pub(crate) fn lalr(table: &ParseTable) -> Lookaheads {
+    let analysis = Analysis::new(table.grammar());
+
+    let mut kernels = vec![];
+    let mut kernel_index = HashMap::new();
+    for (state, items) in table.states.iter().enumerate() {
+        for item in items {
+            if item.pos > 0 || (state == 0 && item.rule == 0) {
+                kernel_index.insert((state, *item), kernels.len());
+                kernels.push((state, *item));
+            }
+        }
+    }
+
+    let goto = |state: StateIndex, symbol: SymbolIndex| {
+        table.actions[&(state, Some(symbol))]
+            .iter()
+            .find_map(|action| match action {
+                Action::Shift(state) => Some(*state),
+                _ => None,
+            })
+            .expect("every item with a next symbol has a transition on it")
+    };
+
+    // The lookaheads of each kernel item, and the kernel items each one passes its lookaheads on to.
+    let mut kernel_lookaheads: Vec<HashSet<Option<SymbolIndex>>> = vec![HashSet::new(); kernels.len()];
+    let mut propagates_to: Vec<Vec<usize>> = vec![vec![]; kernels.len()];
+    kernel_lookaheads[kernel_index[&(0, ItemData { rule: 0, pos: 0 })]].insert(None);
+
+    let mut lookaheads = Lookaheads::new();
+    // The kernel items whose lookaheads are also those of a reduction.
+    let mut reductions_from: HashMap<(StateIndex, RuleIndex), Vec<usize>> = HashMap::new();
+
+    for (kernel, (state, item)) in kernels.iter().enumerate() {
+        for (closure_item, marker) in analysis.closure(*item) {
+            let rhs = &analysis.rules[closure_item.rule].1;
+            if let Some(&next) = rhs.get(closure_item.pos) {
+                let target = ItemData {
+                    rule: closure_item.rule,
+                    pos: closure_item.pos + 1,
+                };
+                let target = kernel_index[&(goto(*state, next), target)];
+                match marker {
+                    Marker::Symbol(symbol) => {
+                        kernel_lookaheads[target].insert(symbol);
+                    }
+                    Marker::Propagate => propagates_to[kernel].push(target),
+                }
+            } else {
+                let key = (*state, closure_item.rule);
+                match marker {
+                    Marker::Symbol(symbol) => {
+                        lookaheads.entry(key).or_default().insert(symbol);
+                    }
+                    Marker::Propagate => reductions_from.entry(key).or_default().push(kernel),
+                }
+            }
+        }
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for kernel in 0..kernels.len() {
+            for &target in &propagates_to[kernel] {
+                let new: Vec<_> = kernel_lookaheads[kernel].difference(&kernel_lookaheads[target]).copied().collect();
+                if !new.is_empty() {
+                    kernel_lookaheads[target].extend(new);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    for (key, kernels) in reductions_from {
+        let set = lookaheads.entry(key).or_default();
+        for kernel in kernels {
+            set.extend(&kernel_lookaheads[kernel]);
+        }
+    }
+    lookaheads
+}
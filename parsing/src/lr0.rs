@@ -1,68 +1,308 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{collections::HashMap, convert::Infallible, marker::PhantomData, rc::Rc, sync::Arc, time::{Duration, Instant}};
 
 use crate::*;
+use crate::lookahead;
 
 pub type State<'a> = ItemSet<'a>;
 
+/// For each state, the state to go to after reducing to each nonterminal, sorted by nonterminal.
+type Gotos = Vec<Vec<(SymbolIndex, StateIndex)>>;
+
+/// A node in the parse tree built by `Machine`.
+/// Leaves carry the token they were shifted from.
 #[derive(Clone)]
-struct Node<'a>(Rc<NodeData<'a>>);
+pub struct Node<'a, 's>(Rc<NodeData<'a, 's>>);
 
 #[derive(Debug)]
-struct NodeData<'a> {
-    symbol: Symbol<'a>,
-    children: Vec<Node<'a>>,
+pub struct NodeData<'a, 's> {
+    pub symbol: Symbol<'a>,
+    pub children: Vec<Node<'a, 's>>,
+    pub token: Option<Token<'a, 's>>,
+    /// The rule this node was reduced by. `None` for leaves.
+    pub rule: Option<Rule<'a>>,
 }
 
-impl<'a> std::ops::Deref for Node<'a> {
-    type Target = NodeData<'a>;
+impl<'a, 's> std::ops::Deref for Node<'a, 's> {
+    type Target = NodeData<'a, 's>;
 
     fn deref(&self) -> &Self::Target {
         self.0.as_ref()
     }
 }
 
-impl<'a> From<NodeData<'a>> for Node<'a> {
-    fn from(value: NodeData<'a>) -> Self {
+impl<'a, 's> From<NodeData<'a, 's>> for Node<'a, 's> {
+    fn from(value: NodeData<'a, 's>) -> Self {
         Node(Rc::new(value))
     }
 }
 
-impl<'a> std::fmt::Debug for Node<'a> {
+/// Trees from left-recursive rules are as deep as the input,
+/// so they are torn down from a worklist rather than by recursing into each child.
+impl<'a, 's> Drop for Node<'a, 's> {
+    fn drop(&mut self) {
+        let mut orphans = match Rc::get_mut(&mut self.0) {
+            Some(data) => std::mem::take(&mut data.children),
+            None => return,
+        };
+        while let Some(mut node) = orphans.pop() {
+            if let Some(data) = Rc::get_mut(&mut node.0) {
+                orphans.append(&mut data.children);
+            }
+        }
+    }
+}
+
+impl<'a, 's> From<Token<'a, 's>> for Node<'a, 's> {
+    fn from(token: Token<'a, 's>) -> Self {
+        NodeData {
+            symbol: token.terminal,
+            children: vec![],
+            token: Some(token),
+            rule: None,
+        }.into()
+    }
+}
+
+impl<'a, 's> Node<'a, 's> {
+    /// The span of source covered by the tokens under this node.
+    /// Returns `None` if no tokens were consumed (eg, for an empty rule).
+    pub fn span(&self) -> Option<Span> {
+        if let Some(token) = &self.token {
+            return Some(token.span.clone());
+        }
+        let start = self.children.iter().find_map(|child| child.span())?.start;
+        let end = self.children.iter().rev().find_map(|child| child.span())?.end;
+        Some(start..end)
+    }
+
+    /// The tokens under this node, from left to right.
+    pub fn tokens(&self) -> Vec<&Token<'a, 's>> {
+        let mut tokens = vec![];
+        if let Some(token) = &self.token {
+            tokens.push(token);
+        }
+        for child in &self.children {
+            tokens.extend(child.tokens());
+        }
+        tokens
+    }
+}
+
+/// Prints the tree as an s-expression, eg `(circuit version newline ...)`.
+impl<'a, 's> std::fmt::Debug for Node<'a, 's> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0.symbol)
+        if self.token.is_some() {
+            write!(f, "{}", self.symbol)
+        } else {
+            write!(f, "({}", self.symbol)?;
+            for child in &self.children {
+                write!(f, " {child:?}")?;
+            }
+            write!(f, ")")
+        }
+    }
+}
+
+/// How a `Machine` builds its tree, for callers who want nodes other than `Node`.
+pub trait TreeBuilder<'t, 's> {
+    type Node;
+
+    fn symbol(node: &Self::Node) -> Symbol<'t>;
+    /// A leaf for `token`, shifted in `state`.
+    fn leaf(token: Token<'t, 's>, state: StateIndex) -> Self::Node;
+    /// A node for `rule` over `children`, pushed from `state`, the state under its children.
+    fn branch(rule: Rule<'t>, children: Vec<Self::Node>, state: StateIndex) -> Self::Node;
+}
+
+/// Builds the tree of `Node`s a `Machine` makes by default.
+#[derive(Debug, Clone)]
+pub struct NodeBuilder;
+
+impl<'t, 's> TreeBuilder<'t, 's> for NodeBuilder {
+    type Node = Node<'t, 's>;
+
+    fn symbol(node: &Node<'t, 's>) -> Symbol<'t> {
+        node.symbol
+    }
+
+    fn leaf(token: Token<'t, 's>, _state: StateIndex) -> Node<'t, 's> {
+        token.into()
+    }
+
+    fn branch(rule: Rule<'t>, children: Vec<Node<'t, 's>>, _state: StateIndex) -> Node<'t, 's> {
+        NodeData {
+            symbol: rule.lhs(),
+            children,
+            token: None,
+            rule: Some(rule),
+        }
+        .into()
     }
 }
 
 pub type StateIndex = usize;
 
+/// An LR(0) parse table.
+///
+/// The table owns its grammar and refers to symbols, rules, and items only by index,
+/// so it has no lifetime and can be stored alongside the grammar, cached, or shared between threads.
+/// Use `grammar()` to get borrowed views like `Symbol` and `Rule` back out of it.
 #[derive(Debug)]
-pub struct ParseTable<'a> {
-    pub grammar: &'a Grammar,
-    pub states: Vec<ItemSet<'a>>,
-    pub actions: HashMap<(StateIndex, Option<Symbol<'a>>), Vec<Action<'a>>>,
+pub struct ParseTable {
+    pub grammar: Arc<Grammar>,
+    pub states: Vec<Vec<ItemData>>,
+    pub actions: HashMap<(StateIndex, Option<SymbolIndex>), Vec<Action>>,
+    /// The shifts on nonterminals in `actions`, which reductions look up here instead.
+    pub(crate) gotos: Gotos,
+    /// Where reducing to a nonterminal halts the machine instead.
+    pub(crate) halts: Vec<(StateIndex, SymbolIndex)>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Action<'a> {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Action {
     Shift(StateIndex),
-    Reduce(Rule<'a>),
+    Reduce(RuleIndex),
     Halt,
 }
 
-impl<'a> ParseTable<'a> {
-    pub fn new(grammar: &'a Grammar) -> ParseTable<'a> {
-        tracing::info!("Here");
-        let states = Self::build_states(&grammar);
-        let actions = Self::build_actions(&grammar, &states);
+impl ParseTable {
+    pub fn new(grammar: impl Into<Arc<Grammar>>) -> ParseTable {
+        let grammar = grammar.into();
+        let _span = tracing::info_span!("parse_table", symbols = grammar.symbols.len(), rules = grammar.rules.len()).entered();
+        let states = tracing::debug_span!("goto").in_scope(|| Self::build_states(&grammar));
+        let actions = tracing::debug_span!("action_fill").in_scope(|| Self::build_actions(&grammar, &states));
+        tracing::info!(states = states.len(), "built");
+        let (gotos, halts) = Self::build_gotos(&grammar, states.len(), &actions);
+
+        ParseTable {
+            states: states.iter().map(ItemSet::data).collect(),
+            actions,
+            gotos,
+            halts,
+            grammar,
+        }
+    }
+
+    /// An SLR(1) table: reductions are only made on the terminals which can follow the rule's nonterminal.
+    pub fn slr(grammar: impl Into<Arc<Grammar>>) -> ParseTable {
+        let mut table = Self::new(grammar);
+        let lookaheads = tracing::debug_span!("lookaheads", kind = "slr").in_scope(|| lookahead::slr(&table));
+        table.restrict_reductions(&lookaheads);
+        table
+    }
+
+    /// An LALR(1) table: reductions are only made on the lookaheads of their item.
+    pub fn lalr(grammar: impl Into<Arc<Grammar>>) -> ParseTable {
+        let mut table = Self::new(grammar);
+        let lookaheads = tracing::debug_span!("lookaheads", kind = "lalr").in_scope(|| lookahead::lalr(&table));
+        table.restrict_reductions(&lookaheads);
+        table
+    }
+
+    /// Drop every reduction whose symbol is not among its lookaheads.
+    fn restrict_reductions(&mut self, lookaheads: &lookahead::Lookaheads) {
+        let mut dropped = 0;
+        for ((state, symbol), actions) in &mut self.actions {
+            let before = actions.len();
+            actions.retain(|action| match action {
+                Action::Reduce(rule) => lookaheads.get(&(*state, *rule)).is_some_and(|set| set.contains(symbol)),
+                _ => true,
+            });
+            dropped += before - actions.len();
+        }
+        tracing::debug!(dropped, "restricted reductions to their lookaheads");
+    }
+
+    /// Assemble a table whose states and actions were built elsewhere, eg at compile time.
+    /// Only the non-empty entries of `actions` need be given.
+    pub fn from_parts(
+        grammar: impl Into<Arc<Grammar>>,
+        states: Vec<Vec<ItemData>>,
+        actions: impl IntoIterator<Item = ((StateIndex, Option<SymbolIndex>), Vec<Action>)>,
+    ) -> ParseTable {
+        let grammar = grammar.into();
+        let mut all_actions = HashMap::new();
+        for state_index in 0..states.len() {
+            for symbol in grammar.symbols() {
+                all_actions.insert((state_index, Some(symbol.index())), vec![]);
+            }
+            all_actions.insert((state_index, None), vec![]);
+        }
+        all_actions.extend(actions);
+        let (gotos, halts) = Self::build_gotos(&grammar, states.len(), &all_actions);
 
         ParseTable {
             grammar,
             states,
-            actions,
+            actions: all_actions,
+            gotos,
+            halts,
+        }
+    }
+
+    pub fn grammar(&self) -> &Grammar {
+        &self.grammar
+    }
+
+    /// The items of the state at `index`.
+    pub fn state(&self, index: StateIndex) -> State<'_> {
+        self.grammar.item_set(&self.states[index])
+    }
+
+    /// The actions available in `state` on `symbol`. `None` is the end of input.
+    pub fn actions(&self, state: StateIndex, symbol: Option<Symbol>) -> &[Action] {
+        self.actions
+            .get(&(state, symbol.map(|symbol| symbol.index())))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// The action `Machine` takes in `state` on `symbol`. On a conflict, it prefers to shift.
+    pub fn action(&self, state: StateIndex, symbol: Option<Symbol>) -> Option<Action> {
+        let actions = self.actions(state, symbol);
+        actions.iter().find(|action| matches!(action, Action::Shift(_))).or(actions.first()).copied()
+    }
+
+    /// The state to go to from `state` after reducing to `nonterminal`.
+    pub fn goto(&self, state: StateIndex, nonterminal: Symbol) -> Option<StateIndex> {
+        let gotos = self.gotos.get(state)?;
+        let index = gotos.binary_search_by_key(&nonterminal.index(), |(symbol, _state)| *symbol).ok()?;
+        Some(gotos[index].1)
+    }
+
+    /// Whether reducing to `nonterminal` when the stack is back to `state` accepts the input.
+    pub fn halts(&self, state: StateIndex, nonterminal: Symbol) -> bool {
+        self.halts.contains(&(state, nonterminal.index()))
+    }
+
+    /// Split the shifts and halts on nonterminals out of `actions`.
+    pub(crate) fn build_gotos(
+        grammar: &Grammar,
+        states: usize,
+        actions: &HashMap<(StateIndex, Option<SymbolIndex>), Vec<Action>>,
+    ) -> (Gotos, Vec<(StateIndex, SymbolIndex)>) {
+        let mut gotos = vec![vec![]; states];
+        let mut halts = vec![];
+        for ((state, symbol), actions) in actions {
+            let Some(symbol) = symbol.filter(|symbol| !grammar.symbol_at(*symbol).is_terminal()) else {
+                continue;
+            };
+            for action in actions {
+                match action {
+                    Action::Shift(to) => gotos[*state].push((symbol, *to)),
+                    Action::Halt => halts.push((*state, symbol)),
+                    Action::Reduce(_) => {}
+                }
+            }
         }
+        for gotos in &mut gotos {
+            gotos.sort_unstable();
+        }
+        (gotos, halts)
     }
 
-    fn build_states(grammar: &'a Grammar) -> Vec<State<'a>> {
+    fn build_states(grammar: &Grammar) -> Vec<State<'_>> {
         let mut states = vec![];
 
         // We crated a start state for the non-terminal that appears in the first rule.
@@ -78,6 +318,7 @@ impl<'a> ParseTable<'a> {
             // This pushes the . in the items, removing any items which go past the end of the rule.
             for symbol in grammar.symbols() {
                 let next_state = state.follow(symbol);
+                tracing::trace!(symbol = %symbol, items = next_state.items().len(), "goto");
 
                 // ignore the "empty" state
                 // this would be if we have an item with rules like `A -> B . a C`
@@ -92,14 +333,15 @@ impl<'a> ParseTable<'a> {
                 }
             }
 
+            tracing::debug!(state = states.len(), items = state.items().len(), "state");
             states.push(state);
         }
 
         states
     }
 
-    fn build_actions(grammar: &'a Grammar, states: &[State<'a>]) -> HashMap<(StateIndex, Option<Symbol<'a>>), Vec<Action<'a>>> {
-        // actions[(state_i, Some(symbol)] is a list of all Actions that can be taken 
+    fn build_actions(grammar: &Grammar, states: &[State]) -> HashMap<(StateIndex, Option<SymbolIndex>), Vec<Action>> {
+        // actions[(state_i, Some(symbol)] is a list of all Actions that can be taken
         // when the machine is in state `state_i` and the nekxt input is `symbol`.
         // (`None` represents the end of input).
         let mut actions = HashMap::new();
@@ -107,7 +349,7 @@ impl<'a> ParseTable<'a> {
         // Pre-allocate an empty list for all (state_i, maybe_symbol)-pairs.
         for (i, _src_state) in states.iter().enumerate() {
             for symbol in grammar.symbols() {
-                actions.insert((i, Some(symbol)), vec![]);
+                actions.insert((i, Some(symbol.index())), vec![]);
             }
             actions.insert((i, None), vec![]);
         }
@@ -117,28 +359,30 @@ impl<'a> ParseTable<'a> {
                 match src_item.next_symbol() {
                     Some(symbol) => {
                         let dst_state = src_state.follow(symbol);
-                        let dst_state_index = Self::state_index(&dst_state, &states);
-                        let mut actions = actions.get_mut(&(src_state_index, Some(symbol))).unwrap();
+                        let dst_state_index = Self::state_index(&dst_state, states);
+                        let actions = actions.get_mut(&(src_state_index, Some(symbol.index()))).unwrap();
                         let action = Action::Shift(dst_state_index);
                         if !actions.contains(&action) {
+                            tracing::trace!(state = src_state_index, symbol = %symbol, action = ?action, "action");
                             actions.push(action);
                         }
                     }
                     None => {
-                        for symbol in src_item.rule().grammar().symbols() {
-                            let mut actions = actions.get_mut(&(src_state_index, Some(symbol))).unwrap();
-                            actions.push(Action::Reduce(src_item.rule()));
+                        tracing::trace!(state = src_state_index, action = ?Action::Reduce(src_item.rule().index()), "action");
+                        for symbol in grammar.symbols() {
+                            let actions = actions.get_mut(&(src_state_index, Some(symbol.index()))).unwrap();
+                            actions.push(Action::Reduce(src_item.rule().index()));
                         }
 
                         // End of input
-                        let mut actions = actions.get_mut(&(src_state_index, None)).unwrap();
-                        actions.push(Action::Reduce(src_item.rule()));
+                        let actions = actions.get_mut(&(src_state_index, None)).unwrap();
+                        actions.push(Action::Reduce(src_item.rule().index()));
                     }
                 }
             }
         }
 
-        actions.get_mut(&(0, Some(grammar.start_rule().lhs()))).unwrap().insert(0, Action::Halt);
+        actions.get_mut(&(0, Some(grammar.start_rule().lhs().index()))).unwrap().insert(0, Action::Halt);
 
         actions
     }
@@ -151,13 +395,14 @@ impl<'a> ParseTable<'a> {
             .unwrap()
     }
 
-    pub fn conflicts(&self) -> Vec<Conflict> {
+    pub fn conflicts(&self) -> Vec<Conflict<'_>> {
         let mut conflicts = vec![];
         for (state_index, _state) in self.states.iter().enumerate() {
             for symbol in self.grammar.symbols() {
-                let actions = &self.actions[&(state_index, Some(symbol))];
+                let actions = self.actions(state_index, Some(symbol));
                 if actions.len() > 1 {
                     conflicts.push(Conflict {
+                        table: self,
                         state: state_index,
                         symbol: Some(symbol),
                         actions: actions.to_vec(),
@@ -165,9 +410,10 @@ impl<'a> ParseTable<'a> {
                 }
             }
 
-            let actions = &self.actions[&(state_index, None)];
+            let actions = self.actions(state_index, None);
             if actions.len() > 1 {
                 conflicts.push(Conflict {
+                    table: self,
                     state: state_index,
                     symbol: None,
                     actions: actions.to_vec(),
@@ -178,114 +424,505 @@ impl<'a> ParseTable<'a> {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct Conflict<'a> {
-    state: StateIndex,
-    symbol: Option<Symbol<'a>>,
-    actions: Vec<Action<'a>>,
+#[derive(Clone)]
+pub struct Conflict<'t> {
+    table: &'t ParseTable,
+    pub state: StateIndex,
+    pub symbol: Option<Symbol<'t>>,
+    pub actions: Vec<Action>,
 }
 
-#[derive(Debug)]
-pub struct Machine<'a, 'b> {
-    parse_table: &'b ParseTable<'a>,
-    head: Vec<Symbol<'a>>,
-    stack: Vec<(StateIndex, Symbol<'a>)>,
+impl<'t> std::fmt::Debug for Conflict<'t> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let grammar = self.table.grammar();
+        write!(f, "Conflict {{ state: {}, symbol: {:?}, actions: [", self.state, self.symbol)?;
+        for (i, action) in self.actions.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match action {
+                Action::Reduce(rule) => write!(f, "Reduce({:?})", grammar.rule_at(*rule))?,
+                action => write!(f, "{action:?}")?,
+            }
+        }
+        write!(f, "] }}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The machine is waiting for more input.
+    Pending,
+    /// The machine has accepted the input.
+    Halted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError<'a, E = Infallible> {
+    /// There is no action for `symbol` in `state`. `None` is the end of input.
+    UnexpectedSymbol {
+        state: StateIndex,
+        symbol: Option<Symbol<'a>>,
+        step: usize,
+    },
+    /// The machine halted before consuming `symbol`.
+    TrailingInput(Symbol<'a>),
+    /// A symbol was fed to the machine after it halted.
+    AlreadyHalted,
+    /// The parse went past one of the machine's `Limits`.
+    LimitExceeded { limit: Limit, step: usize },
+    /// `rule` was reduced in `state` with fewer symbols on the stack than its right-hand side,
+    /// which only a corrupt table can do.
+    StackUnderflow { state: StateIndex, rule: RuleIndex, step: usize },
+    /// The token source failed to produce the next token.
+    Input(E),
+}
+
+impl<'a> ParseError<'a> {
+    /// Widen an error raised by the machine itself to one from a run over a fallible source.
+    fn into_input<E>(self) -> ParseError<'a, E> {
+        match self {
+            ParseError::UnexpectedSymbol { state, symbol, step } => ParseError::UnexpectedSymbol { state, symbol, step },
+            ParseError::TrailingInput(symbol) => ParseError::TrailingInput(symbol),
+            ParseError::AlreadyHalted => ParseError::AlreadyHalted,
+            ParseError::LimitExceeded { limit, step } => ParseError::LimitExceeded { limit, step },
+            ParseError::StackUnderflow { state, rule, step } => ParseError::StackUnderflow { state, rule, step },
+            ParseError::Input(never) => match never {},
+        }
+    }
+}
+
+impl<'a, E: std::fmt::Display> std::fmt::Display for ParseError<'a, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::UnexpectedSymbol { state, symbol: Some(symbol), step } => {
+                write!(f, "Unexpected {symbol} in state {state} (step {step})")
+            }
+            ParseError::UnexpectedSymbol { state, symbol: None, step } => {
+                write!(f, "Unexpected end of input in state {state} (step {step})")
+            }
+            ParseError::TrailingInput(symbol) => write!(f, "Input continues after the parse halted: {symbol}"),
+            ParseError::AlreadyHalted => write!(f, "The machine has already halted"),
+            ParseError::LimitExceeded { limit, step } => write!(f, "Exceeded {limit} (step {step})"),
+            ParseError::StackUnderflow { state, rule, step } => {
+                write!(f, "Reduced rule {rule} in state {state} with too few symbols on the stack (step {step})")
+            }
+            ParseError::Input(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl<'a, E: std::error::Error> std::error::Error for ParseError<'a, E> {}
+
+/// One of the `Limits`, with the value it was set to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Depth(usize),
+    Tokens(usize),
+    Steps(usize),
+    Time(Duration),
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::Depth(depth) => write!(f, "the maximum stack depth of {depth}"),
+            Limit::Tokens(tokens) => write!(f, "the maximum of {tokens} tokens"),
+            Limit::Steps(steps) => write!(f, "the maximum of {steps} steps"),
+            Limit::Time(budget) => write!(f, "the time budget of {budget:?}"),
+        }
+    }
+}
+
+/// Bounds on the resources a `Machine` may use, for parsing input which can't be trusted.
+/// Nothing is limited by default.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Limits {
+    depth: Option<usize>,
+    tokens: Option<usize>,
+    steps: Option<usize>,
+    /// The budget, and how many steps to take between checking it.
+    time: Option<(Duration, usize)>,
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Limits::default()
+    }
+
+    /// Fail rather than shift onto a stack already `depth` nodes deep.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Fail rather than take more than `tokens` tokens.
+    pub fn max_tokens(mut self, tokens: usize) -> Self {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    /// Fail rather than take more than `steps` steps.
+    pub fn max_steps(mut self, steps: usize) -> Self {
+        self.steps = Some(steps);
+        self
+    }
+
+    /// Fail once `budget` has passed since the first step.
+    /// The clock is only read every `every` steps, so the parse may run over by that many.
+    pub fn time_budget(mut self, budget: Duration, every: usize) -> Self {
+        self.time = Some((budget, every.max(1)));
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Machine<'t, 's, B: TreeBuilder<'t, 's> = NodeBuilder> {
+    parse_table: &'t ParseTable,
+    /// The token the last reduction was made on, which the machine steps on again before taking more input.
+    lookahead: Option<Token<'t, 's>>,
+    stack: Vec<(StateIndex, B::Node)>,
+    tree: Option<B::Node>,
     halted: bool,
     step: usize,
+    limits: Limits,
+    /// How many tokens the machine has taken.
+    tokens: usize,
+    /// When the first step was taken, if the time is limited.
+    started: Option<Instant>,
+    builder: PhantomData<B>,
+}
+
+impl<'t, 's> Machine<'t, 's> {
+    pub fn new(parse_table: &'t ParseTable) -> Machine<'t, 's> {
+        Machine::with_limits(parse_table, Limits::new())
+    }
+
+    pub fn with_limits(parse_table: &'t ParseTable, limits: Limits) -> Machine<'t, 's> {
+        Machine::with_builder(parse_table, limits)
+    }
 }
 
-impl<'a, 'b> Machine<'a, 'b> {
-    pub fn new(parse_table: &'b ParseTable<'a>) -> Machine<'a, 'b> {
+impl<'t, 's, B: TreeBuilder<'t, 's>> Machine<'t, 's, B> {
+    /// A machine which builds its tree with `B`.
+    pub fn with_builder(parse_table: &'t ParseTable, limits: Limits) -> Machine<'t, 's, B> {
         Machine {
             parse_table,
-            head: vec![],
+            lookahead: None,
             stack: vec![],
+            tree: None,
             halted: false,
             step: 0,
+            limits,
+            tokens: 0,
+            started: None,
+            builder: PhantomData,
         }
     }
 
-    fn state(&self) -> StateIndex {
+    /// The parse tree, once the machine has halted.
+    pub fn tree(&self) -> Option<&B::Node> {
+        self.tree.as_ref()
+    }
+
+    pub fn state(&self) -> StateIndex {
         self.stack.last().map(|(state_index, _sym)| *state_index).unwrap_or(0)
     }
 
-    fn step(&mut self, symbol: Option<Symbol<'a>>) {
-        let state = self.state();
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// The states and nodes on the stack, from the bottom.
+    pub fn stack(&self) -> &[(StateIndex, B::Node)] {
+        &self.stack
+    }
+
+    /// The token the last reduction was made on, which is the next the machine steps on.
+    pub fn lookahead(&self) -> Option<&Token<'t, 's>> {
+        self.lookahead.as_ref()
+    }
 
-        #[cfg(feature = "debug")]
-        {
-            eprintln!("STEP:   {:?}", self.step);
-            eprintln!("SYMBOL: {:?}", symbol);
-            eprintln!("STACK:  {:?}", &self.stack);
-            eprintln!("STATE:  {:?}", state);
+    /// How many steps the machine has taken.
+    pub fn steps(&self) -> usize {
+        self.step
+    }
+
+    /// The terminals the machine can take next without an error.
+    ///
+    /// A terminal may only be shifted after the machine reduces on it, and in an LR(0) table
+    /// a state with a reduction has it on every terminal, so each terminal is tried on the stack's states:
+    /// first stepping over the lookahead left by a reduction, then reducing until the terminal is shifted or fails.
+    pub fn expected_terminals(&self) -> Vec<Symbol<'t>> {
+        let grammar = self.parse_table.grammar();
+        let lookahead = self.lookahead.as_ref().map(|token| token.terminal);
+        grammar
+            .terminals()
+            .into_iter()
+            .filter(|terminal| !self.halted && self.accepts(lookahead.into_iter().chain([*terminal])))
+            .collect()
+    }
 
-            let state_rep = format!("{:?}", &self.parse_table.states[state]);
-            for line in state_rep.lines() {
-                eprintln!("    {line}");
+    /// Whether the machine would shift every one of `terminals` in turn, without changing the machine.
+    fn accepts(&self, terminals: impl IntoIterator<Item = Symbol<'t>>) -> bool {
+        let grammar = self.parse_table.grammar();
+        let mut states: Vec<StateIndex> = self.stack.iter().map(|(state, _node)| *state).collect();
+        for terminal in terminals {
+            loop {
+                let state = states.last().copied().unwrap_or(0);
+                match self.parse_table.action(state, Some(terminal)) {
+                    Some(Action::Shift(next)) => {
+                        states.push(next);
+                        break;
+                    }
+                    Some(Action::Reduce(rule)) => {
+                        let rule = grammar.rule_at(rule);
+                        states.truncate(states.len().saturating_sub(rule.rhs().len()));
+                        let below = states.last().copied().unwrap_or(0);
+                        // Without a goto the machine halts or fails, and either way the terminal is never shifted.
+                        match self.parse_table.goto(below, rule.lhs()) {
+                            Some(next) => states.push(next),
+                            None => return false,
+                        }
+                    }
+                    Some(Action::Halt) | None => return false,
+                }
             }
         }
+        true
+    }
 
-        let actions = &self.parse_table.actions.get(&(state, symbol));
+    fn exceeded(&self, limit: Limit) -> ParseError<'t> {
+        tracing::debug!(step = self.step, limit = %limit, "limit exceeded");
+        ParseError::LimitExceeded { limit, step: self.step }
+    }
 
-        if let Some(actions) = actions {
-            #[cfg(feature = "debug")]
-            eprintln!("ACTIONS:  {:?}", &actions);
-            // TODO
-            // assert_eq!(actions.len(), 1, "Available actions: {actions:?}");
-            let action: Action = if actions.len() == 0 {
-                panic!("Machine halted unexpectedly");
-            } else if actions.len() == 1 {
-                 actions[0].clone()
-            } else {
-                *actions.into_iter().cloned().filter(|action| matches!(action, Action::Shift(_))).collect::<Vec<_>>().first().unwrap()
-            };
+    /// Count a token taken from the input against the limit.
+    fn take_token(&mut self) -> Result<(), ParseError<'t>> {
+        if self.limits.tokens.is_some_and(|tokens| self.tokens >= tokens) {
+            return Err(self.exceeded(Limit::Tokens(self.tokens)));
+        }
+        self.tokens += 1;
+        Ok(())
+    }
 
-            match action {
-                Action::Shift(dst_state_index) => {
-                    #[cfg(feature = "debug")]
-                    eprintln!("ACTION: SHIFT {}", dst_state_index);
-                    self.stack.push((dst_state_index, symbol.unwrap()));
+    /// Check the limits on steps and time before taking another step.
+    fn check_step(&mut self) -> Result<(), ParseError<'t>> {
+        if let Some(steps) = self.limits.steps.filter(|steps| self.step >= *steps) {
+            return Err(self.exceeded(Limit::Steps(steps)));
+        }
+        if let Some((budget, every)) = self.limits.time {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            if self.step.is_multiple_of(every) && started.elapsed() >= budget {
+                return Err(self.exceeded(Limit::Time(budget)));
+            }
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, token: Option<Token<'t, 's>>) -> Result<Action, ParseError<'t>> {
+        self.check_step()?;
+
+        let state = self.state();
+        let symbol = token.as_ref().map(|token| token.terminal);
+        let lookahead = || symbol.map_or("$".to_string(), |symbol| symbol.to_string());
+
+        // TODO
+        // assert_eq!(actions.len(), 1, "Available actions: {actions:?}");
+        let Some(mut action) = self.parse_table.action(state, symbol) else {
+            tracing::debug!(step = self.step, state, lookahead = lookahead(), "no action");
+            return Err(ParseError::UnexpectedSymbol {
+                state,
+                symbol,
+                step: self.step,
+            });
+        };
+
+        // A reduction goes straight to the state after its nonterminal, from the state under its children.
+        // Where there is none, reducing to the start symbol halts the machine instead.
+        let rule = match action {
+            Action::Reduce(rule) => Some(self.parse_table.grammar().rule_at(rule)),
+            _ => None,
+        };
+        // The state under the children, which is the start state when they are the whole stack.
+        let below = match rule {
+            Some(rule) => {
+                let Some(depth) = self.stack.len().checked_sub(rule.rhs().len()) else {
+                    tracing::debug!(step = self.step, state, rule = rule.index(), depth = self.stack.len(), "stack underflow");
+                    return Err(ParseError::StackUnderflow { state, rule: rule.index(), step: self.step });
+                };
+                Some(depth.checked_sub(1).map_or(0, |index| self.stack[index].0))
+            }
+            None => None,
+        };
+        let mut goto = None;
+        if let (Some(rule), Some(below)) = (rule, below) {
+            match self.parse_table.goto(below, rule.lhs()) {
+                Some(to) => goto = Some(to),
+                None if self.parse_table.halts(below, rule.lhs()) => action = Action::Halt,
+                None => {
+                    tracing::debug!(step = self.step, state = below, nonterminal = %rule.lhs(), "no goto");
+                    return Err(ParseError::UnexpectedSymbol {
+                        state: below,
+                        symbol: Some(rule.lhs()),
+                        step: self.step,
+                    });
                 }
-                Action::Reduce(rule) => {
-                    #[cfg(feature = "debug")]
-                    eprintln!("ACTION: REDUCE {:?}", rule);
+            }
+        }
+        tracing::debug!(
+            step = self.step,
+            state,
+            lookahead = lookahead(),
+            action = ?action,
+            depth = self.stack.len(),
+            "step",
+        );
+
+        match action {
+            Action::Shift(dst_state_index) => {
+                let Some(token) = token else {
+                    return Err(ParseError::UnexpectedSymbol { state, symbol, step: self.step });
+                };
+                self.push(dst_state_index, B::leaf(token, state))?;
+            }
+            Action::Reduce(_) => {
+                let reduced = self.reduce(rule.unwrap(), below.unwrap());
+                self.push(goto.unwrap(), reduced)?;
+                self.lookahead = token;
+            }
+            Action::Halt => {
+                self.halted = true;
+                self.tree = Some(self.reduce(rule.unwrap(), below.unwrap()));
+                self.lookahead = token;
+            }
+        }
+        self.step += 1;
+        Ok(action)
+    }
 
-                    self.head.insert(0, rule.lhs());
+    fn push(&mut self, state: StateIndex, node: B::Node) -> Result<(), ParseError<'t>> {
+        if let Some(depth) = self.limits.depth.filter(|depth| self.stack.len() >= *depth) {
+            return Err(self.exceeded(Limit::Depth(depth)));
+        }
+        self.stack.push((state, node));
+        Ok(())
+    }
 
-                    if let Some(symbol) = symbol {
-                        self.head.insert(0, symbol);
-                    }
+    /// Pop the children of `rule` off the stack, into a node for its nonterminal to push from `below`.
+    fn reduce(&mut self, rule: Rule<'t>, below: StateIndex) -> B::Node {
+        let children = self.stack.drain(self.stack.len() - rule.rhs().len()..).map(|(_state, child)| child).collect();
+        B::branch(rule, children, below)
+    }
 
-                    let mut children = vec![];
+    /// Push a whole subtree through the goto on its nonterminal from the current state.
+    fn shift_subtree(&mut self, subtree: B::Node) -> Result<(), ParseError<'t>> {
+        self.check_step()?;
+        let state = self.state();
+        let symbol = B::symbol(&subtree);
+        let Some(to) = self.parse_table.goto(state, symbol) else {
+            return Err(ParseError::UnexpectedSymbol { state, symbol: Some(symbol), step: self.step });
+        };
+        tracing::debug!(step = self.step, state, nonterminal = %symbol, to, depth = self.stack.len(), "reuse");
+        self.push(to, subtree)?;
+        self.step += 1;
+        Ok(())
+    }
 
-                    for _ in 0..rule.rhs().len() {
-                        let Some((_state, sym)) = self.stack.pop() else { panic!() };
-                        children.insert(0, sym);
-                    }
+    /// Take a single action, on the lookahead left by the last reduction if there is one,
+    /// or else on the next token from `input`, or the end of input when it has none.
+    pub fn step_once<S: TokenSource<'t, 's>>(&mut self, input: &mut S) -> Result<Action, ParseError<'t, S::Error>> {
+        if self.halted {
+            return Err(ParseError::AlreadyHalted);
+        }
+        let node = match self.lookahead.take() {
+            Some(node) => Some(node),
+            None => match input.next_token().transpose().map_err(ParseError::Input)? {
+                Some(token) => {
+                    self.take_token().map_err(ParseError::into_input)?;
+                    tracing::debug!(terminal = %token.terminal, text = token.text, start = token.span.start, "token");
+                    Some(token)
                 }
-                Action::Halt => {
-                    eprintln!("ACTION: HALT");
-                    self.halted = true;
+                None => None,
+            },
+        };
+        self.step(node).map_err(ParseError::into_input)
+    }
+
+    /// Step on the lookahead left by reductions until the machine needs more input.
+    fn drain(&mut self) -> Result<Status, ParseError<'t>> {
+        while !self.halted {
+            match self.lookahead.take() {
+                Some(node) => {
+                    self.step(Some(node))?;
                 }
+                None => return Ok(Status::Pending),
             }
         }
-        #[cfg(feature = "debug")]
-        eprintln!();
-        self.step += 1;
+        match self.lookahead.take() {
+            Some(token) => Err(ParseError::TrailingInput(token.terminal)),
+            None => Ok(Status::Halted),
+        }
     }
 
-    pub fn run(&mut self, input: &mut impl Iterator<Item=Symbol<'a>>) {
-        let mut i = 0;
+    /// Push the next input token into the machine.
+    /// The machine runs until it either needs another token or halts.
+    pub fn feed(&mut self, token: Token<'t, 's>) -> Result<Status, ParseError<'t>> {
+        self.feed_or_reuse(token, |_state| None)
+    }
+
+    /// Like `feed`, but when the machine comes to shift `token`, it first asks `reuse`, with the state it is in,
+    /// for a subtree to shift instead: one built before from the same tokens, starting with this one.
+    /// The input then continues after the subtree, which counts as a single token and step against the limits.
+    pub fn feed_or_reuse(
+        &mut self,
+        mut token: Token<'t, 's>,
+        reuse: impl FnOnce(StateIndex) -> Option<B::Node>,
+    ) -> Result<Status, ParseError<'t>> {
+        if self.halted {
+            return Err(ParseError::AlreadyHalted);
+        }
+        self.take_token()?;
+        tracing::debug!(terminal = %token.terminal, text = token.text, start = token.span.start, "token");
+        let mut reuse = Some(reuse);
+        loop {
+            let state = self.state();
+            if let Some(Action::Shift(_)) = self.parse_table.action(state, Some(token.terminal))
+                && let Some(subtree) = reuse.take().and_then(|reuse| reuse(state))
+            {
+                self.shift_subtree(subtree)?;
+                return Ok(Status::Pending);
+            }
+            self.step(Some(token))?;
+            match self.lookahead.take() {
+                Some(lookahead) if !self.halted => token = lookahead,
+                lookahead => {
+                    self.lookahead = lookahead;
+                    return self.drain();
+                }
+            }
+        }
+    }
+
+    /// Signal the end of input. The machine runs until it halts.
+    pub fn finish(&mut self) -> Result<(), ParseError<'t>> {
         while !self.halted {
-            if let Some(symbol) = self.head.pop() {
-                self.step(Some(symbol));
-            } else {
-                let symbol = input.next();
-                #[cfg(feature = "debug")]
-                eprintln!("READ SYMBOL: {symbol:?}  #{i}");
-                i += 1;
-                self.step(symbol);
+            self.step(None)?;
+            self.drain()?;
+        }
+        Ok(())
+    }
+
+    pub fn run<S: TokenSource<'t, 's>>(&mut self, input: &mut S) -> Result<(), ParseError<'t, S::Error>> {
+        let _span = tracing::debug_span!("parse").entered();
+        while let Some(token) = input.next_token().transpose().map_err(ParseError::Input)? {
+            if self.feed(token).map_err(ParseError::into_input)? == Status::Halted {
+                return match input.next_token().transpose().map_err(ParseError::Input)? {
+                    Some(token) => Err(ParseError::TrailingInput(token.terminal)),
+                    None => Ok(()),
+                };
             }
         }
+        self.finish().map_err(ParseError::into_input)
     }
 }
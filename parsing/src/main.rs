@@ -67,15 +67,15 @@ fn main() {
     tracing::info!("Nullables");
     eprintln!("Nullables: {:?}", grammar.nullables());
 
-    let table = ParseTable::new(&grammar);
+    let table = ParseTable::new(grammar);
 
     eprintln!("Parse Table");
     for state in 0..table.states.len() {
         eprintln!("    State {state}");
         for symbol in table.grammar.symbols() {
-            eprintln!("        on {symbol} => {:?}", &table.actions[&(state, Some(symbol))]);
+            eprintln!("        on {symbol} => {:?}", table.actions(state, Some(symbol)));
         }
-        eprintln!("        on $ => {:?}", &table.actions[&(state, None)]);
+        eprintln!("        on $ => {:?}", table.actions(state, None));
         eprintln!();
     }
     eprintln!();
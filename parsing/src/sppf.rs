@@ -0,0 +1,408 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::*;
+
+pub type NodeIndex = usize;
+
+/// A shared packed parse forest.
+///
+/// Each node is identified by the symbol it derives and the span of input `start..end` it covers.
+/// A node for the same `(symbol, start, end)` is only ever allocated once, so subtrees are shared
+/// between all the derivations that use them.
+/// Alternative derivations of the same node are kept side-by-side as packed nodes.
+#[derive(Default)]
+pub struct Forest<'a> {
+    nodes: Vec<SymbolNode<'a>>,
+    index: HashMap<(Symbol<'a>, usize, usize), NodeIndex>,
+}
+
+#[derive(Debug)]
+pub struct SymbolNode<'a> {
+    pub symbol: Symbol<'a>,
+    pub start: usize,
+    pub end: usize,
+    pub packed: Vec<Packed<'a>>,
+}
+
+/// One way of deriving a `SymbolNode`: the rule applied and the nodes for each symbol on its RHS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packed<'a> {
+    pub rule: Rule<'a>,
+    pub children: Vec<NodeIndex>,
+}
+
+/// A single tree extracted from a `Forest`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Tree<'a> {
+    pub symbol: Symbol<'a>,
+    pub start: usize,
+    pub end: usize,
+    pub rule: Option<Rule<'a>>,
+    pub children: Vec<Tree<'a>>,
+}
+
+impl<'a> SymbolNode<'a> {
+    pub fn is_leaf(&self) -> bool {
+        self.packed.is_empty()
+    }
+
+    pub fn is_ambiguous(&self) -> bool {
+        self.packed.len() > 1
+    }
+}
+
+impl<'a> Forest<'a> {
+    pub fn new() -> Forest<'a> {
+        Forest::default()
+    }
+
+    pub fn nodes(&self) -> &[SymbolNode<'a>] {
+        &self.nodes
+    }
+
+    pub fn node(&self, node: NodeIndex) -> &SymbolNode<'a> {
+        &self.nodes[node]
+    }
+
+    pub fn find(&self, symbol: Symbol<'a>, start: usize, end: usize) -> Option<NodeIndex> {
+        self.index.get(&(symbol, start, end)).copied()
+    }
+
+    /// Find or allocate the node for `symbol` spanning `start..end`.
+    pub fn insert(&mut self, symbol: Symbol<'a>, start: usize, end: usize) -> NodeIndex {
+        if let Some(node) = self.find(symbol, start, end) {
+            return node;
+        }
+        let node = self.nodes.len();
+        self.nodes.push(SymbolNode {
+            symbol,
+            start,
+            end,
+            packed: vec![],
+        });
+        self.index.insert((symbol, start, end), node);
+        node
+    }
+
+    /// Add a derivation of `node` by `rule`.
+    /// Returns false if this derivation was already recorded.
+    pub fn pack(&mut self, node: NodeIndex, rule: Rule<'a>, children: Vec<NodeIndex>) -> bool {
+        assert_eq!(rule.lhs(), self.nodes[node].symbol, "Rule {rule:?} does not derive {:?}", self.nodes[node].symbol);
+        assert_eq!(rule.rhs().len(), children.len(), "Wrong number of children for {rule:?}");
+        for (sym, child) in rule.rhs().iter().zip(children.iter()) {
+            assert_eq!(*sym, self.nodes[*child].symbol, "Child does not match the RHS of {rule:?}");
+        }
+
+        let packed = Packed { rule, children };
+        if self.nodes[node].packed.contains(&packed) {
+            false
+        } else {
+            self.nodes[node].packed.push(packed);
+            true
+        }
+    }
+
+    /// The number of distinct trees rooted at `root`.
+    /// Returns `None` when the forest contains a cycle reachable from `root`,
+    /// since a cyclic grammar (eg, `A -> A`) has infinitely many derivations,
+    /// or when there are more than `u128::MAX`.
+    pub fn count_derivations(&self, root: NodeIndex) -> Option<u128> {
+        let mut counts = HashMap::new();
+        let mut visiting = HashSet::new();
+        self.count(root, &mut counts, &mut visiting)
+    }
+
+    fn count(
+        &self,
+        node: NodeIndex,
+        counts: &mut HashMap<NodeIndex, u128>,
+        visiting: &mut HashSet<NodeIndex>,
+    ) -> Option<u128> {
+        if let Some(count) = counts.get(&node) {
+            return Some(*count);
+        }
+        if !visiting.insert(node) {
+            return None;
+        }
+
+        let count = if self.nodes[node].is_leaf() {
+            1
+        } else {
+            let mut total: u128 = 0;
+            for packed in &self.nodes[node].packed {
+                let mut product: u128 = 1;
+                for child in &packed.children {
+                    product = product.checked_mul(self.count(*child, counts, visiting)?)?;
+                }
+                total = total.checked_add(product)?;
+            }
+            total
+        };
+
+        visiting.remove(&node);
+        counts.insert(node, count);
+        Some(count)
+    }
+
+    /// Lazily enumerate every tree rooted at `root`.
+    /// Each tree is only built when the iterator is advanced.
+    /// A cyclic forest, or one with too many trees to count, yields none (see `count_derivations`).
+    pub fn trees(&self, root: NodeIndex) -> Trees<'a, '_> {
+        let mut counts = HashMap::new();
+        let total = self.count(root, &mut counts, &mut HashSet::new()).unwrap_or(0);
+        Trees {
+            forest: self,
+            root,
+            counts,
+            next: 0,
+            total,
+        }
+    }
+
+    /// Build the `k`th tree under `node`, where `k < counts[node]`, so no product of counts under it overflows.
+    /// The index is decoded in a mixed radix: first to pick the packed node,
+    /// then to pick a tree for each of its children.
+    fn nth_tree(&self, node: NodeIndex, mut k: u128, counts: &HashMap<NodeIndex, u128>) -> Tree<'a> {
+        let symbol_node = &self.nodes[node];
+        if symbol_node.is_leaf() {
+            return symbol_node.leaf();
+        }
+
+        for packed in &symbol_node.packed {
+            let product: u128 = packed.children.iter().map(|child| counts[child]).product();
+            if k >= product {
+                k -= product;
+                continue;
+            }
+
+            let mut children = vec![];
+            for child in &packed.children {
+                let radix = counts[child];
+                children.push(self.nth_tree(*child, k % radix, counts));
+                k /= radix;
+            }
+            return symbol_node.branch(packed.rule, children);
+        }
+        unreachable!()
+    }
+
+    /// Pick a single tree rooted at `root`, resolving ambiguities with `disambiguator`.
+    /// Returns `None` if every derivation was rejected.
+    pub fn pick(&self, root: NodeIndex, disambiguator: &Disambiguator<'a>) -> Option<Tree<'a>> {
+        let mut choices = HashMap::new();
+        self.choose(root, disambiguator, &mut choices, &mut HashMap::new()).0?;
+        Some(self.build_choice(root, disambiguator, &mut choices, &mut HashMap::new()))
+    }
+
+    /// Choose the packed node to build `node` from, avoiding the nodes in `visiting`, which are mapped to their depth.
+    /// Also returns the depth of the shallowest of them the choice avoided, or `usize::MAX` if none.
+    /// A choice which avoided nothing above `node` holds wherever `node` is reached, and only those are kept in `choices`.
+    fn choose(
+        &self,
+        node: NodeIndex,
+        disambiguator: &Disambiguator<'a>,
+        choices: &mut HashMap<NodeIndex, Option<usize>>,
+        visiting: &mut HashMap<NodeIndex, usize>,
+    ) -> (Option<usize>, usize) {
+        if let Some(choice) = choices.get(&node) {
+            return (*choice, usize::MAX);
+        }
+        let symbol_node = &self.nodes[node];
+        if symbol_node.is_leaf() {
+            choices.insert(node, Some(0));
+            return (Some(0), usize::MAX);
+        }
+        if let Some(depth) = visiting.get(&node) {
+            return (None, *depth);
+        }
+        let depth = visiting.len();
+        visiting.insert(node, depth);
+
+        let mut avoided = usize::MAX;
+        let mut candidates = vec![];
+        for (i, packed) in symbol_node.packed.iter().enumerate() {
+            if disambiguator.rejects(packed.rule) {
+                continue;
+            }
+            let viable = packed.children.iter().all(|child| {
+                let (choice, child_avoided) = self.choose(*child, disambiguator, choices, visiting);
+                avoided = avoided.min(child_avoided);
+                choice.is_some()
+            });
+            if viable {
+                candidates.push(i);
+            }
+        }
+
+        if disambiguator.prefer_longest {
+            // Keep the first candidate whose children extend furthest, comparing left to right.
+            let ends = |i: usize| -> Vec<usize> {
+                symbol_node.packed[i].children.iter().map(|child| self.nodes[*child].end).collect()
+            };
+            let mut best: Option<usize> = None;
+            for candidate in candidates.iter().copied() {
+                if best.is_none_or(|best| ends(candidate) > ends(best)) {
+                    best = Some(candidate);
+                }
+            }
+            candidates = best.into_iter().collect();
+        }
+
+        visiting.remove(&node);
+        let choice = candidates.first().copied();
+        if avoided >= depth {
+            choices.insert(node, choice);
+        }
+        (choice, avoided)
+    }
+
+    /// Build the chosen tree under `node`, where `visiting` is the path to it.
+    /// A node whose choice depended on the path it was first reached by is chosen again on this one.
+    fn build_choice(
+        &self,
+        node: NodeIndex,
+        disambiguator: &Disambiguator<'a>,
+        choices: &mut HashMap<NodeIndex, Option<usize>>,
+        visiting: &mut HashMap<NodeIndex, usize>,
+    ) -> Tree<'a> {
+        let symbol_node = &self.nodes[node];
+        if symbol_node.is_leaf() {
+            return symbol_node.leaf();
+        }
+        let choice = match choices.get(&node) {
+            Some(choice) => *choice,
+            None => self.choose(node, disambiguator, choices, visiting).0,
+        };
+        let packed = &symbol_node.packed[choice.expect("a node in a picked tree has a choice")];
+        visiting.insert(node, visiting.len());
+        let children = packed
+            .children
+            .iter()
+            .map(|child| self.build_choice(*child, disambiguator, choices, visiting))
+            .collect();
+        visiting.remove(&node);
+        symbol_node.branch(packed.rule, children)
+    }
+}
+
+impl<'a> SymbolNode<'a> {
+    fn leaf(&self) -> Tree<'a> {
+        Tree {
+            symbol: self.symbol,
+            start: self.start,
+            end: self.end,
+            rule: None,
+            children: vec![],
+        }
+    }
+
+    fn branch(&self, rule: Rule<'a>, children: Vec<Tree<'a>>) -> Tree<'a> {
+        Tree {
+            symbol: self.symbol,
+            start: self.start,
+            end: self.end,
+            rule: Some(rule),
+            children,
+        }
+    }
+}
+
+impl<'a> Tree<'a> {
+    /// This tree as a `lr0::Node`, where the leaf spanning `i..i + 1` is given `tokens[i]`.
+    /// The tokens are the input the forest was parsed from, eg by `earley::parse`.
+    pub fn to_node<'s>(&self, tokens: &[Token<'a, 's>]) -> lr0::Node<'a, 's> {
+        match self.rule {
+            None => tokens[self.start].clone().into(),
+            Some(rule) => lr0::NodeData {
+                symbol: self.symbol,
+                children: self.children.iter().map(|child| child.to_node(tokens)).collect(),
+                token: None,
+                rule: Some(rule),
+            }
+            .into(),
+        }
+    }
+}
+
+pub struct Trees<'a, 'f> {
+    forest: &'f Forest<'a>,
+    root: NodeIndex,
+    counts: HashMap<NodeIndex, u128>,
+    next: u128,
+    total: u128,
+}
+
+impl<'a, 'f> Iterator for Trees<'a, 'f> {
+    type Item = Tree<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.total {
+            return None;
+        }
+        let tree = self.forest.nth_tree(self.root, self.next, &self.counts);
+        self.next += 1;
+        Some(tree)
+    }
+}
+
+/// Filters used by `Forest::pick` to choose between packed alternatives.
+#[derive(Default)]
+pub struct Disambiguator<'a> {
+    rejected: Vec<Rule<'a>>,
+    prefer_longest: bool,
+}
+
+impl<'a> Disambiguator<'a> {
+    pub fn new() -> Disambiguator<'a> {
+        Disambiguator::default()
+    }
+
+    /// When the children of two alternatives split the input differently,
+    /// prefer the one where the leftmost child that differs is longest (maximal munch).
+    pub fn prefer_longest(mut self) -> Self {
+        self.prefer_longest = true;
+        self
+    }
+
+    /// Never use `rule` in a picked tree.
+    pub fn reject(mut self, rule: Rule<'a>) -> Self {
+        self.rejected.push(rule);
+        self
+    }
+
+    pub fn rejects(&self, rule: Rule<'a>) -> bool {
+        self.rejected.contains(&rule)
+    }
+}
+
+impl<'a> std::fmt::Debug for Forest<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, node) in self.nodes.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "#{i} {}[{}..{}]", node.symbol, node.start, node.end)?;
+            for packed in &node.packed {
+                write!(f, "\n    {:?} =>", packed.rule)?;
+                for child in &packed.children {
+                    write!(f, " #{child}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> std::fmt::Debug for Tree<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.rule.is_none() {
+            write!(f, "{}", self.symbol)
+        } else {
+            write!(f, "({}", self.symbol)?;
+            for child in &self.children {
+                write!(f, " {child:?}")?;
+            }
+            write!(f, ")")
+        }
+    }
+}
@@ -273,3 +273,731 @@ fn foo() {
     assert!(decl_star.is_nullable());
     assert_eq!(decl_star.firsts(), vec![kw_module, dedent].into_iter().collect());
 }
+
+/// Build the forest for `x + x + x` under `E -> E + E | x`, which has two derivations.
+fn ambiguous_sum_forest(grammar: &Grammar) -> (sppf::Forest<'_>, sppf::NodeIndex) {
+    let e = grammar.symbol("E").unwrap();
+    let x = grammar.symbol("x").unwrap();
+    let plus = grammar.symbol("+").unwrap();
+    let rules = grammar.rules();
+    let (sum, var) = (rules[0], rules[1]);
+
+    let mut forest = sppf::Forest::new();
+    let xs: Vec<_> = [0, 2, 4].iter().map(|&i| forest.insert(x, i, i + 1)).collect();
+    let plus1 = forest.insert(plus, 1, 2);
+    let plus2 = forest.insert(plus, 3, 4);
+    let es: Vec<_> = [0, 2, 4].iter().map(|&i| forest.insert(e, i, i + 1)).collect();
+    for (&e, &x) in es.iter().zip(xs.iter()) {
+        forest.pack(e, var, vec![x]);
+    }
+
+    let left = forest.insert(e, 0, 3);
+    forest.pack(left, sum, vec![es[0], plus1, es[1]]);
+    let right = forest.insert(e, 2, 5);
+    forest.pack(right, sum, vec![es[1], plus2, es[2]]);
+
+    let root = forest.insert(e, 0, 5);
+    forest.pack(root, sum, vec![left, plus2, es[2]]);
+    forest.pack(root, sum, vec![es[0], plus1, right]);
+    (forest, root)
+}
+
+fn sum_grammar() -> Grammar {
+    Grammar::new()
+        .symbol("E")
+        .symbol("x")
+        .symbol("+")
+        .rule("E", &["E", "+", "E"])
+        .rule("E", &["x"])
+        .build()
+}
+
+/// Make sure shared nodes are only allocated once and duplicate derivations are not packed twice.
+#[test]
+fn test_sppf_sharing() {
+    let grammar = sum_grammar();
+    let (mut forest, root) = ambiguous_sum_forest(&grammar);
+    let e = grammar.symbol("E").unwrap();
+
+    let nodes = forest.nodes().len();
+    assert_eq!(forest.insert(e, 2, 3), forest.find(e, 2, 3).unwrap());
+    assert_eq!(forest.nodes().len(), nodes);
+
+    let packed = forest.node(root).packed[0].clone();
+    assert!(!forest.pack(root, packed.rule, packed.children));
+    assert!(forest.node(root).is_ambiguous());
+}
+
+#[test]
+fn test_sppf_count_and_enumerate() {
+    let grammar = sum_grammar();
+    let (forest, root) = ambiguous_sum_forest(&grammar);
+
+    assert_eq!(forest.count_derivations(root), Some(2));
+
+    let trees: Vec<String> = forest.trees(root).map(|tree| format!("{tree:?}")).collect();
+    assert_eq!(trees, vec![
+        "(E (E (E x) + (E x)) + (E x))",
+        "(E (E x) + (E (E x) + (E x)))",
+    ]);
+}
+
+/// A cyclic grammar has infinitely many derivations, so nothing is counted or enumerated.
+#[test]
+fn test_sppf_cycle() {
+    let grammar = Grammar::new()
+        .symbol("A")
+        .symbol("x")
+        .rule("A", &["A"])
+        .rule("A", &["x"])
+        .build();
+    let a = grammar.symbol("A").unwrap();
+    let x = grammar.symbol("x").unwrap();
+    let rules = grammar.rules();
+
+    let mut forest = sppf::Forest::new();
+    let leaf = forest.insert(x, 0, 1);
+    let root = forest.insert(a, 0, 1);
+    forest.pack(root, rules[0], vec![root]);
+    forest.pack(root, rules[1], vec![leaf]);
+
+    assert_eq!(forest.count_derivations(root), None);
+    assert_eq!(forest.trees(root).count(), 0);
+
+    let tree = forest.pick(root, &sppf::Disambiguator::new()).unwrap();
+    assert_eq!(format!("{tree:?}"), "(A x)");
+}
+
+/// A node first reached around a cycle, which had no tree there, still has one where it is reached again.
+#[test]
+fn test_sppf_pick_after_cycle() {
+    let grammar = Grammar::new()
+        .symbol("S")
+        .symbol("A")
+        .symbol("B")
+        .symbol("x")
+        .rule("S", &["A", "B"])
+        .rule("A", &["B"])
+        .rule("A", &["x"])
+        .rule("B", &["A"])
+        .build();
+    let [s, a, b, x] = ["S", "A", "B", "x"].map(|name| grammar.symbol(name).unwrap());
+    let rules = grammar.rules();
+
+    let mut forest = sppf::Forest::new();
+    let leaf = forest.insert(x, 0, 1);
+    let a_node = forest.insert(a, 0, 1);
+    let b_node = forest.insert(b, 0, 1);
+    forest.pack(a_node, rules[1], vec![b_node]);
+    forest.pack(a_node, rules[2], vec![leaf]);
+    forest.pack(b_node, rules[3], vec![a_node]);
+    let root = forest.insert(s, 0, 1);
+    forest.pack(root, rules[0], vec![a_node, b_node]);
+
+    // `B` is first reached from `A`, where its only tree would go around to `A` again.
+    let tree = forest.pick(root, &sppf::Disambiguator::new()).unwrap();
+    assert_eq!(format!("{tree:?}"), "(S (A x) (B (A x)))");
+}
+
+/// Counts which would pass `u128::MAX` are not counted, rather than saturating.
+#[test]
+fn test_sppf_count_overflow() {
+    let grammar = Grammar::new()
+        .symbol("A")
+        .symbol("x")
+        .rule("A", &["A", "A"])
+        .rule("A", &["A", "A", "A"])
+        .rule("A", &["x"])
+        .build();
+    let [a, x] = ["A", "x"].map(|name| grammar.symbol(name).unwrap());
+    let rules = grammar.rules();
+
+    // Each level has `n * n + n * n * n` trees, for the `n` of the level below.
+    let mut forest = sppf::Forest::new();
+    let leaf = forest.insert(x, 0, 1);
+    let mut levels = vec![forest.insert(a, 0, 1)];
+    forest.pack(levels[0], rules[2], vec![leaf]);
+    for level in 1..7 {
+        let below = levels[level - 1];
+        let node = forest.insert(a, 0, level + 1);
+        forest.pack(node, rules[0], vec![below; 2]);
+        forest.pack(node, rules[1], vec![below; 3]);
+        levels.push(node);
+    }
+
+    assert_eq!(forest.count_derivations(levels[2]), Some(12));
+    assert!(forest.count_derivations(levels[5]).is_some());
+    assert_eq!(forest.count_derivations(levels[6]), None);
+    assert_eq!(forest.trees(levels[6]).next(), None);
+}
+
+#[test]
+fn test_sppf_reject_rule() {
+    let grammar = Grammar::new()
+        .symbol("S")
+        .symbol("A")
+        .symbol("B")
+        .symbol("x")
+        .rule("S", &["A"])
+        .rule("S", &["B"])
+        .rule("A", &["x"])
+        .rule("B", &["x"])
+        .build();
+    let [s, a, b, x] = ["S", "A", "B", "x"].map(|name| grammar.symbol(name).unwrap());
+    let rules = grammar.rules();
+
+    let mut forest = sppf::Forest::new();
+    let leaf = forest.insert(x, 0, 1);
+    let a_node = forest.insert(a, 0, 1);
+    forest.pack(a_node, rules[2], vec![leaf]);
+    let b_node = forest.insert(b, 0, 1);
+    forest.pack(b_node, rules[3], vec![leaf]);
+    let root = forest.insert(s, 0, 1);
+    forest.pack(root, rules[0], vec![a_node]);
+    forest.pack(root, rules[1], vec![b_node]);
+
+    let tree = forest.pick(root, &sppf::Disambiguator::new()).unwrap();
+    assert_eq!(format!("{tree:?}"), "(S (A x))");
+
+    let tree = forest.pick(root, &sppf::Disambiguator::new().reject(rules[0])).unwrap();
+    assert_eq!(format!("{tree:?}"), "(S (B x))");
+
+    let filter = sppf::Disambiguator::new().reject(rules[0]).reject(rules[3]);
+    assert!(forest.pick(root, &filter).is_none());
+}
+
+/// `S -> A A` over `x x x` splits either as `xx|x` or `x|xx`; prefer the longest first child.
+#[test]
+fn test_sppf_prefer_longest() {
+    let grammar = Grammar::new()
+        .symbol("S")
+        .symbol("A")
+        .symbol("x")
+        .rule("S", &["A", "A"])
+        .rule("A", &["x"])
+        .rule("A", &["x", "x"])
+        .build();
+    let [s, a, x] = ["S", "A", "x"].map(|name| grammar.symbol(name).unwrap());
+    let rules = grammar.rules();
+
+    let mut forest = sppf::Forest::new();
+    let xs: Vec<_> = (0..3).map(|i| forest.insert(x, i, i + 1)).collect();
+    let a01 = forest.insert(a, 0, 1);
+    forest.pack(a01, rules[1], vec![xs[0]]);
+    let a02 = forest.insert(a, 0, 2);
+    forest.pack(a02, rules[2], vec![xs[0], xs[1]]);
+    let a13 = forest.insert(a, 1, 3);
+    forest.pack(a13, rules[2], vec![xs[1], xs[2]]);
+    let a23 = forest.insert(a, 2, 3);
+    forest.pack(a23, rules[1], vec![xs[2]]);
+    let root = forest.insert(s, 0, 3);
+    forest.pack(root, rules[0], vec![a01, a13]);
+    forest.pack(root, rules[0], vec![a02, a23]);
+
+    assert_eq!(forest.count_derivations(root), Some(2));
+
+    let tree = forest.pick(root, &sppf::Disambiguator::new()).unwrap();
+    assert_eq!(tree.children[0].end, 1);
+
+    let tree = forest.pick(root, &sppf::Disambiguator::new().prefer_longest()).unwrap();
+    assert_eq!(tree.children[0].end, 2);
+    assert_eq!(format!("{tree:?}"), "(S (A x x) (A x))");
+}
+
+fn parens_grammar() -> Grammar {
+    Grammar::new()
+        .symbol("START")
+        .symbol("L")
+        .symbol("(")
+        .symbol(")")
+        .symbol("x")
+        .rule("START", &["L"])
+        .rule("L", &["(", "L", ")"])
+        .rule("L", &["x"])
+        .build()
+}
+
+/// Feed tokens one at a time, checking the machine waits for more input between them.
+#[test]
+fn test_machine_feed() {
+    let table = lr0::ParseTable::new(parens_grammar());
+    let grammar = table.grammar();
+    let [open, close, x] = ["(", ")", "x"].map(|name| grammar.symbol(name).unwrap());
+    let mut machine = lr0::Machine::new(&table);
+
+    assert_eq!(machine.expected_terminals(), vec![open, x]);
+    for symbol in [open, open, x, close] {
+        assert_eq!(machine.feed(symbol.into()), Ok(lr0::Status::Pending));
+    }
+    // The state after the inner `)` reduces on every terminal, but only `)` can be shifted once it has.
+    assert_eq!(machine.expected_terminals(), vec![close]);
+    assert!(!machine.is_halted());
+    assert_eq!(machine.feed(close.into()), Ok(lr0::Status::Pending));
+    assert_eq!(machine.finish(), Ok(()));
+    assert!(machine.is_halted());
+    assert_eq!(machine.feed(x.into()), Err(lr0::ParseError::AlreadyHalted));
+}
+
+#[test]
+fn test_machine_feed_errors() {
+    let table = lr0::ParseTable::new(parens_grammar());
+    let grammar = table.grammar();
+    let [open, close, x] = ["(", ")", "x"].map(|name| grammar.symbol(name).unwrap());
+
+    let mut machine = lr0::Machine::new(&table);
+    assert!(matches!(
+        machine.feed(close.into()),
+        Err(lr0::ParseError::UnexpectedSymbol { symbol: Some(symbol), .. }) if symbol == close,
+    ));
+
+    let mut machine = lr0::Machine::new(&table);
+    machine.feed(open.into()).unwrap();
+    assert!(matches!(machine.finish(), Err(lr0::ParseError::UnexpectedSymbol { symbol: None, .. })));
+
+    let mut machine = lr0::Machine::new(&table);
+    assert_eq!(machine.run(&mut [x, close].into_iter().map(Token::from)), Err(lr0::ParseError::TrailingInput(close)));
+}
+
+/// Each limit stops the parse with an error naming it, and input within the limits still parses.
+#[test]
+fn test_machine_limits() {
+    let table = lr0::ParseTable::new(parens_grammar());
+    let grammar = table.grammar();
+    let [open, close, x] = ["(", ")", "x"].map(|name| grammar.symbol(name).unwrap());
+    let nested = |depth: usize| {
+        let mut symbols = vec![open; depth];
+        symbols.push(x);
+        symbols.extend(vec![close; depth]);
+        symbols.into_iter().map(Token::from)
+    };
+    let run = |limits: lr0::Limits, depth: usize| lr0::Machine::with_limits(&table, limits).run(&mut nested(depth));
+    let exceeded = |limit, step| Err(lr0::ParseError::LimitExceeded { limit, step });
+
+    // Each `(` is shifted in its own step, so the stack is 10 deep after 10 steps.
+    // 8 fit, with the innermost `L` and the `)` after it on top.
+    assert_eq!(run(lr0::Limits::new().max_depth(10), 100), exceeded(lr0::Limit::Depth(10), 10));
+    assert_eq!(run(lr0::Limits::new().max_depth(10), 8), Ok(()));
+
+    assert_eq!(run(lr0::Limits::new().max_tokens(5), 100), exceeded(lr0::Limit::Tokens(5), 5));
+    assert_eq!(run(lr0::Limits::new().max_tokens(5), 2), Ok(()));
+
+    assert_eq!(run(lr0::Limits::new().max_steps(20), 100), exceeded(lr0::Limit::Steps(20), 20));
+    let mut machine = lr0::Machine::new(&table);
+    machine.run(&mut nested(2)).unwrap();
+    assert_eq!(run(lr0::Limits::new().max_steps(machine.steps()), 2), Ok(()));
+
+    // The clock is read on the first step and every 8th after it.
+    let budget = std::time::Duration::ZERO;
+    assert_eq!(run(lr0::Limits::new().time_budget(budget, 8), 2), exceeded(lr0::Limit::Time(budget), 0));
+    let budget = std::time::Duration::from_secs(3600);
+    assert_eq!(run(lr0::Limits::new().time_budget(budget, 8), 100), Ok(()));
+}
+
+/// A left-recursive list makes a tree as deep as the input, which has to be dropped without recursing.
+#[test]
+fn test_machine_deep_tree() {
+    let grammar = Grammar::new()
+        .symbol("START")
+        .symbol("L")
+        .symbol("x")
+        .rule("START", &["L"])
+        .rule("L", &["L", "x"])
+        .rule("L", &["x"])
+        .build();
+    let table = lr0::ParseTable::new(grammar);
+    let x = table.grammar().symbol("x").unwrap();
+    let mut machine = lr0::Machine::new(&table);
+    machine.run(&mut std::iter::repeat_n(Token::from(x), 1_000_000)).unwrap();
+    assert_eq!(machine.tree().unwrap().children.len(), 1);
+}
+
+/// The GOTO table has the shifts on nonterminals, and the halt on the start symbol.
+#[test]
+fn test_goto() {
+    let table = lr0::ParseTable::new(parens_grammar());
+    let grammar = table.grammar();
+    let [start, l] = ["START", "L"].map(|name| grammar.symbol(name).unwrap());
+
+    for state in 0..table.states.len() {
+        for nonterminal in [start, l] {
+            let shift = table.actions(state, Some(nonterminal)).iter().find_map(|action| match action {
+                lr0::Action::Shift(to) => Some(*to),
+                _ => None,
+            });
+            assert_eq!(table.goto(state, nonterminal), shift);
+        }
+    }
+    assert!(table.goto(0, l).is_some());
+    assert_eq!(table.goto(0, start), None);
+    assert!(table.halts(0, start));
+    assert!(!table.halts(0, l));
+}
+
+/// Stepping one action at a time reaches the same tree as running the machine.
+#[test]
+fn test_machine_step_once() {
+    let table = lr0::ParseTable::new(parens_grammar());
+    let grammar = table.grammar();
+    let [open, close, x] = ["(", ")", "x"].map(|name| grammar.symbol(name).unwrap());
+    let mut input = [open, x, close].into_iter().map(Token::from);
+
+    let mut machine = lr0::Machine::new(&table);
+    assert!(matches!(machine.step_once(&mut input), Ok(lr0::Action::Shift(_))));
+    assert!(matches!(machine.step_once(&mut input), Ok(lr0::Action::Shift(_))));
+    assert!(matches!(machine.step_once(&mut input), Ok(lr0::Action::Reduce(2))));
+    // The reduction goes straight to the state after `L`, and leaves the `)` it was made on to be stepped on again.
+    assert_eq!(machine.stack().iter().map(|(_state, node)| node.symbol.to_string()).collect::<Vec<_>>(), vec!["(", "L"]);
+    assert_eq!(machine.lookahead().map(|token| token.terminal), Some(close));
+
+    while !machine.is_halted() {
+        machine.step_once(&mut input).unwrap();
+    }
+    assert_eq!(format!("{:?}", machine.tree().unwrap()), "(START (L ( (L x) )))");
+    assert_eq!(machine.step_once(&mut input), Err(lr0::ParseError::AlreadyHalted));
+}
+
+/// Tokens keep their text and span when they are placed in the tree.
+#[test]
+fn test_machine_tree() {
+    let table = lr0::ParseTable::new(parens_grammar());
+    let grammar = table.grammar();
+    let [open, close, x] = ["(", ")", "x"].map(|name| grammar.symbol(name).unwrap());
+
+    let source = "( x )";
+    let mut tokens = [(open, 0..1), (x, 2..3), (close, 4..5)]
+        .into_iter()
+        .map(|(terminal, span)| Token::new(terminal, &source[span.clone()], span));
+
+    let mut machine = lr0::Machine::new(&table);
+    machine.run(&mut tokens).unwrap();
+
+    let tree = machine.tree().unwrap();
+    assert_eq!(format!("{tree:?}"), "(START (L ( (L x) )))");
+    assert_eq!(tree.span(), Some(0..5));
+
+    let texts: Vec<&str> = tree.tokens().iter().map(|token| token.text).collect();
+    assert_eq!(texts, vec!["(", "x", ")"]);
+
+    let inner = &tree.children[0].children[1];
+    assert_eq!(inner.span(), Some(2..3));
+    assert_eq!(inner.children[0].token.as_ref().unwrap().terminal, x);
+}
+
+/// `E -> E + T | T`, `T -> T * F | F`, `F -> ( E ) | x`, which is SLR(1) but not LR(0).
+fn expr_grammar() -> Grammar {
+    Grammar::new()
+        .symbol("START")
+        .symbol("E")
+        .symbol("T")
+        .symbol("F")
+        .symbol("+")
+        .symbol("*")
+        .symbol("(")
+        .symbol(")")
+        .symbol("x")
+        .rule("START", &["E"])
+        .rule("E", &["E", "+", "T"])
+        .rule("E", &["T"])
+        .rule("T", &["T", "*", "F"])
+        .rule("T", &["F"])
+        .rule("F", &["(", "E", ")"])
+        .rule("F", &["x"])
+        .build()
+}
+
+/// `S -> L = R | R`, `L -> * R | x`, `R -> L`, which is LALR(1) but not SLR(1).
+fn assignment_grammar() -> Grammar {
+    Grammar::new()
+        .symbol("START")
+        .symbol("S")
+        .symbol("L")
+        .symbol("R")
+        .symbol("=")
+        .symbol("*")
+        .symbol("x")
+        .rule("START", &["S"])
+        .rule("S", &["L", "=", "R"])
+        .rule("S", &["R"])
+        .rule("L", &["*", "R"])
+        .rule("L", &["x"])
+        .rule("R", &["L"])
+        .build()
+}
+
+fn run_words<'t>(table: &'t lr0::ParseTable, words: &str) -> Result<String, lr0::ParseError<'t>> {
+    let grammar = table.grammar();
+    let mut input = words.split_whitespace().map(|word| Token::from(grammar.symbol(word).unwrap()));
+    let mut machine = lr0::Machine::new(table);
+    machine.run(&mut input)?;
+    Ok(format!("{:?}", machine.tree().unwrap()))
+}
+
+#[test]
+fn test_slr_table() {
+    assert!(!lr0::ParseTable::new(expr_grammar()).conflicts().is_empty());
+
+    let table = lr0::ParseTable::slr(expr_grammar());
+    assert_eq!(table.conflicts().len(), 0);
+    assert_eq!(table.states.len(), lr0::ParseTable::new(expr_grammar()).states.len());
+    assert_eq!(
+        run_words(&table, "x + x * ( x )").unwrap(),
+        "(START (E (E (T (F x))) + (T (T (F x)) * (F ( (E (T (F x))) )))))",
+    );
+    assert!(matches!(
+        run_words(&table, "x + * x"),
+        Err(lr0::ParseError::UnexpectedSymbol { symbol: Some(symbol), .. }) if symbol.as_str() == "*",
+    ));
+}
+
+#[test]
+fn test_lalr_table() {
+    let slr = lr0::ParseTable::slr(assignment_grammar());
+    let conflicts = slr.conflicts();
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].symbol.unwrap().as_str(), "=");
+
+    let table = lr0::ParseTable::lalr(assignment_grammar());
+    assert_eq!(table.conflicts().len(), 0);
+    assert_eq!(run_words(&table, "* x = x").unwrap(), "(START (S (L * (R (L x))) = (R (L x))))");
+    assert_eq!(run_words(&table, "* * x").unwrap(), "(START (S (R (L * (R (L * (R (L x))))))))");
+    assert!(run_words(&table, "x = = x").is_err());
+
+    // LALR(1) never needs more lookahead than SLR(1).
+    assert_eq!(lr0::ParseTable::lalr(expr_grammar()).conflicts().len(), 0);
+}
+
+#[test]
+fn test_earley() {
+    let grammar = sum_grammar();
+    let [x, plus] = ["x", "+"].map(|name| grammar.symbol(name).unwrap());
+
+    let (forest, root) = earley::parse(&grammar, &[x, plus, x, plus, x]).unwrap();
+    assert_eq!(forest.count_derivations(root), Some(2));
+    let trees: Vec<String> = forest.trees(root).map(|tree| format!("{tree:?}")).collect();
+    assert!(trees.contains(&"(E (E (E x) + (E x)) + (E x))".to_string()));
+    assert!(trees.contains(&"(E (E x) + (E (E x) + (E x)))".to_string()));
+
+    assert_eq!(
+        earley::parse(&grammar, &[x, plus, plus]).unwrap_err(),
+        earley::ParseError::UnexpectedSymbol { position: 2, symbol: Some(plus) },
+    );
+    assert_eq!(
+        earley::parse(&grammar, &[x, plus]).unwrap_err(),
+        earley::ParseError::UnexpectedSymbol { position: 2, symbol: None },
+    );
+}
+
+/// Empty rules, including ones only nullable through other nonterminals, are completed where they are predicted.
+#[test]
+fn test_earley_empty_rules() {
+    let grammar = Grammar::new()
+        .symbol("S")
+        .symbol("A")
+        .symbol("B")
+        .symbol("x")
+        .rule("S", &["A", "A", "x"])
+        .rule("A", &["B"])
+        .rule("A", &[])
+        .rule("B", &[])
+        .build();
+    let x = grammar.symbol("x").unwrap();
+
+    let (forest, root) = earley::parse(&grammar, &[x]).unwrap();
+    assert_eq!(forest.count_derivations(root), Some(4));
+    let tree = forest.pick(root, &sppf::Disambiguator::new()).unwrap();
+    assert_eq!(format!("{tree:?}"), "(S (A (B)) (A (B)) x)");
+
+    let source = "x";
+    let node = tree.to_node(&[Token::new(x, source, 0..1)]);
+    assert_eq!(format!("{node:?}"), "(S (A (B)) (A (B)) x)");
+    assert_eq!(node.span(), Some(0..1));
+}
+
+static PARENS: std::sync::OnceLock<lr0::ParseTable> = std::sync::OnceLock::new();
+
+/// A table owns its grammar, so it can live in a global and be shared between threads.
+#[test]
+fn test_parse_table_shared() {
+    fn assert_send_sync<T: Send + Sync + 'static>() {}
+    assert_send_sync::<lr0::ParseTable>();
+
+    let handles: Vec<_> = (0..4)
+        .map(|depth| {
+            std::thread::spawn(move || {
+                let table = PARENS.get_or_init(|| lr0::ParseTable::new(parens_grammar()));
+                let grammar = table.grammar();
+                let [open, close, x] = ["(", ")", "x"].map(|name| grammar.symbol(name).unwrap());
+
+                let mut input = vec![open; depth];
+                input.push(x);
+                input.extend(vec![close; depth]);
+
+                let mut machine = lr0::Machine::new(table);
+                machine.run(&mut input.into_iter().map(Token::from)).unwrap();
+                format!("{:?}", machine.tree().unwrap())
+            })
+        })
+        .collect();
+
+    let trees: Vec<String> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+    assert_eq!(trees[0], "(START (L x))");
+    assert_eq!(trees[2], "(START (L ( (L ( (L x) )) )))");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_cache_roundtrip() {
+    let table = lr0::ParseTable::new(parens_grammar());
+
+    let json = table.to_json();
+    let loaded = lr0::ParseTable::from_json(parens_grammar(), &json).unwrap();
+    assert_eq!(loaded.states, table.states);
+    assert_eq!(loaded.actions, table.actions);
+    assert_eq!(loaded.to_json(), json);
+
+    let bytes = table.to_bytes();
+    assert!(bytes.len() < json.len());
+    let loaded = lr0::ParseTable::from_bytes(parens_grammar(), &bytes).unwrap();
+    assert_eq!(loaded.actions, table.actions);
+
+    let grammar = loaded.grammar();
+    let [open, close, x] = ["(", ")", "x"].map(|name| grammar.symbol(name).unwrap());
+    let mut machine = lr0::Machine::new(&loaded);
+    machine.run(&mut [open, x, close].into_iter().map(Token::from)).unwrap();
+    assert_eq!(format!("{:?}", machine.tree().unwrap()), "(START (L ( (L x) )))");
+}
+
+/// A cached table must not be used with a grammar it was not built from.
+#[cfg(feature = "serde")]
+#[test]
+fn test_cache_grammar_mismatch() {
+    let json = lr0::ParseTable::new(parens_grammar()).to_json();
+    let result = lr0::ParseTable::from_json(sum_grammar(), &json);
+    assert!(matches!(result, Err(cache::CacheError::GrammarMismatch { .. })));
+}
+
+/// A cached table for the right grammar is still checked for indices it doesn't have.
+#[cfg(feature = "serde")]
+#[test]
+fn test_cache_out_of_range() {
+    let json = lr0::ParseTable::new(parens_grammar()).to_json();
+    let corrupt = |edit: &dyn Fn(&mut serde_json::Value)| {
+        let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        edit(&mut value);
+        lr0::ParseTable::from_json(parens_grammar(), &value.to_string())
+    };
+    let out_of_range = |result: Result<lr0::ParseTable, cache::CacheError>| matches!(result, Err(cache::CacheError::OutOfRange(_)));
+
+    assert!(out_of_range(corrupt(&|value| value["states"][0][0]["rule"] = 99.into())));
+    assert!(out_of_range(corrupt(&|value| value["actions"][0][0] = 99.into())));
+    assert!(out_of_range(corrupt(&|value| value["actions"][0][1] = 99.into())));
+    assert!(out_of_range(corrupt(&|value| value["actions"][0][2] = serde_json::json!([{ "Shift": 99 }]))));
+    assert!(out_of_range(corrupt(&|value| value["actions"][0][2] = serde_json::json!([{ "Reduce": 99 }]))));
+    assert!(out_of_range(corrupt(&|value| value["states"] = serde_json::json!([]))));
+    assert!(corrupt(&|_value| {}).is_ok());
+}
+
+/// A table which is in range but reduces more symbols than the stack holds fails the parse rather than panicking.
+#[cfg(feature = "serde")]
+#[test]
+fn test_cache_bad_reduction() {
+    let table = lr0::ParseTable::new(parens_grammar());
+    let x = table.grammar().symbol("x").unwrap().index();
+    let mut value: serde_json::Value = serde_json::from_str(&table.to_json()).unwrap();
+    // Reduce `L -> ( L )` on `x` in the start state, where the stack is empty.
+    for action in value["actions"].as_array_mut().unwrap() {
+        if action[0] == 0 && action[1] == x {
+            action[2] = serde_json::json!([{ "Reduce": 1 }]);
+        }
+    }
+    let table = lr0::ParseTable::from_json(parens_grammar(), &value.to_string()).unwrap();
+    let x = table.grammar().symbol("x").unwrap();
+    let mut machine = lr0::Machine::new(&table);
+    assert_eq!(
+        machine.run(&mut std::iter::once(Token::from(x))),
+        Err(lr0::ParseError::StackUnderflow { state: 0, rule: 1, step: 0 }),
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_grammar_serde() {
+    let grammar = parens_grammar();
+    let json = serde_json::to_string(&grammar).unwrap();
+    let loaded: Grammar = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.fingerprint(), grammar.fingerprint());
+    assert_eq!(format!("{loaded:?}"), format!("{grammar:?}"));
+}
+
+/// A writer whose output the test can read back after the subscriber is gone.
+#[derive(Clone, Default)]
+struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Building a table and running the machine leave a trace which can be read back, step by step.
+#[test]
+fn test_trace_replay() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let buffer = SharedBuffer::default();
+    let subscriber = tracing_subscriber::registry().with(trace::TraceFile::new(buffer.clone()));
+    let states = tracing::subscriber::with_default(subscriber, || {
+        let table = lr0::ParseTable::new(parens_grammar());
+        run_words(&table, "( x )").unwrap();
+        assert!(run_words(&table, "( x").is_err());
+        table.states.len()
+    });
+
+    let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let events = trace::read(text.as_bytes()).unwrap();
+    let messages = |spans: &[&str], message: &str| {
+        events.iter().filter(|event| event.spans == spans && event.message() == Some(message)).count()
+    };
+    assert_eq!(messages(&["parse_table", "goto"], "state"), states);
+    assert!(messages(&["parse_table", "goto"], "closure") > 0);
+    assert!(messages(&["parse_table", "action_fill"], "action") > 0);
+    assert_eq!(messages(&["parse"], "token"), 5);
+    assert_eq!(messages(&["parse"], "no action"), 1);
+
+    let token = events.iter().find(|event| event.message() == Some("token")).unwrap();
+    assert_eq!((token.level.as_str(), token.target.as_str()), ("DEBUG", "parsing::lr0"));
+    assert_eq!(token.field("terminal"), Some("("));
+
+    // The steps of the first parse, which halts, then those of the second, which fails at the end of input.
+    let steps = trace::steps(&events);
+    let halt = steps.iter().position(|step| step.action == "Halt").unwrap();
+    assert!(steps[..=halt].iter().enumerate().all(|(i, step)| step.step == i));
+    assert_eq!(steps[0], trace::Step {
+        step: 0,
+        state: 0,
+        lookahead: "(".to_string(),
+        action: format!("{:?}", lr0::Action::Shift(steps[1].state)),
+        depth: 0,
+    });
+    // The machine halts in place of reducing `L` to `START`, at the end of input.
+    assert_eq!((steps[halt].lookahead.as_str(), steps[halt].depth), ("$", 1));
+    assert_eq!(trace::parses(&events).iter().map(Vec::len).collect::<Vec<_>>(), vec![halt + 1, steps.len() - halt - 1]);
+    let failure = events.iter().find(|event| event.message() == Some("no action")).unwrap();
+    assert_eq!(failure.field("lookahead"), Some("$"));
+}
+
+#[test]
+fn test_trace_event_parse() {
+    let event = trace::TraceEvent::parse(r#"INFO parsing::lr0 - message="a \"b\"\n\u{7f}" n=3 ok=true"#).unwrap();
+    assert_eq!(event.spans, Vec::<String>::new());
+    assert_eq!(event.fields, vec![
+        ("message".to_string(), "a \"b\"\n\u{7f}".to_string()),
+        ("n".to_string(), "3".to_string()),
+        ("ok".to_string(), "true".to_string()),
+    ]);
+    assert_eq!(trace::TraceEvent::parse("INFO"), None);
+}
@@ -0,0 +1,47 @@
+use crate::*;
+
+pub type Span = std::ops::Range<usize>;
+
+/// A terminal read from the input, together with the text it was lexed from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token<'a, 's> {
+    pub terminal: Symbol<'a>,
+    pub text: &'s str,
+    pub span: Span,
+}
+
+impl<'a, 's> Token<'a, 's> {
+    pub fn new(terminal: Symbol<'a>, text: &'s str, span: Span) -> Token<'a, 's> {
+        Token {
+            terminal,
+            text,
+            span,
+        }
+    }
+}
+
+/// A bare terminal with no text, for driving the machine by hand.
+impl<'a> From<Symbol<'a>> for Token<'a, 'static> {
+    fn from(terminal: Symbol<'a>) -> Self {
+        Token::new(terminal, "", 0..0)
+    }
+}
+
+/// Anything which can feed tokens to an `lr0::Machine`.
+///
+/// `'a` is the lifetime of the grammar the terminals belong to.
+/// `'s` is the lifetime of the source text the tokens are sliced from.
+pub trait TokenSource<'a, 's> {
+    /// Why the source could not produce a token, such as text no terminal matches.
+    type Error;
+
+    fn next_token(&mut self) -> Option<Result<Token<'a, 's>, Self::Error>>;
+}
+
+impl<'a, 's, I: Iterator<Item = Token<'a, 's>>> TokenSource<'a, 's> for I {
+    type Error = std::convert::Infallible;
+
+    fn next_token(&mut self) -> Option<Result<Token<'a, 's>, Self::Error>> {
+        self.next().map(Ok)
+    }
+}
@@ -0,0 +1,235 @@
+//! A `tracing` layer which writes events to a file, one per line, and a reader to replay them.
+//!
+//! Table construction is traced under a `parse_table` span, with a span for each phase:
+//! `goto` (finding the states, with `state`, `goto`, and `closure` events), `action_fill`, and `lookaheads`.
+//! The machine records a `token` event for each token it is fed, and a `step` event for each action it takes,
+//! with the step number, state, lookahead, action, and stack depth.
+//!
+//! Each line of a trace file is the event's level, target, and the names of the spans it is in, joined with `:`,
+//! followed by its fields as `name=value`. Numbers and booleans are written bare, and anything else as a quoted string:
+//!
+//! ```text
+//! DEBUG parsing::lr0 parse message="step" step=3 state=5 lookahead="id" action="Shift(7)" depth=2
+//! ```
+
+use std::fs::File;
+use std::io::{BufRead, LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::lr0::StateIndex;
+
+pub struct TraceFile<W> {
+    writer: Mutex<W>,
+}
+
+impl TraceFile<LineWriter<File>> {
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(TraceFile::new(LineWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> TraceFile<W> {
+    pub fn new(writer: W) -> Self {
+        TraceFile {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<S, W> Layer<S> for TraceFile<W>
+where
+    S: Subscriber + for<'l> LookupSpan<'l>,
+    W: Write + 'static,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let spans: Vec<&str> = match ctx.event_scope(event) {
+            Some(scope) => scope.from_root().map(|span| span.name()).collect(),
+            None => vec![],
+        };
+        let spans = if spans.is_empty() { "-".to_string() } else { spans.join(":") };
+
+        let mut line = format!("{} {} {spans}", metadata.level(), metadata.target());
+        event.record(&mut FieldWriter(&mut line));
+        line.push('\n');
+        // A trace is only for debugging, so failing to write it shouldn't stop the program.
+        let _ = self.writer.lock().unwrap().write_all(line.as_bytes());
+    }
+}
+
+struct FieldWriter<'l>(&'l mut String);
+
+impl<'l> Visit for FieldWriter<'l> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let value = format!("{value:?}");
+        self.record_str(field, &value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push_str(&format!(" {}={value:?}", field.name()));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.push_str(&format!(" {}={value}", field.name()));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.push_str(&format!(" {}={value}", field.name()));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.push_str(&format!(" {}={value}", field.name()));
+    }
+}
+
+/// Write the events `filter` allows to a trace file at `path`, for the rest of the program.
+/// `filter` is in the syntax of `RUST_LOG`, eg `parsing=debug`.
+pub fn install(path: impl AsRef<Path>, filter: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::try_new(filter)?)
+        .with(TraceFile::create(path)?);
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(())
+}
+
+/// One line of a trace file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub level: String,
+    pub target: String,
+    pub spans: Vec<String>,
+    /// In the order they were written, with strings unquoted.
+    pub fields: Vec<(String, String)>,
+}
+
+impl TraceEvent {
+    pub fn parse(line: &str) -> Option<TraceEvent> {
+        let mut parts = line.splitn(4, ' ');
+        let level = parts.next()?.to_string();
+        let target = parts.next()?.to_string();
+        let spans = match parts.next()? {
+            "-" => vec![],
+            spans => spans.split(':').map(str::to_string).collect(),
+        };
+
+        let mut fields = vec![];
+        let mut rest = parts.next().unwrap_or_default();
+        while !rest.is_empty() {
+            let (name, after) = rest.split_once('=')?;
+            let (value, after) = if after.starts_with('"') {
+                unquote(after)?
+            } else {
+                let (value, after) = after.split_once(' ').unwrap_or((after, ""));
+                (value.to_string(), after)
+            };
+            fields.push((name.to_string(), value));
+            rest = after.trim_start_matches(' ');
+        }
+        Some(TraceEvent {
+            level,
+            target,
+            spans,
+            fields,
+        })
+    }
+
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find(|(field, _value)| field == name).map(|(_field, value)| value.as_str())
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.field("message")
+    }
+}
+
+/// Split a string written with `{:?}` off the front of `s`, returning its contents and what follows it.
+fn unquote(s: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((_, c)) = chars.next() {
+        match c {
+            '"' => {
+                let end = chars.next().map_or(s.len(), |(i, _)| i);
+                return Some((value, &s[end..]));
+            }
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                '0' => value.push('\0'),
+                'u' => {
+                    let mut hex = String::new();
+                    for (_, c) in chars.by_ref().skip(1) {
+                        if c == '}' {
+                            break;
+                        }
+                        hex.push(c);
+                    }
+                    value.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Read every event in a trace file.
+pub fn read(reader: impl BufRead) -> std::io::Result<Vec<TraceEvent>> {
+    let mut events = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let event = TraceEvent::parse(&line).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Not a trace event: {line}"))
+        })?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// One action taken by `lr0::Machine`, read back from a trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub step: usize,
+    pub state: StateIndex,
+    /// The symbol the action was taken on, or `$` for the end of input.
+    pub lookahead: String,
+    pub action: String,
+    pub depth: usize,
+}
+
+/// The steps of each machine in the trace, split where a step numbered 0 starts a new one.
+pub fn parses(events: &[TraceEvent]) -> Vec<Vec<Step>> {
+    let mut parses: Vec<Vec<Step>> = vec![];
+    for step in steps(events) {
+        match parses.last_mut() {
+            Some(parse) if step.step != 0 => parse.push(step),
+            _ => parses.push(vec![step]),
+        }
+    }
+    parses
+}
+
+/// The machines' steps, in the order they were taken.
+pub fn steps(events: &[TraceEvent]) -> Vec<Step> {
+    events
+        .iter()
+        .filter(|event| event.message() == Some("step"))
+        .filter_map(|event| {
+            Some(Step {
+                step: event.field("step")?.parse().ok()?,
+                state: event.field("state")?.parse().ok()?,
+                lookahead: event.field("lookahead")?.to_string(),
+                action: event.field("action")?.to_string(),
+                depth: event.field("depth")?.parse().ok()?,
+            })
+        })
+        .collect()
+}
@@ -67,7 +67,7 @@ pub enum LexToken {
     KwReset,
     #[token(r"Probe")]
     KwProbe2,
-    #[token(r"RwProbe")]
+    #[token(r"RWProbe")]
     KwRwProbe2,
     #[token(r"wire")]
     KwWire,
@@ -251,10 +251,20 @@ impl std::fmt::Debug for Token {
     }
 }
 
+/// The start of a line, after the newline and indentation before it, where lexing can resume.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineStart {
+    pub offset: usize,
+    /// The indentation levels open at the start of the line.
+    pub indents: Vec<usize>,
+}
+
 pub struct FirrtlLexer<'a> {
     lex: Lexer<'a, LexToken>,
     token_queue: VecDeque<Token>,
     indents: Vec<usize>,
+    /// Whether nothing has been lexed yet, or the last token was a newline, until the input runs out.
+    at_line_start: bool,
 }
 
 impl<'a> FirrtlLexer<'a> {
@@ -264,9 +274,33 @@ impl<'a> FirrtlLexer<'a> {
             lex,
             token_queue: VecDeque::with_capacity(4),
             indents: vec![],
+            at_line_start: true,
         }
     }
 
+    /// A lexer for `s` which starts at `line`, producing the tokens lexing all of `s` would from there.
+    pub fn resume(s: &'a str, line: &LineStart) -> FirrtlLexer<'a> {
+        let mut lex = LexToken::lexer(s);
+        lex.bump(line.offset);
+        FirrtlLexer {
+            lex,
+            token_queue: VecDeque::with_capacity(4),
+            indents: line.indents.clone(),
+            at_line_start: true,
+        }
+    }
+
+    /// Where the lexer is, if it is at the start of a line and has emitted the indents and dedents before it.
+    pub fn line_start(&self) -> Option<LineStart> {
+        if !self.at_line_start || !self.token_queue.is_empty() {
+            return None;
+        }
+        Some(LineStart {
+            offset: self.lex.span().end,
+            indents: self.indents.clone(),
+        })
+    }
+
     pub fn indent_level(&self) -> usize {
         *self.indents.last().unwrap_or(&0)
     }
@@ -291,19 +325,20 @@ impl<'a> FirrtlLexer<'a> {
             match maybe_token {
                 Some(Ok(LexToken::Newline(indent))) => {
                     self.redent(indent);
+                    self.at_line_start = true;
                     Some(Ok(Token::Newline))
                 }
-                Some(Ok(tok)) => Some(Ok(Token::Lex(tok))),
-                Some(Err(e)) => {
-                    dbg!(e);
-                    dbg!(self.slice());
-                    dbg!(self.span());
-                    let span = self.span();
-                    eprintln!("{:?}", &self.lex.source()[span.start..span.end + 10]);
-                    eprintln!();
-                    panic!()
+                Some(Ok(tok)) => {
+                    self.at_line_start = false;
+                    Some(Ok(Token::Lex(tok)))
+                }
+                // The span and slice of the lexer are the text which is not a token.
+                Some(Err(())) => {
+                    self.at_line_start = false;
+                    Some(Err(()))
                 }
                 None => {
+                    self.at_line_start = false;
                     self.redent(0);
                     self.token_queue.pop_front().map(|token| Ok(token))
                 },